@@ -0,0 +1,582 @@
+use crate::{
+    Location, Token, TokenType,
+    lexer::{BinOp, Builtin, to_superscript},
+    number::Number,
+    parse::Function,
+};
+
+/// A real-valued expression as a tree, which is what derivatives are taken on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Num(f32),
+    Var(String),
+    BinOp(BinOp, Box<Node>, Box<Node>),
+    Call(Builtin, Box<Node>),
+}
+
+impl Node {
+    /// Rebuilds the tree from RPN, inlining calls to the given user functions.
+    pub fn from_rpn(rpn: &[Token], functions: &[Function]) -> Result<Node, String> {
+        let mut stack: Vec<Node> = vec![];
+        for token in rpn {
+            match &token.token_type {
+                TokenType::Num(Number::Float(x)) => stack.push(Node::Num(*x)),
+                TokenType::Num(n) => {
+                    return Err(format!(
+                        "Only real numbers can be differentiated, not `{n}`."
+                    ));
+                }
+                TokenType::Id(id) => stack.push(Node::Var(id.clone())),
+                TokenType::BinOp(BinOp::MatMul) | TokenType::Array(_) | TokenType::LinAlg(_) => {
+                    return Err("Vectors and matrices cannot be differentiated.".to_owned());
+                }
+                TokenType::Statistic(statistic, _) => {
+                    return Err(format!("`{statistic}` cannot be differentiated."));
+                }
+                TokenType::BinOp(op) => {
+                    let y = stack.pop().ok_or("Ill-formed expression.")?;
+                    let x = stack.pop().ok_or("Ill-formed expression.")?;
+                    stack.push(Node::BinOp(op.clone(), Box::new(x), Box::new(y)));
+                }
+                TokenType::Negate => {
+                    let x = stack.pop().ok_or("Ill-formed expression.")?;
+                    stack.push(match x {
+                        Node::Num(x) => Node::Num(-x),
+                        x => mul(Node::Num(-1.), x),
+                    });
+                }
+                TokenType::Builtin(builtin) => {
+                    let x = stack.pop().ok_or("Ill-formed expression.")?;
+                    stack.push(Node::Call(*builtin, Box::new(x)));
+                }
+                TokenType::Call(name, arity) => {
+                    let function = functions
+                        .iter()
+                        .find(|f| &f.name == name)
+                        .ok_or_else(|| format!("Unknown function `{name}`."))?;
+                    let args = stack.split_off(stack.len().saturating_sub(*arity));
+                    let mut body = Node::from_rpn(&function.body, functions)?;
+                    for (param, arg) in function.params.iter().zip(&args) {
+                        body = body.substitute(param, arg);
+                    }
+                    stack.push(body);
+                }
+                TokenType::Loop(reduction) => {
+                    return Err(format!(
+                        "`{}` cannot be differentiated.",
+                        reduction.reduction
+                    ));
+                }
+                TokenType::Convert(_) => {
+                    return Err("Unit conversions cannot be differentiated.".to_owned());
+                }
+                TokenType::Grad(..) => {
+                    return Err("The result of `grad` cannot be differentiated.".to_owned());
+                }
+                TokenType::Integral(_) => {
+                    return Err("Integrals cannot be differentiated.".to_owned());
+                }
+                TokenType::Equation(_) => {
+                    return Err("The roots of `solve` cannot be differentiated.".to_owned());
+                }
+                TokenType::Simplified(node) => stack.push(*node.clone()),
+                _ => (),
+            }
+        }
+
+        match (stack.pop(), stack.is_empty()) {
+            (Some(node), true) => Ok(node),
+            _ => Err("Ill-formed expression.".to_owned()),
+        }
+    }
+
+    /// Flattens the tree back into RPN, with every token placed at `location`.
+    pub fn to_rpn(&self, location: Option<Location>) -> Vec<Token> {
+        let token = |token_type| Token {
+            token_type,
+            location,
+        };
+        match self {
+            Node::Num(x) => vec![token(TokenType::Num(Number::Float(*x)))],
+            Node::Var(id) => vec![token(TokenType::Id(id.clone()))],
+            Node::BinOp(op, x, y) => {
+                let mut rpn = x.to_rpn(location);
+                rpn.extend(y.to_rpn(location));
+                rpn.push(token(TokenType::BinOp(op.clone())));
+                rpn
+            }
+            Node::Call(builtin, x) => {
+                let mut rpn = x.to_rpn(location);
+                rpn.push(token(TokenType::Builtin(*builtin)));
+                rpn
+            }
+        }
+    }
+
+    pub fn depends_on(&self, x: &str) -> bool {
+        match self {
+            Node::Num(_) => false,
+            Node::Var(id) => id == x,
+            Node::BinOp(_, u, v) => u.depends_on(x) || v.depends_on(x),
+            Node::Call(_, u) => u.depends_on(x),
+        }
+    }
+
+    /// Replaces every occurrence of the variable `x` with `value`.
+    pub fn substitute(&self, x: &str, value: &Node) -> Node {
+        match self {
+            Node::Var(id) if id == x => value.clone(),
+            Node::Num(_) | Node::Var(_) => self.clone(),
+            Node::BinOp(op, u, v) => Node::BinOp(
+                op.clone(),
+                Box::new(u.substitute(x, value)),
+                Box::new(v.substitute(x, value)),
+            ),
+            Node::Call(builtin, u) => Node::Call(*builtin, Box::new(u.substitute(x, value))),
+        }
+    }
+
+    /// The derivative with respect to `x`, before simplification.
+    pub fn derivative(&self, x: &str) -> Node {
+        match self {
+            Node::Num(_) => Node::Num(0.),
+            Node::Var(id) => Node::Num(if id == x { 1. } else { 0. }),
+            Node::BinOp(op, u, v) => {
+                let (du, dv) = (u.derivative(x), v.derivative(x));
+                let (u, v) = (*u.clone(), *v.clone());
+                match op {
+                    BinOp::Plus => add(du, dv),
+                    BinOp::Minus => sub(du, dv),
+                    // Product rule.
+                    BinOp::Times => add(mul(du, v), mul(u, dv)),
+                    // Quotient rule.
+                    BinOp::Divide => {
+                        div(sub(mul(du, v.clone()), mul(u, dv)), pow(v, Node::Num(2.)))
+                    }
+                    // Power rule, when the exponent does not depend on `x`.
+                    BinOp::Power if !v.depends_on(x) => {
+                        mul(mul(v.clone(), pow(u, sub(v, Node::Num(1.)))), du)
+                    }
+                    // `a^v` for a base that does not depend on `x`.
+                    BinOp::Power if !u.depends_on(x) => {
+                        mul(mul(self.clone(), Node::Call(Builtin::Ln, Box::new(u))), dv)
+                    }
+                    // `u^v = exp(v ln u)`, so its derivative is `u^v (v' ln u + v u' / u)`.
+                    BinOp::Power => mul(
+                        self.clone(),
+                        add(
+                            mul(dv, Node::Call(Builtin::Ln, Box::new(u.clone()))),
+                            div(mul(v, du), u),
+                        ),
+                    ),
+                    BinOp::Equal | BinOp::MatMul => unreachable!(),
+                }
+            }
+            // Chain rule, treating every argument as real.
+            Node::Call(builtin, u) => {
+                let du = u.derivative(x);
+                let u = *u.clone();
+                match builtin {
+                    Builtin::Re | Builtin::Conj => du,
+                    Builtin::Im | Builtin::Arg => Node::Num(0.),
+                    Builtin::Abs => mul(div(u.clone(), Node::Call(Builtin::Abs, Box::new(u))), du),
+                    Builtin::Sqrt => div(du, mul(Node::Num(2.), self.clone())),
+                    Builtin::Exp => mul(self.clone(), du),
+                    Builtin::Ln => div(du, u),
+                }
+            }
+        }
+    }
+
+    /// Rewrites the tree bottom-up into a canonical form: constants are folded, sums collect like
+    /// terms (`2*x + 3*x` is `5*x`, `x - x` is `0`), products collect powers of the same base
+    /// (`x * x` is `x^2`), and both are put in a fixed order, so equal expressions print the same.
+    pub fn simplify(&self) -> Node {
+        match self {
+            Node::Num(_) | Node::Var(_) => self.clone(),
+            Node::Call(builtin, u) => match u.simplify() {
+                Node::Num(x) if let Some(Number::Float(y)) = Number::Float(x).call(*builtin) => {
+                    Node::Num(y)
+                }
+                u => Node::Call(*builtin, Box::new(u)),
+            },
+            Node::BinOp(op, u, v) => {
+                let (u, v) = (u.simplify(), v.simplify());
+                match (op, &u, &v) {
+                    (_, Node::Num(a), Node::Num(b)) if let Some(n) = fold(op, *a, *b) => {
+                        Node::Num(n)
+                    }
+                    (BinOp::Plus | BinOp::Minus, _, _) => {
+                        collect_sum(Node::BinOp(op.clone(), Box::new(u), Box::new(v)))
+                    }
+                    (BinOp::Times, _, _) => collect_product(mul(u, v)),
+                    // Division by zero is left for evaluation to report.
+                    (BinOp::Divide, _, Node::Num(z)) if *z == 0. => div(u, v),
+                    (BinOp::Divide, _, _) => collect_product(div(u, v)),
+                    (BinOp::Power, _, Node::Num(z)) if *z == 0. => Node::Num(1.),
+                    (BinOp::Power, _, Node::Num(one)) if *one == 1. => u,
+                    (BinOp::Power, Node::Num(one), _) if *one == 1. => Node::Num(1.),
+                    _ => Node::BinOp(op.clone(), Box::new(u), Box::new(v)),
+                }
+            }
+        }
+    }
+
+    /// Degree in the variables, counting only numeric exponents, which orders the terms of a sum.
+    fn degree(&self) -> f32 {
+        match self {
+            Node::Var(_) => 1.,
+            Node::BinOp(BinOp::Times, u, v) => u.degree() + v.degree(),
+            Node::BinOp(BinOp::Power, u, v) if let Node::Num(n) = **v => u.degree() * n,
+            Node::BinOp(BinOp::Divide, u, v) => u.degree() - v.degree(),
+            _ => 0.,
+        }
+    }
+
+    /// Variables come first in a product, then compound expressions, then calls.
+    fn rank(&self) -> u8 {
+        match self {
+            Node::Num(_) => 0,
+            Node::Var(_) => 1,
+            Node::BinOp(..) => 2,
+            Node::Call(..) => 3,
+        }
+    }
+
+    fn fmt_child(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        parent: &BinOp,
+        right: bool,
+    ) -> std::fmt::Result {
+        let parens = match self {
+            // A leading coefficient reads fine without them, as in `-3 * x`.
+            Node::Num(x) => *x < 0. && (right || !matches!(parent, BinOp::Times | BinOp::Divide)),
+            Node::BinOp(op, _, _) => {
+                op.precedence() < parent.precedence()
+                    || (op.precedence() == parent.precedence()
+                        && (right != parent.is_right_associative())
+                        && !(matches!(parent, BinOp::Plus | BinOp::Times) && op == parent))
+            }
+            _ => false,
+        };
+        match (parens, f.alternate()) {
+            (true, true) => write!(f, "({self:#})"),
+            (true, false) => write!(f, "({self})"),
+            (false, true) => write!(f, "{self:#}"),
+            (false, false) => write!(f, "{self}"),
+        }
+    }
+
+    /// A whole power in superscript, as in `x²`.
+    fn superscript(&self) -> Option<String> {
+        match self {
+            Node::Num(x) if x.fract() == 0. && x.abs() < 1e6 => (*x as i64)
+                .to_string()
+                .chars()
+                .map(to_superscript)
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+/// `{:#}` writes the Unicode operators, as in `2 × √x − y²`.
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unicode = f.alternate();
+        match self {
+            Node::Num(x) if unicode && *x < 0. => write!(f, "−{}", -x),
+            Node::Num(x) => write!(f, "{x}"),
+            Node::Var(id) => f.write_str(id),
+            Node::BinOp(BinOp::Power, u, v) if unicode && v.superscript().is_some() => {
+                u.fmt_child(f, &BinOp::Power, false)?;
+                f.write_str(&v.superscript().unwrap_or_default())
+            }
+            Node::BinOp(op, u, v) => {
+                u.fmt_child(f, op, false)?;
+                match (op, unicode) {
+                    (BinOp::Power, _) => write!(f, "{op}")?,
+                    (_, true) => write!(f, " {op:#} ")?,
+                    (_, false) => write!(f, " {op} ")?,
+                }
+                v.fmt_child(f, op, true)
+            }
+            Node::Call(Builtin::Sqrt, u) if unicode => match **u {
+                Node::Var(_) => write!(f, "√{u}"),
+                Node::Num(x) if x >= 0. => write!(f, "√{u}"),
+                _ => write!(f, "√({u:#})"),
+            },
+            Node::Call(builtin, u) if unicode => write!(f, "{builtin}({u:#})"),
+            Node::Call(builtin, u) => write!(f, "{builtin}({u})"),
+        }
+    }
+}
+
+/// Flattens a sum whose operands are already simplified into signed terms, adds up the
+/// coefficients of equal terms and rebuilds it with the highest degree first and the constant last.
+fn collect_sum(node: Node) -> Node {
+    let mut terms: Vec<Term> = vec![];
+    add_terms(node, 1., &mut terms);
+    let mut collected: Vec<Term> = vec![];
+    for term in terms {
+        // A sum of coefficients that would overflow stays two terms.
+        let like =
+            |t: &&mut Term| t.key == term.key && (t.coefficient + term.coefficient).is_finite();
+        match collected.iter_mut().find(like) {
+            Some(t) => t.coefficient += term.coefficient,
+            None => collected.push(term),
+        }
+    }
+    collected.retain(|t| t.coefficient != 0.);
+    collected.sort_by(|a, b| {
+        let constant = |t: &Term| t.factors.is_empty();
+        constant(a)
+            .cmp(&constant(b))
+            .then(b.key.degree().total_cmp(&a.key.degree()))
+            .then_with(|| a.key.to_string().cmp(&b.key.to_string()))
+    });
+    // Starting with a positive term saves a leading `-1 *`.
+    if let Some(first) = collected.iter().position(|t| t.coefficient > 0.) {
+        let term = collected.remove(first);
+        collected.insert(0, term);
+    }
+
+    let mut collected = collected.into_iter();
+    let Some(first) = collected.next() else {
+        return Node::Num(0.);
+    };
+    collected.fold(
+        build_product(first.coefficient, first.factors),
+        |sum, term| {
+            if term.coefficient < 0. {
+                sub(sum, build_product(-term.coefficient, term.factors))
+            } else {
+                add(sum, build_product(term.coefficient, term.factors))
+            }
+        },
+    )
+}
+
+/// One term of a sum: a coefficient times powers of bases, with `key` being the term without its coefficient.
+struct Term {
+    coefficient: f32,
+    factors: Vec<(Node, f32)>,
+    key: Node,
+}
+
+fn add_terms(node: Node, sign: f32, terms: &mut Vec<Term>) {
+    match node {
+        Node::BinOp(BinOp::Plus, u, v) => {
+            add_terms(*u, sign, terms);
+            add_terms(*v, sign, terms);
+        }
+        Node::BinOp(BinOp::Minus, u, v) => {
+            add_terms(*u, sign, terms);
+            add_terms(*v, -sign, terms);
+        }
+        node => {
+            let mut coefficient = 1.;
+            let mut factors = vec![];
+            add_factors(node, 1., &mut coefficient, &mut factors);
+            let key = build_product(1., factors.clone());
+            terms.push(Term {
+                coefficient: sign * coefficient,
+                factors,
+                key,
+            });
+        }
+    }
+}
+
+/// Flattens a product or quotient whose operands are already simplified, multiplies its
+/// constants into one coefficient and adds up the exponents of equal bases.
+fn collect_product(node: Node) -> Node {
+    let mut coefficient = 1.;
+    let mut factors = vec![];
+    add_factors(node, 1., &mut coefficient, &mut factors);
+    // A zero coefficient makes the product zero, unless a factor such as `1/0` was kept for evaluation to report.
+    if coefficient == 0.
+        && factors
+            .iter()
+            .all(|(base, _)| !matches!(base, Node::Num(_)))
+    {
+        return Node::Num(0.);
+    }
+    build_product(coefficient, factors)
+}
+
+/// Adds the factors of `node` raised to `power`, which is `-1` in a denominator.
+fn add_factors(node: Node, power: f32, coefficient: &mut f32, factors: &mut Vec<(Node, f32)>) {
+    let (base, exponent) = match node {
+        Node::BinOp(BinOp::Times, u, v) => {
+            add_factors(*u, power, coefficient, factors);
+            add_factors(*v, power, coefficient, factors);
+            return;
+        }
+        Node::BinOp(BinOp::Divide, u, v) => {
+            add_factors(*u, power, coefficient, factors);
+            add_factors(*v, -power, coefficient, factors);
+            return;
+        }
+        // A constant that would make the coefficient overflow, or divide it by zero, stays a factor of its own.
+        Node::Num(c) => {
+            match *coefficient * c.powf(power) {
+                scaled if scaled.is_finite() => *coefficient = scaled,
+                _ => factors.push((Node::Num(c), power)),
+            }
+            return;
+        }
+        Node::BinOp(BinOp::Power, u, v) if let Node::Num(n) = *v => (*u, n * power),
+        node => (node, power),
+    };
+    match factors.iter_mut().find(|(b, _)| *b == base) {
+        Some((_, total)) => *total += exponent,
+        None => factors.push((base, exponent)),
+    }
+}
+
+/// Rebuilds a product as the coefficient and the positive powers, over the negative ones.
+fn build_product(coefficient: f32, mut factors: Vec<(Node, f32)>) -> Node {
+    factors.retain(|(_, exponent)| *exponent != 0.);
+    factors.sort_by(|(a, _), (b, _)| {
+        a.rank()
+            .cmp(&b.rank())
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+    let power = |base: Node, exponent: f32| match exponent {
+        1. => base,
+        n => pow(base, Node::Num(n)),
+    };
+    let chain = |start: Option<Node>, factors: Vec<Node>| {
+        factors.into_iter().fold(start, |product, factor| {
+            Some(match product {
+                Some(product) => mul(product, factor),
+                None => factor,
+            })
+        })
+    };
+
+    let (numerator, denominator): (Vec<_>, Vec<_>) = factors
+        .into_iter()
+        .partition(|(_, exponent)| *exponent > 0.);
+    let numerator = chain(
+        (coefficient != 1.).then_some(Node::Num(coefficient)),
+        numerator.into_iter().map(|(b, e)| power(b, e)).collect(),
+    )
+    .unwrap_or(Node::Num(coefficient));
+    match chain(
+        None,
+        denominator.into_iter().map(|(b, e)| power(b, -e)).collect(),
+    ) {
+        Some(denominator) => div(numerator, denominator),
+        None => numerator,
+    }
+}
+
+/// Evaluates an operation on two constants, unless the result would leave the reals. Powers are
+/// computed as compiled code computes them, so folding never changes a value.
+pub(crate) fn fold(op: &BinOp, a: f32, b: f32) -> Option<f32> {
+    let n = match op {
+        BinOp::Plus => a + b,
+        BinOp::Minus => a - b,
+        BinOp::Times => a * b,
+        BinOp::Divide => a / b,
+        // `CodeGen::build_rpn` multiplies these out.
+        BinOp::Power if [2., 3., 4.].contains(&b) => {
+            (1..b as usize).fold(a, |product, _| product * a)
+        }
+        BinOp::Power => a.powf(b),
+        BinOp::Equal | BinOp::MatMul => unreachable!(),
+    };
+    n.is_finite().then_some(n)
+}
+
+fn add(u: Node, v: Node) -> Node {
+    Node::BinOp(BinOp::Plus, Box::new(u), Box::new(v))
+}
+
+fn sub(u: Node, v: Node) -> Node {
+    Node::BinOp(BinOp::Minus, Box::new(u), Box::new(v))
+}
+
+fn mul(u: Node, v: Node) -> Node {
+    Node::BinOp(BinOp::Times, Box::new(u), Box::new(v))
+}
+
+fn div(u: Node, v: Node) -> Node {
+    Node::BinOp(BinOp::Divide, Box::new(u), Box::new(v))
+}
+
+fn pow(u: Node, v: Node) -> Node {
+    Node::BinOp(BinOp::Power, Box::new(u), Box::new(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(id: &str) -> Node {
+        Node::Var(id.to_owned())
+    }
+
+    fn num(x: f32) -> Node {
+        Node::Num(x)
+    }
+
+    /// The simplified form of `node`, printed both ways.
+    fn simplified(node: Node) -> (String, String) {
+        let node = node.simplify();
+        (node.to_string(), format!("{node:#}"))
+    }
+
+    #[test]
+    fn identities_and_like_terms() {
+        let x = || var("x");
+        assert_eq!(mul(x(), num(1.)).simplify(), x());
+        assert_eq!(add(num(0.), x()).simplify(), x());
+        assert_eq!(pow(x(), num(1.)).simplify(), x());
+        assert_eq!(sub(x(), x()).simplify(), num(0.));
+        assert_eq!(div(mul(x(), x()), x()).simplify(), x());
+        assert_eq!(
+            add(mul(num(2.), x()), mul(num(3.), x())).simplify(),
+            mul(num(5.), x())
+        );
+        assert_eq!(mul(x(), x()).simplify(), pow(x(), num(2.)));
+    }
+
+    #[test]
+    fn canonical_order() {
+        let (x, y) = (|| var("x"), || var("y"));
+        assert_eq!(simplified(mul(y(), x())).0, "x * y");
+        assert_eq!(
+            simplified(add(add(num(1.), x()), pow(x(), num(2.)))),
+            ("x^2 + x + 1".to_owned(), "x² + x + 1".to_owned())
+        );
+        // A positive term goes first, so the sum does not start with `-1 *`.
+        assert_eq!(simplified(sub(num(0.), add(x(), y()))).0, "-1 * x - y");
+        assert_eq!(simplified(sub(y(), x())).0, "y - x");
+        assert_eq!(
+            simplified(sub(
+                mul(num(2.), Node::Call(Builtin::Sqrt, Box::new(x()))),
+                pow(y(), num(2.))
+            )),
+            ("2 * sqrt(x) - y^2".to_owned(), "2 × √x − y²".to_owned())
+        );
+    }
+
+    #[test]
+    fn constants_that_are_not_finite_stay_apart() {
+        let (x, y) = (|| var("x"), || var("y"));
+        assert_eq!(
+            mul(mul(num(1e30), num(1e30)), x()).simplify(),
+            mul(mul(num(1e30), num(1e30)), x())
+        );
+        assert_eq!(simplified(mul(x(), div(y(), num(0.)))).0, "x * y / 0");
+        assert_eq!(simplified(mul(num(0.), div(num(1.), num(0.)))).0, "0 / 0");
+        assert_eq!(
+            add(mul(num(3e38), x()), mul(num(3e38), x())).simplify(),
+            add(mul(num(3e38), x()), mul(num(3e38), x()))
+        );
+    }
+}
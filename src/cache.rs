@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Overrides where `Cache::user` keeps compiled programs.
+pub const CACHE_DIR_VARIABLE: &str = "MATHLING_CACHE_DIR";
+
+/// Extension of the files that hold object code.
+const EXTENSION: &str = "o";
+
+/// The object code of compiled programs, one file per program, so that running one again skips LLVM.
+/// It is only ever an optimization: any entry that cannot be read or written is compiled again.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `$MATHLING_CACHE_DIR`, or `mathling` in the user's cache directory when there is one.
+    pub fn user() -> Option<Self> {
+        let var = |name| std::env::var_os(name).filter(|dir| !dir.is_empty());
+        if let Some(dir) = var(CACHE_DIR_VARIABLE) {
+            return Some(Cache::new(dir));
+        }
+        let cache_home = var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(Cache::new(cache_home.join("mathling")))
+    }
+
+    /// A 128-bit FNV-1a hash of `parts`, which is long enough that distinct programs never share one.
+    pub fn key(parts: &[&str]) -> String {
+        const PRIME: u128 = 0x0000000001000000000000000000013b;
+        let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+        for part in parts {
+            // The length keeps `["ab", "c"]` and `["a", "bc"]` apart.
+            for byte in (part.len() as u64)
+                .to_le_bytes()
+                .iter()
+                .chain(part.as_bytes())
+            {
+                hash = (hash ^ *byte as u128).wrapping_mul(PRIME);
+            }
+        }
+        format!("{hash:032x}")
+    }
+
+    /// The object code stored under `key`, whose entry then counts as just used.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        let object = std::fs::read(&path).ok()?;
+        let _ = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(object)
+    }
+
+    pub fn put(&self, key: &str, object: &[u8]) {
+        // Written under another name first, so that a concurrent `get` never reads half an entry.
+        let partial = self
+            .dir
+            .join(format!("{key}.{}.partial", std::process::id()));
+        let stored = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&partial, object))
+            .and_then(|_| std::fs::rename(&partial, self.path(key)));
+        if stored.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        let _ = std::fs::remove_file(self.path(key));
+    }
+
+    /// Removes every entry, and returns how many there were.
+    pub fn clear(&self) -> std::io::Result<usize> {
+        self.prune(Duration::ZERO)
+    }
+
+    /// Removes the entries that have not been used for at least `age`, and returns how many there were.
+    pub fn prune(&self, age: Duration) -> std::io::Result<usize> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_none_or(|extension| extension != EXTENSION)
+            {
+                continue;
+            }
+            let unused = std::fs::metadata(&path)?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if unused >= age {
+                std::fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.{EXTENSION}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache in a directory of its own, which is removed first if an earlier run left it behind.
+    fn cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("mathling-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Cache::new(dir)
+    }
+
+    fn age(cache: &Cache, key: &str, days: u64) {
+        let modified = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        std::fs::File::options()
+            .write(true)
+            .open(cache.path(key))
+            .and_then(|file| file.set_modified(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Cache::key(&["a", "b"]), Cache::key(&["a", "b"]));
+        assert_ne!(Cache::key(&["ab", "c"]), Cache::key(&["a", "bc"]));
+        assert_ne!(Cache::key(&["x86_64"]), Cache::key(&["aarch64"]));
+        assert_eq!(Cache::key(&[]).len(), 32);
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = cache("hits");
+        let key = Cache::key(&["program"]);
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, b"object");
+        assert_eq!(cache.get(&key), Some(b"object".to_vec()));
+        assert_eq!(cache.get(&Cache::key(&["another program"])), None);
+        cache.remove(&key);
+        assert_eq!(cache.get(&key), None);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn partial_entries_are_never_read() {
+        let cache = cache("partial");
+        let key = Cache::key(&["program"]);
+        cache.put(&key, b"object");
+        let names = || -> Vec<String> {
+            std::fs::read_dir(&cache.dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect()
+        };
+        // `put` renames what it wrote.
+        assert_eq!(names(), [format!("{key}.o")]);
+
+        // What a process that stopped halfway through `put` leaves behind.
+        let other = Cache::key(&["other program"]);
+        std::fs::write(cache.dir.join(format!("{other}.1.partial")), b"obj").unwrap();
+        assert_eq!(cache.get(&other), None);
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(names(), [format!("{other}.1.partial")]);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn pruning() {
+        let cache = cache("prune");
+        assert_eq!(cache.prune(Duration::ZERO).unwrap(), 0);
+        let (old, recent) = (Cache::key(&["old"]), Cache::key(&["recent"]));
+        cache.put(&old, b"old");
+        cache.put(&recent, b"recent");
+        age(&cache, &old, 10);
+        age(&cache, &recent, 10);
+        // Reading an entry counts as using it.
+        cache.get(&recent).unwrap();
+
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        assert_eq!(cache.prune(week).unwrap(), 1);
+        assert_eq!(cache.get(&old), None);
+        assert!(cache.get(&recent).is_some());
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.get(&recent), None);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+}
@@ -1,151 +1,2059 @@
-use crate::error::ParseError;
-use crate::parse::Parser;
-use crate::symbols::SymbolTable;
-use crate::{Span, Token, TokenType};
-use inkwell::execution_engine::{ExecutionEngine, JitFunction};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::Node;
+use crate::cache::Cache;
+use crate::csv::Table;
+use crate::error::{CompileError, EvalError, IntegrationError, ParseError, ShapeError, TypeError};
+use crate::jit::{self, Jit};
+use crate::matrix::{Matrix, MatrixError, Shape};
+use crate::number::{
+    Complex, Decimal, DecimalError, Estimate, Gradient, Number, NumberMode, Roots, RoundingMode,
+    pow10,
+};
+use crate::parse::{self, Equation, Integral, Loop, Parser, Program};
+use crate::quadrature::{self, QuadratureError};
+use crate::roots::{self, RootError};
+use crate::session::LAST_RESULT;
+use crate::stats::{self, Histogram, StatsError};
+use crate::symbols::{self, SymbolTable, lookup_builtin};
+use crate::units::{self, Quantity};
+use crate::util::is_identifier;
+use crate::{Location, Token, TokenType, covering, span, start};
+use inkwell::attributes::AttributeLoc;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{FileType, TargetMachine};
+use inkwell::values::{FloatValue, FunctionValue, IntValue, PointerValue};
+use inkwell::{AddressSpace, IntPredicate};
 use inkwell::{builder::Builder, context::Context, module::Module};
+use miette::{LabeledSpan, NamedSource, SourceSpan, miette};
 
-use crate::IResult;
-use crate::lexer::{BinOp, Expr, Lexer};
+use crate::lexer::{BinOp, Builtin, Format, Lexer, LinAlg, Reduction, Statistic};
 
 type Function = unsafe extern "C" fn(f32, f32) -> f32;
+type UnaryFunction = unsafe extern "C" fn(f32) -> f32;
+/// Takes both operands in units, writes the result through the pointer, and returns whether it
+/// overflowed.
+type DecimalFunction = unsafe extern "C" fn(i64, i64, *mut i64) -> bool;
+/// Takes both operands as `(re, im)` pairs and writes the result through the pointer.
+type ComplexFunction = unsafe extern "C" fn(f32, f32, f32, f32, *mut Complex);
+/// A compiled `sum`/`prod`: takes both bounds and the captured variables, in the order of `Loop::captures`.
+type LoopFunction = unsafe extern "C" fn(f32, f32, *const f32) -> f32;
+/// A compiled `integrate` or `solve` body: takes its variable and the captured variables, in the order of
+/// `Integral::captures` or `Equation::captures`.
+type KernelFunction = unsafe extern "C" fn(f32, *const f32) -> f32;
+/// A user function, taking its arguments as an array in parameter order.
+type CompiledFunction = unsafe extern "C" fn(*const f32) -> f32;
+/// The dual-number version of a user function, which writes its value and then its gradient through the pointer.
+/// Returns whether every sum and product it ran had whole-number bounds.
+pub type DualFunction = unsafe extern "C" fn(*const f32, *mut f32) -> bool;
+/// An expression compiled for whole arrays: takes one array per parameter, the captured variables, the
+/// output array and the number of entries in each.
+type BatchFunction = unsafe extern "C" fn(*const *const f64, *const f32, *mut f64, u64);
+/// The same expression for a single entry: takes the parameters as an array, and the captured variables.
+type ScalarFunction = unsafe extern "C" fn(*const f64, *const f32) -> f64;
+
+/// Built-ins that have a float implementation in `CodeGen::compile_builtins`, paired with the intrinsic used.
+pub(crate) const FLOAT_BUILTINS: [(Builtin, &str); 4] = [
+    (Builtin::Sqrt, "llvm.sqrt"),
+    (Builtin::Exp, "llvm.exp"),
+    (Builtin::Ln, "llvm.log"),
+    (Builtin::Abs, "llvm.fabs"),
+];
+
+/// Name of the function `Compiler::run_rows` compiles the expression into, which no user function can have.
+const ROW_FUNCTION: &str = "table_row";
+
+/// What `Compiler::run_rows` produces.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowsOutput {
+    /// The value of the expression in each row.
+    Column(Vec<f32>),
+    /// A statistic of those values.
+    Aggregate(Number),
+}
+
+/// A value the program prints, and how it asks for it to be shown.
+#[derive(Debug, Clone)]
+pub struct Printed {
+    pub value: Number,
+    pub format: Format,
+    /// The expression as it was written.
+    pub text: String,
+}
+
+/// Names of the functions `Compiler::compile_expr` compiles the expression into.
+const BATCH_FUNCTION: &str = "batch";
+const SCALAR_FUNCTION: &str = "scalar";
+
+/// Compiles `src` in float mode, so that it can be evaluated many times with different values of
+/// its parameters.
+pub fn compile(context: &Context, src: &str) -> miette::Result<CompiledExpr> {
+    if src.trim().is_empty() {
+        return Err(miette!("Expression cannot be empty."));
+    }
+    let codegen = CodeGen {
+        context,
+        module: context.create_module("expr"),
+        builder: context.create_builder(),
+    };
+    Compiler::new(src, codegen, Lexer::new(src)).compile_expr()
+}
+
+/// An expression compiled by `Compiler::compile_expr`, which evaluates it for any values of its
+/// parameters without lexing, parsing or compiling it again.
+pub struct CompiledExpr {
+    params: Vec<String>,
+    /// Values of the variables the program assigns itself, which are the same for every call.
+    captured: Vec<f32>,
+    batch: BatchFunction,
+    scalar: ScalarFunction,
+    /// Keeps the code both functions point into loaded.
+    _jit: Rc<Jit>,
+}
+
+impl CompiledExpr {
+    /// The variables the expression reads but the program does not assign, in alphabetical order.
+    /// This is the order `call` and `eval_slice` take them in.
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// Evaluates the expression for the given values of its parameters. The arithmetic itself is done
+    /// in `f32`, like everywhere else.
+    ///
+    /// Panics unless there is one argument per parameter.
+    pub fn call(&self, args: &[f64]) -> f64 {
+        assert_eq!(
+            args.len(),
+            self.params.len(),
+            "Expected one argument per parameter."
+        );
+        unsafe { (self.scalar)(args.as_ptr(), self.captured.as_ptr()) }
+    }
+
+    /// Evaluates the expression once per entry of `out`, reading each parameter from the entry at the
+    /// same index of its input. Like `call`, it takes and returns `f64`s but does the arithmetic in
+    /// `f32`, so each entry is exactly what `call` returns for the same values.
+    ///
+    /// Panics unless there is one input per parameter, each as long as `out`.
+    pub fn eval_slice(&self, inputs: &[&[f64]], out: &mut [f64]) {
+        assert_eq!(
+            inputs.len(),
+            self.params.len(),
+            "Expected one input per parameter."
+        );
+        assert!(
+            inputs.iter().all(|input| input.len() == out.len()),
+            "Every input must be as long as the output."
+        );
+        let inputs: Vec<*const f64> = inputs.iter().map(|input| input.as_ptr()).collect();
+        unsafe {
+            (self.batch)(
+                inputs.as_ptr(),
+                self.captured.as_ptr(),
+                out.as_mut_ptr(),
+                out.len() as u64,
+            )
+        }
+    }
+}
+
+/// Loops, integrals and equations that are run from Rust, each compiled into its own function.
+#[derive(Default)]
+struct Kernels<'t> {
+    loops: Vec<&'t Loop>,
+    integrals: Vec<&'t Integral>,
+    equations: Vec<&'t Equation>,
+    /// Whether the dual versions of the user functions were compiled too, for `grad`.
+    duals: bool,
+}
+
+/// Everything fetched from the compiled program, indexed in the order the functions are compiled.
+struct JitFunctions {
+    float: [Function; 5],
+    builtins: [UnaryFunction; 4],
+    complex: [ComplexFunction; 4],
+    decimal: Option<[DecimalFunction; 4]>,
+    /// Keyed by `Loop::location`.
+    loops: HashMap<usize, LoopFunction>,
+    /// Keyed by `Integral::location`.
+    integrands: HashMap<usize, KernelFunction>,
+    /// Keyed by `Equation::location`: the body, followed by its two derivatives when they exist.
+    equations: HashMap<usize, Vec<KernelFunction>>,
+    user: HashMap<String, CompiledFunction>,
+    /// Only compiled when the program uses `grad`.
+    duals: HashMap<String, DualFunction>,
+    /// Only compiled by `Compiler::compile_expr`.
+    batch: Option<BatchFunction>,
+    scalar: Option<ScalarFunction>,
+    /// Keeps the code every function points into loaded.
+    jit: Rc<Jit>,
+}
 
 pub struct Compiler<'ctx> {
+    src: &'ctx str,
     codegen: CodeGen<'ctx>,
     lexer: Lexer<'ctx>,
+    /// Values defined before the program runs, which its own assignments take precedence over.
+    inputs: Vec<(String, Number)>,
+    /// How many results were printed before the program runs, so that the ones it prints go on
+    /// numbering from there.
+    results: usize,
+    /// Where the object code of compiled programs is kept, if anywhere.
+    cache: Option<Cache>,
 }
 
 impl<'ctx> Compiler<'ctx> {
     pub fn new(src: &'ctx str, codegen: CodeGen<'ctx>, lexer: Lexer<'ctx>) -> Self {
-        let lexer = Lexer::new(src);
-        Self { codegen, lexer }
+        Self {
+            src,
+            codegen,
+            lexer,
+            inputs: vec![],
+            results: 0,
+            cache: None,
+        }
+    }
+
+    /// Defines `name` as `value`, unless the program assigns it itself.
+    pub fn with_input(mut self, name: &str, value: Number) -> Self {
+        self.inputs.push((name.to_owned(), value));
+        self
+    }
+
+    /// Defines `_1`, `_2`, ... as `results` and `ans` as the last of them, unless the program assigns
+    /// those itself. Each expression the program prints is added to them for the statements after it.
+    pub fn with_results(mut self, results: &[Number]) -> Self {
+        for (n, result) in results.iter().enumerate() {
+            self = self.with_input(&format!("_{}", n + 1), result.clone());
+        }
+        if let Some(last) = results.last() {
+            self = self.with_input(LAST_RESULT, last.clone());
+        }
+        self.results = results.len();
+        self
+    }
+
+    /// Reuses the object code of a program compiled before, and stores it otherwise.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The value of the last expression the program prints.
+    pub fn run(self) -> miette::Result<Number> {
+        let mut printed = self.run_all()?;
+        match printed.pop() {
+            Some(last) => Ok(last.value),
+            None => Err(miette!("The program does not print anything.")),
+        }
+    }
+
+    /// Every value the program prints, in the order the expressions were written.
+    pub fn run_all(mut self) -> miette::Result<Vec<Printed>> {
+        let (outputs, mut var_symbol_table, user_functions) = self.parse()?;
+        let rpn_tokens: Vec<Token> = outputs
+            .iter()
+            .flat_map(|output| output.rpn.iter().cloned())
+            .collect();
+        let functions = self.compile(&rpn_tokens, &var_symbol_table, &user_functions)?;
+        let assigns_last = var_symbol_table.lookup(LAST_RESULT).is_some()
+            && !self.inputs.iter().any(|(id, _)| id == LAST_RESULT);
+        // The results still to be printed, which definitions after them read as `_n` or `ans`.
+        let mut later: Vec<String> = (1..=outputs.len())
+            .map(|n| format!("_{}", self.results + n))
+            .filter(|numbered| var_symbol_table.lookup(numbered).is_none())
+            .rev()
+            .collect();
+        let mut fp_symbol_table = SymbolTable::new();
+
+        let mut printed = vec![];
+        for output in outputs {
+            // Definitions are evaluated once the results they read are.
+            self.eval_ready(&var_symbol_table, &mut fp_symbol_table, &later, &functions)?;
+            units::check_dimensions(self.src, &output.rpn, &var_symbol_table).map_err(|e| *e)?;
+            let value = Compiler::eval_rpn(self.src, &output.rpn, &fp_symbol_table, &functions)
+                .map_err(|e| *e)?;
+            // The statements after this one read it as `ans` and as the next `_n`.
+            self.results += 1;
+            let numbered = format!("_{}", self.results);
+            let assigns_numbered = var_symbol_table.lookup(&numbered).is_some();
+            let mut define = |id: &str| {
+                let token = Token {
+                    token_type: TokenType::Num(value.clone()),
+                    location: None,
+                };
+                var_symbol_table.add(id, vec![token]);
+                fp_symbol_table.add(id, value.clone());
+            };
+            if !assigns_numbered {
+                define(&numbered);
+                later.pop();
+            }
+            if !assigns_last {
+                define(LAST_RESULT);
+            }
+            printed.push(Printed {
+                value,
+                format: output.format,
+                text: output.text,
+            });
+        }
+        // Definitions after the last result are still evaluated, to report their errors.
+        self.eval_ready(&var_symbol_table, &mut fp_symbol_table, &[], &functions)?;
+        Ok(printed)
+    }
+
+    /// Evaluates the printed expression once per row of `table`, with each numeric column bound to the
+    /// variable of the same name. The expression is compiled into a single function that every row
+    /// calls. When it is a statistic, such as `mean(price * qty)`, its first argument is what is
+    /// compiled, and the statistic is taken of the values of every row.
+    pub fn run_rows(mut self, table: &Table) -> miette::Result<RowsOutput> {
+        if let NumberMode::Decimal(_) = self.lexer.number_mode() {
+            return Err(miette!("Tables are only available in float mode."));
+        }
+        // Whole columns are also vectors, which is what they are outside the compiled expression.
+        let mut text_columns = HashMap::new();
+        for (n, name) in table.header.iter().enumerate() {
+            if !is_identifier(name) {
+                continue;
+            }
+            match table.numbers(n) {
+                Ok(values) => {
+                    self.inputs
+                        .push((name.clone(), Number::Matrix(Matrix::vector(values))));
+                }
+                Err(cell) => {
+                    text_columns.insert(name.clone(), cell);
+                }
+            }
+        }
+        let (outputs, var_symbol_table, mut user_functions) = self.parse()?;
+        self.check_dimensions(&outputs, &var_symbol_table)?;
+        let rpn_tokens = Compiler::single(outputs)?;
+
+        let (row_range, aggregate) = match rpn_tokens.last().map(|t| &t.token_type) {
+            Some(TokenType::Statistic(_, arity)) => {
+                let mut start = rpn_tokens.len() - 1;
+                let mut end = start;
+                for _ in 0..*arity {
+                    end = start;
+                    start = parse::operand_start(&rpn_tokens[..end]).ok_or_else(|| {
+                        let location = rpn_tokens.last().and_then(|token| token.location);
+                        *Compiler::ill_formed(self.src, location)
+                    })?;
+                }
+                (start..end, true)
+            }
+            _ => (0..rpn_tokens.len(), false),
+        };
+        let row = &rpn_tokens[row_range.clone()];
+        let rest: Vec<Token> = rpn_tokens[row_range.end..].to_vec();
+
+        let columns: HashMap<&str, &[f32]> = self
+            .inputs
+            .iter()
+            .filter_map(|(id, value)| match value {
+                Number::Matrix(m) => Some((id.as_str(), m.data.as_slice())),
+                _ => None,
+            })
+            .collect();
+        let mut params = vec![];
+        parse::free_variables(row, &mut vec![], &mut params);
+        for id in &params {
+            if columns.contains_key(id.as_str()) || var_symbol_table.lookup(id).is_some() {
+                continue;
+            }
+            let location = row
+                .iter()
+                .find(|t| t.token_type == TokenType::Id(id.clone()))
+                .and_then(|t| t.location);
+            let (label, help) = match text_columns.get(id) {
+                Some((row, cell)) => (
+                    "this column is not numeric".to_owned(),
+                    format!("Row {row} of `{id}` is `{cell}`."),
+                ),
+                None => (
+                    "this is not a column".to_owned(),
+                    format!("The columns are {}.", table.header.join(", ")),
+                ),
+            };
+            return Err(CompileError {
+                src: NamedSource::new("input", self.src.to_owned()),
+                message: format!("`{id}` cannot be read from the table"),
+                span: span(location),
+                label,
+                help: Some(help),
+            }
+            .into());
+        }
+        user_functions.push(parse::Function {
+            name: ROW_FUNCTION.to_owned(),
+            params: params.clone(),
+            body: row.to_vec(),
+            location: start(row[0].location),
+        });
+
+        let functions = self.compile(&rest, &var_symbol_table, &user_functions)?;
+        let fp_symbol_table = self.eval_variables(&var_symbol_table, &functions)?;
+        // Variables the program assigns itself are the same in every row.
+        let mut args = vec![0.; params.len()];
+        let mut per_row = vec![];
+        for (n, id) in params.iter().enumerate() {
+            match fp_symbol_table.lookup(id) {
+                Some(Number::Matrix(_)) if columns.contains_key(id.as_str()) => {
+                    per_row.push((n, columns[id.as_str()]))
+                }
+                Some(Number::Float(x)) => args[n] = *x,
+                Some(value) => {
+                    return Err(miette!(
+                        "`{id}` is used in every row, but is {value} rather than a real number."
+                    ));
+                }
+                None => unreachable!("Checked against the table and the variables above."),
+            }
+        }
+        let values: Vec<f32> = (0..table.rows.len())
+            .map(|row| {
+                for (n, column) in &per_row {
+                    args[*n] = column[row];
+                }
+                unsafe { (functions.user[ROW_FUNCTION])(args.as_ptr()) }
+            })
+            .collect();
+
+        if !aggregate {
+            return Ok(RowsOutput::Column(values));
+        }
+        let mut tokens = vec![Token {
+            token_type: TokenType::Num(Number::Matrix(Matrix::vector(values))),
+            location: row[0].location,
+        }];
+        tokens.extend(rest);
+        Ok(RowsOutput::Aggregate(
+            Compiler::eval_rpn(self.src, &tokens, &fp_symbol_table, &functions).map_err(|e| *e)?,
+        ))
     }
 
-    pub fn run(mut self) -> IResult<'ctx, f32> {
-        let (_, lexed_tokens) = self.lexer.lex()?;
+    /// Compiles the printed expression into a function of its parameters, and a loop over arrays of
+    /// them, which LLVM inlines and vectorizes for the host. Every variable the program does not
+    /// assign becomes a parameter.
+    pub fn compile_expr(mut self) -> miette::Result<CompiledExpr> {
+        if let NumberMode::Decimal(_) = self.lexer.number_mode() {
+            return Err(miette!(
+                "Compiled expressions are only available in float mode."
+            ));
+        }
+        let (outputs, var_symbol_table, user_functions) = self.parse()?;
+        self.check_dimensions(&outputs, &var_symbol_table)?;
+        let rpn_tokens = Compiler::single(outputs)?;
+        let mut free = vec![];
+        parse::free_variables(&rpn_tokens, &mut vec![], &mut free);
+        let (captures, mut params): (Vec<String>, Vec<String>) = free
+            .into_iter()
+            .partition(|id| var_symbol_table.lookup(id).is_some());
+        params.sort();
+
+        let kernels = self.emit(&[], &var_symbol_table, &user_functions)?;
+        self.codegen
+            .compile_batch(self.src, &params, &captures, &rpn_tokens)
+            .map_err(|e| *e)?;
+        self.codegen
+            .compile_scalar(self.src, &params, &captures, &rpn_tokens)
+            .map_err(|e| *e)?;
+        let functions = self.jit(&kernels, &user_functions, true)?;
+        let fp_symbol_table = self.eval_variables(&var_symbol_table, &functions)?;
+        let captured = captures
+            .iter()
+            .map(|id| match fp_symbol_table.lookup(id) {
+                Some(Number::Float(x)) => Ok(*x),
+                Some(value) => Err(miette!(
+                    "`{id}` is used in every entry, but is {value} rather than a real number."
+                )),
+                None => unreachable!("Captures are the variables the program assigns."),
+            })
+            .collect::<miette::Result<_>>()?;
+        let batch = functions
+            .batch
+            .expect("The batch function was emitted above.");
+        let scalar = functions
+            .scalar
+            .expect("The scalar function was emitted above.");
+        Ok(CompiledExpr {
+            params,
+            captured,
+            batch,
+            scalar,
+            _jit: functions.jit,
+        })
+    }
+
+    /// Lexes and parses the program, defines the inputs it does not assign itself, and checks its
+    /// assignments before anything is compiled.
+    fn parse(&mut self) -> miette::Result<Program> {
+        let lexed_tokens = self.lexer.lex().map_err(|e| *e)?;
+        symbols::check_assignments(self.src, &lexed_tokens).map_err(|e| *e)?;
+        let parser = Parser::new(lexed_tokens)
+            .with_results(self.results)
+            .with_inputs(&self.inputs);
+        let (_, (outputs, mut var_symbol_table, user_functions)) =
+            parser.parse().map_err(|e| syntax_error(self.src, e))?;
+        // Only the inputs that end up defined are kept.
+        self.inputs
+            .retain(|(id, _)| var_symbol_table.lookup(id).is_none());
+        for (id, value) in &self.inputs {
+            let token = Token {
+                token_type: TokenType::Num(value.clone()),
+                location: None,
+            };
+            var_symbol_table.add(id, vec![token]);
+        }
+        Ok((outputs, var_symbol_table, user_functions))
+    }
+
+    /// Checks that every printed expression only combines quantities of the same dimension.
+    fn check_dimensions(
+        &self,
+        outputs: &[parse::Output],
+        var_symbol_table: &SymbolTable<Vec<Token>>,
+    ) -> miette::Result<()> {
+        for output in outputs {
+            units::check_dimensions(self.src, &output.rpn, var_symbol_table).map_err(|e| *e)?;
+        }
+        Ok(())
+    }
+
+    /// The one expression the program prints, for the ways of running it that produce a single value.
+    fn single(mut outputs: Vec<parse::Output>) -> miette::Result<Vec<Token>> {
+        match outputs.len() {
+            0 => Err(miette!("The program does not print anything.")),
+            1 => Ok(outputs.pop().unwrap().rpn),
+            n => Err(miette!(
+                "The program prints {n} expressions, but only one can be used here."
+            )),
+        }
+    }
+
+    /// Compiles the arithmetic, the user functions and every loop, integral and equation that
+    /// `rpn_tokens` or a variable runs, and fetches them all from a new execution engine.
+    fn compile(
+        &self,
+        rpn_tokens: &[Token],
+        var_symbol_table: &SymbolTable<Vec<Token>>,
+        user_functions: &[parse::Function],
+    ) -> miette::Result<JitFunctions> {
+        let kernels = self.emit(rpn_tokens, var_symbol_table, user_functions)?;
+        self.jit(&kernels, user_functions, false)
+    }
+
+    /// Emits the IR of everything `compile` fetches, and returns the kernels it emitted.
+    fn emit<'t>(
+        &self,
+        rpn_tokens: &'t [Token],
+        var_symbol_table: &'t SymbolTable<Vec<Token>>,
+        user_functions: &[parse::Function],
+    ) -> miette::Result<Kernels<'t>> {
+        let number_mode = self.lexer.number_mode();
+        self.codegen.compile_all_fns();
+        if let NumberMode::Decimal(config) = number_mode {
+            self.codegen
+                .compile_decimal_fns(config.scale, config.rounding);
+        }
+        let mut kernels = Kernels::default();
+        Compiler::collect_kernels(rpn_tokens, &mut kernels);
+        for tokens in var_symbol_table.variables.values() {
+            Compiler::collect_kernels(tokens, &mut kernels);
+        }
+        if let (Some(reduction), NumberMode::Decimal(_)) = (kernels.loops.first(), number_mode) {
+            return Err(miette!(
+                "`{}` is only available in float mode.",
+                reduction.reduction
+            ));
+        }
+        if let (Some(_), NumberMode::Decimal(_)) = (kernels.integrals.first(), number_mode) {
+            return Err(miette!("`integrate` is only available in float mode."));
+        }
+        if let (Some(_), NumberMode::Decimal(_)) = (kernels.equations.first(), number_mode) {
+            return Err(miette!("`solve` is only available in float mode."));
+        }
+        if let (Some(function), NumberMode::Decimal(_)) = (user_functions.first(), number_mode) {
+            return Err(miette!(
+                "Functions such as `{}` are only available in float mode.",
+                function.name
+            ));
+        }
+        // In the parser's order, which puts every function after the ones it calls.
+        for function in user_functions {
+            self.codegen
+                .compile_function(self.src, function)
+                .map_err(|e| *e)?;
+        }
+        kernels.duals = Compiler::uses_grad(rpn_tokens)
+            || var_symbol_table
+                .variables
+                .values()
+                .any(|tokens| Compiler::uses_grad(tokens));
+        if kernels.duals {
+            for function in user_functions {
+                self.codegen
+                    .compile_dual(self.src, function)
+                    .map_err(|e| *e)?;
+            }
+        }
+        for reduction in &kernels.loops {
+            self.codegen
+                .compile_loop(self.src, reduction)
+                .map_err(|e| *e)?;
+        }
+        for integral in &kernels.integrals {
+            self.codegen
+                .compile_integrand(self.src, integral)
+                .map_err(|e| *e)?;
+        }
+        for equation in &kernels.equations {
+            self.codegen
+                .compile_equation(self.src, equation)
+                .map_err(|e| *e)?;
+        }
+        Ok(kernels)
+    }
+
+    /// Turns the module `emit` filled into object code, or takes it from the cache when the same module
+    /// was compiled before, and fetches every function from it. `optimize` runs the `-O3` pipeline
+    /// over the module first.
+    fn jit(
+        &self,
+        kernels: &Kernels,
+        user_functions: &[parse::Function],
+        optimize: bool,
+    ) -> miette::Result<JitFunctions> {
+        let module = &self.codegen.module;
+        let machine = jit::host_machine();
+        module.set_triple(&machine.get_triple());
+        module.set_data_layout(&machine.get_target_data().get_data_layout());
+        // The IR is the source in normal form, and also records the number mode. The target and the
+        // host CPU with its features are part of the key too, so that a cache shared between
+        // machines never hands one of them code compiled for another.
+        let key = Cache::key(&[
+            &module.print_to_string().to_string(),
+            &format!("{:?} {optimize}", jit::OPTIMIZATION_LEVEL),
+            &machine.get_triple().to_string(),
+            &TargetMachine::get_host_cpu_name().to_string(),
+            &TargetMachine::get_host_cpu_features().to_string(),
+        ]);
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(&key))
+            .and_then(|object| Jit::load(&object).ok());
+        let jit = match cached {
+            Some(jit) => jit,
+            None => {
+                if optimize {
+                    self.codegen.optimize(&machine);
+                }
+                let object = machine
+                    .write_to_memory_buffer(module, FileType::Object)
+                    .map_err(|e| miette!("{e}"))?;
+                if let Some(cache) = &self.cache {
+                    cache.put(&key, object.as_slice());
+                }
+                Jit::load(object.as_slice()).map_err(|e| miette!("{e}"))?
+            }
+        };
+        Ok(self.fetch(Rc::new(jit), kernels, user_functions))
+    }
+
+    fn fetch(
+        &self,
+        jit: Rc<Jit>,
+        kernels: &Kernels,
+        user_functions: &[parse::Function],
+    ) -> JitFunctions {
+        let number_mode = self.lexer.number_mode();
+        JitFunctions {
+            float: ["sum", "sub", "mul", "div", "pow"]
+                .map(|name| unsafe { jit.get::<Function>(name) }),
+            builtins: FLOAT_BUILTINS.map(|(builtin, _)| unsafe { jit.get(&builtin.to_string()) }),
+            complex: ["c_sum", "c_sub", "c_mul", "c_div"]
+                .map(|name| unsafe { jit.get::<ComplexFunction>(name) }),
+            decimal: match number_mode {
+                NumberMode::Decimal(_) => Some(
+                    ["dec_add", "dec_sub", "dec_mul", "dec_div"]
+                        .map(|name| unsafe { jit.get::<DecimalFunction>(name) }),
+                ),
+                _ => None,
+            },
+            loops: kernels
+                .loops
+                .iter()
+                .map(|reduction| {
+                    let name = format!("loop_{}", reduction.location);
+                    let function = unsafe { jit.get::<LoopFunction>(&name) };
+                    (reduction.location, function)
+                })
+                .collect(),
+            integrands: kernels
+                .integrals
+                .iter()
+                .map(|integral| {
+                    let name = format!("integrand_{}", integral.location);
+                    let function = unsafe { jit.get::<KernelFunction>(&name) };
+                    (integral.location, function)
+                })
+                .collect(),
+            equations: kernels
+                .equations
+                .iter()
+                .map(|equation| {
+                    let suffixes = match equation.derivatives {
+                        Some(_) => &["", "_d1", "_d2"][..],
+                        None => &[""],
+                    };
+                    let functions = suffixes
+                        .iter()
+                        .map(|suffix| unsafe {
+                            jit.get::<KernelFunction>(&format!(
+                                "solve_{}{suffix}",
+                                equation.location
+                            ))
+                        })
+                        .collect();
+                    (equation.location, functions)
+                })
+                .collect(),
+            user: user_functions
+                .iter()
+                .map(|function| {
+                    let compiled =
+                        unsafe { jit.get::<CompiledFunction>(&format!("fn_{}", function.name)) };
+                    (function.name.clone(), compiled)
+                })
+                .collect(),
+            duals: user_functions
+                .iter()
+                .filter(|_| kernels.duals)
+                .map(|function| {
+                    let compiled =
+                        unsafe { jit.get::<DualFunction>(&format!("fn_{}_dual", function.name)) };
+                    (function.name.clone(), compiled)
+                })
+                .collect(),
+            batch: self
+                .codegen
+                .module
+                .get_function(BATCH_FUNCTION)
+                .map(|_| unsafe { jit.get::<BatchFunction>(BATCH_FUNCTION) }),
+            scalar: self
+                .codegen
+                .module
+                .get_function(SCALAR_FUNCTION)
+                .map(|_| unsafe { jit.get::<ScalarFunction>(SCALAR_FUNCTION) }),
+            jit,
+        }
+    }
+
+    /// Builds the complete variable symbol table.
+    fn eval_variables(
+        &self,
+        var_symbol_table: &SymbolTable<Vec<Token>>,
+        functions: &JitFunctions,
+    ) -> miette::Result<SymbolTable<Number>> {
+        let mut values = SymbolTable::new();
+        self.eval_ready(var_symbol_table, &mut values, &[], functions)?;
+        Ok(values)
+    }
+
+    /// Adds to `values` the variables that do not read a result in `later`, which is still to be printed.
+    fn eval_ready(
+        &self,
+        var_symbol_table: &SymbolTable<Vec<Token>>,
+        values: &mut SymbolTable<Number>,
+        later: &[String],
+        functions: &JitFunctions,
+    ) -> miette::Result<()> {
+        let src = self.src;
+        var_symbol_table
+            .evaluate(values, later, |tokens, symbol_table| {
+                Compiler::eval_rpn(src, tokens, symbol_table, functions).map(Number::into_value)
+            })
+            .map_err(|e| *e)?;
+        Ok(())
+    }
+
+    /// The printed expression as a simplified tree, with variables and function calls substituted,
+    /// instead of a number. Variables that are never assigned stay symbolic.
+    pub fn simplified(self) -> miette::Result<Node> {
+        let lexed_tokens = self.lexer.lex().map_err(|e| *e)?;
+        symbols::check_assignments(self.src, &lexed_tokens).map_err(|e| *e)?;
         let parser = Parser::new(lexed_tokens);
-        let (_, (rpn_tokens, mut var_symbol_table)) = parser.parse()?;
-        // Build complete variable symbol table.
-        let fp_symbol_table = var_symbol_table.simplify();
-
-        // LLVM.
-        let fn_symbol_table = self.codegen.compile_all_fns();
-        let execution_engine: ExecutionEngine<'ctx> = self
-            .codegen
-            .module
-            .create_jit_execution_engine(inkwell::OptimizationLevel::Aggressive)
-            .unwrap();
+        let (_, (outputs, var_symbol_table, user_functions)) =
+            parser.parse().map_err(|e| syntax_error(self.src, e))?;
+        let rpn_tokens = Compiler::single(outputs)?;
 
-        // Need to get all functions here because doing so compiles the module. For some reason, you can not recompile the module after the first time.
-        let sum = { unsafe { execution_engine.get_function("sum").ok().unwrap() } }
-            as JitFunction<'ctx, Function>;
-        let sub = { unsafe { execution_engine.get_function("sub").ok().unwrap() } }
-            as JitFunction<'ctx, Function>;
-        let mul = { unsafe { execution_engine.get_function("mul").ok().unwrap() } }
-            as JitFunction<'ctx, Function>;
-        let div = { unsafe { execution_engine.get_function("div").ok().unwrap() } }
-            as JitFunction<'ctx, Function>;
-        let functions = [sum, sub, mul, div];
+        let mut node = Node::from_rpn(&rpn_tokens, &user_functions).map_err(|e| miette!("{e}"))?;
+        // Once per variable is enough for definitions that refer to each other.
+        for _ in 0..var_symbol_table.variables.len() {
+            for (id, tokens) in &var_symbol_table.variables {
+                let value = Node::from_rpn(tokens, &user_functions).map_err(|e| miette!("{e}"))?;
+                node = node.substitute(id, &value);
+            }
+        }
 
-        let result = Compiler::eval_rpn(rpn_tokens, fp_symbol_table, functions);
-        Ok((Span::new(""), result))
+        Ok(node.simplify())
     }
 
-    fn eval_rpn(
-        tokens: Vec<Token>,
-        symbol_table: SymbolTable<f32>,
-        functions: [JitFunction<'ctx, Function>; 4],
-    ) -> f32 {
-        let sum = &functions[0];
-        let sub = &functions[1];
-        let mul = &functions[2];
-        let div = &functions[3];
-
-        let mut stack: Vec<Token> = vec![];
+    fn uses_grad(tokens: &[Token]) -> bool {
+        tokens.iter().any(|token| match &token.token_type {
+            TokenType::Grad(..) => true,
+            TokenType::Loop(reduction) => {
+                Compiler::uses_grad(&reduction.from) || Compiler::uses_grad(&reduction.to)
+            }
+            TokenType::Integral(integral) => {
+                Compiler::uses_grad(&integral.from)
+                    || Compiler::uses_grad(&integral.to)
+                    || integral
+                        .tolerance
+                        .as_deref()
+                        .is_some_and(Compiler::uses_grad)
+            }
+            TokenType::Equation(equation) => {
+                equation.guess.as_deref().is_some_and(Compiler::uses_grad)
+            }
+            _ => false,
+        })
+    }
+
+    /// Loops, integrals and equations that are run from Rust: those outside any loop body or
+    /// integrand, including ones inside the bounds, tolerance or guess of another.
+    fn collect_kernels<'t>(tokens: &'t [Token], kernels: &mut Kernels<'t>) {
         for token in tokens {
-            match token {
-                Token {
-                    token_type: TokenType::Fp(ref n),
-                    ..
-                } => stack.push(token),
-                Token {
-                    token_type: TokenType::Id(ref id),
-                    location_col,
-                } => {
-                    let val = *symbol_table.lookup(id).unwrap();
-                    stack.push(Token {
-                        token_type: TokenType::Fp(val),
-                        location_col,
+            match &token.token_type {
+                TokenType::Loop(reduction) => {
+                    kernels.loops.push(reduction);
+                    Compiler::collect_kernels(&reduction.from, kernels);
+                    Compiler::collect_kernels(&reduction.to, kernels);
+                }
+                TokenType::Integral(integral) => {
+                    kernels.integrals.push(integral);
+                    Compiler::collect_kernels(&integral.from, kernels);
+                    Compiler::collect_kernels(&integral.to, kernels);
+                    if let Some(tolerance) = &integral.tolerance {
+                        Compiler::collect_kernels(tolerance, kernels);
+                    }
+                }
+                TokenType::Equation(equation) => {
+                    kernels.equations.push(equation);
+                    if let Some(guess) = &equation.guess {
+                        Compiler::collect_kernels(guess, kernels);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Checks the bounds, then runs the compiled loop with the current values of its captured variables.
+    fn eval_loop(
+        src: &str,
+        reduction: &Loop,
+        symbol_table: &SymbolTable<Number>,
+        functions: &JitFunctions,
+    ) -> Result<Number, Box<EvalError>> {
+        let bound = |rpn: &[Token], location: usize| match Compiler::eval_rpn(
+            src,
+            rpn,
+            symbol_table,
+            functions,
+        )?
+        .into_value()
+        {
+            Number::Float(x) if x.is_finite() && x.fract() == 0. => Ok(x),
+            n => Err(Compiler::compile_error(
+                src,
+                format!("Bounds of `{}` must be whole numbers", reduction.reduction),
+                location,
+                format!("this is {n}"),
+                None,
+            )),
+        };
+        let from = bound(&reduction.from, reduction.from_location)?;
+        let to = bound(&reduction.to, reduction.to_location)?;
+        if from > to {
+            return Err(Compiler::compile_error(
+                src,
+                format!(
+                    "The lower bound of `{}` is greater than its upper bound",
+                    reduction.reduction
+                ),
+                reduction.from_location,
+                format!("{from} > {to}"),
+                Some("Swap the bounds, or use bounds that describe at least one term."),
+            ));
+        }
+
+        let captures = reduction
+            .captures()
+            .into_iter()
+            .map(|id| {
+                match symbol_table
+                    .lookup(&id)
+                    .cloned()
+                    .or_else(|| lookup_builtin(&id))
+                {
+                    Some(Number::Float(x)) => Ok(x),
+                    Some(n) => Err(Compiler::compile_error(
+                        src,
+                        format!(
+                            "`{id}` is used inside `{}` but is not a real number",
+                            reduction.reduction
+                        ),
+                        reduction.location,
+                        format!("`{id}` is {n}"),
+                        Some("Only real numbers can be used inside sums and products."),
+                    )),
+                    None => Err(Compiler::compile_error(
+                        src,
+                        format!("Unknown variable `{id}`"),
+                        reduction.location,
+                        format!("`{id}` is used in here"),
+                        None,
+                    )),
+                }
+            })
+            .collect::<Result<Vec<f32>, _>>()?;
+
+        let function = &functions.loops[&reduction.location];
+        Ok(Number::Float(unsafe {
+            function(from, to, captures.as_ptr())
+        }))
+    }
+
+    /// Current values of the variables a compiled integrand or equation reads from outside.
+    fn captured_values(
+        src: &str,
+        captures: Vec<String>,
+        keyword: &str,
+        location: usize,
+        symbol_table: &SymbolTable<Number>,
+    ) -> Result<Vec<f32>, Box<EvalError>> {
+        captures
+            .into_iter()
+            .map(|id| {
+                match symbol_table
+                    .lookup(&id)
+                    .cloned()
+                    .or_else(|| lookup_builtin(&id))
+                {
+                    Some(Number::Float(x)) => Ok(x),
+                    Some(n) => Err(Compiler::compile_error(
+                        src,
+                        format!("`{id}` is used inside `{keyword}` but is not a real number"),
+                        location,
+                        format!("`{id}` is {n}"),
+                        None,
+                    )),
+                    None => Err(Compiler::compile_error(
+                        src,
+                        format!("Unknown variable `{id}`"),
+                        location,
+                        format!("`{id}` is used in here"),
+                        None,
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    /// Solves in closed form when the equation is at most quadratic, and otherwise with Newton's
+    /// method from the guess, falling back to Brent's method on a bracket around it. Without a
+    /// guess, every sign change found by scanning is refined.
+    fn eval_equation(
+        src: &str,
+        equation: &Equation,
+        symbol_table: &SymbolTable<Number>,
+        functions: &JitFunctions,
+    ) -> Result<Vec<Number>, Box<EvalError>> {
+        let guess = match &equation.guess {
+            Some(rpn) => {
+                match Compiler::eval_rpn(src, rpn, symbol_table, functions)?.into_value() {
+                    Number::Float(x) if x.is_finite() => Some(x as f64),
+                    n => {
+                        return Err(Compiler::compile_error(
+                            src,
+                            "The guess passed to `solve` must be a finite real number".to_owned(),
+                            equation.guess_location,
+                            format!("this is {n}"),
+                            None,
+                        ));
+                    }
+                }
+            }
+            None => None,
+        };
+        let captures = Compiler::captured_values(
+            src,
+            equation.captures(),
+            "solve",
+            equation.location,
+            symbol_table,
+        )?;
+
+        let compiled = &functions.equations[&equation.location];
+        let kernel = |n: usize| {
+            let compiled = compiled.get(n);
+            let captures = &captures;
+            move |x: f64| unsafe { compiled.unwrap()(x as f32, captures.as_ptr()) as f64 }
+        };
+        let (f, df, d2f) = (kernel(0), kernel(1), kernel(2));
+        // Only equations with derivatives can be quadratic.
+        let coefficients = match equation.quadratic {
+            true => Some([d2f(0.) / 2., df(0.), f(0.)]).filter(|c| c.iter().all(|c| c.is_finite())),
+            false => None,
+        };
+        let found: Vec<Number> = if let Some([a, b, c]) = coefficients {
+            match roots::quadratic(a, b, c) {
+                // A guess picks the closest real root.
+                Ok(found) if let Some(guess) = guess => found
+                    .into_iter()
+                    .filter(|(_, im)| *im == 0.)
+                    .min_by(|(x, _), (y, _)| (x - guess).abs().total_cmp(&(y - guess).abs()))
+                    .map(|(re, _)| Number::Float(re as f32 + 0.))
+                    .into_iter()
+                    .collect(),
+                // Adding zero turns `-0` into `0`.
+                Ok(found) => found
+                    .into_iter()
+                    .map(|(re, im)| match im {
+                        0. => Number::Float(re as f32 + 0.),
+                        _ => Number::Complex(Complex::new(re as f32 + 0., im as f32)),
                     })
+                    .collect(),
+                Err(RootError::Degenerate { always }) => {
+                    let message = if always {
+                        format!(
+                            "Every value of `{}` solves this equation",
+                            equation.variable
+                        )
+                    } else {
+                        "This equation has no solution".to_owned()
+                    };
+                    return Err(Compiler::compile_error(
+                        src,
+                        message,
+                        equation.location,
+                        "solved here".to_owned(),
+                        None,
+                    ));
                 }
-                Token {
-                    token_type: TokenType::BinOp(op),
-                    ..
-                } => {
-                    // Made `mut` so they can be made into floats if the operator is division.
-                    let mut y = match stack.pop() {
-                        Some(Token {
-                            token_type: TokenType::Fp(n),
-                            ..
-                        }) => n,
-                        _ => {
-                            panic!("Ill-formed expression.")
+            }
+        } else {
+            let newton = |guess| match equation.derivatives {
+                Some(_) => roots::newton(f, df, guess),
+                None => None,
+            };
+            let mut found: Vec<f64> = match guess {
+                Some(guess) => newton(guess)
+                    .or_else(|| roots::bracket(f, guess).and_then(|(a, b)| roots::brent(f, a, b)))
+                    .into_iter()
+                    .collect(),
+                None => roots::scan(f)
+                    .into_iter()
+                    .filter_map(|(a, b)| roots::brent(f, a, b))
+                    .collect(),
+            };
+            // Roots that touch zero without crossing it are only found by Newton's method.
+            if found.is_empty() && guess.is_none() {
+                found.extend(newton(0.));
+            }
+            found.sort_by(f64::total_cmp);
+            found.dedup_by(|x, y| (*x as f32) == (*y as f32));
+            found
+                .into_iter()
+                .map(|root| Number::Float(root as f32))
+                .collect()
+        };
+
+        if found.is_empty() {
+            return Err(Compiler::compile_error(
+                src,
+                format!("`solve` found no roots for `{}`", equation.variable),
+                equation.location,
+                "solved here".to_owned(),
+                Some("Try passing a guess close to a root as the third argument."),
+            ));
+        }
+        Ok(found)
+    }
+
+    /// Evaluates the bounds and tolerance, then integrates the compiled integrand between the bounds.
+    fn eval_integral(
+        src: &str,
+        integral: &Integral,
+        symbol_table: &SymbolTable<Number>,
+        functions: &JitFunctions,
+    ) -> Result<Estimate, Box<EvalError>> {
+        let real = |rpn: &[Token], location: usize, what: &str| match Compiler::eval_rpn(
+            src,
+            rpn,
+            symbol_table,
+            functions,
+        )?
+        .into_value()
+        {
+            Number::Float(x) if x.is_finite() => Ok(x as f64),
+            n => Err(Compiler::compile_error(
+                src,
+                format!("The {what} of `integrate` must be a finite real number"),
+                location,
+                format!("this is {n}"),
+                None,
+            )),
+        };
+        let from = real(&integral.from, integral.from_location, "lower bound")?;
+        let to = real(&integral.to, integral.to_location, "upper bound")?;
+        let tolerance = match &integral.tolerance {
+            Some(rpn) => real(rpn, integral.tolerance_location, "tolerance")?,
+            None => quadrature::DEFAULT_TOLERANCE,
+        };
+        if tolerance <= 0. {
+            return Err(Compiler::compile_error(
+                src,
+                "The tolerance of `integrate` must be positive".to_owned(),
+                integral.tolerance_location,
+                format!("this is {tolerance}"),
+                None,
+            ));
+        }
+
+        let captures = Compiler::captured_values(
+            src,
+            integral.captures(),
+            "integrate",
+            integral.location,
+            symbol_table,
+        )?;
+
+        let integrand = &functions.integrands[&integral.location];
+        let f = |x: f64| unsafe { integrand(x as f32, captures.as_ptr()) } as f64;
+        let not_converged = |message: String, label: String, help: &str| {
+            Box::new(EvalError::from(IntegrationError {
+                src: NamedSource::new("input", src.to_owned()),
+                message,
+                span: SourceSpan::from(integral.location),
+                label,
+                help: Some(help.to_owned()),
+            }))
+        };
+        match quadrature::adaptive_simpson(f, from, to, tolerance) {
+            Ok((value, error)) => Ok(Estimate {
+                value: value as f32,
+                error: error as f32,
+            }),
+            Err(QuadratureError::NotConverged { value, error }) => Err(not_converged(
+                format!("`integrate` did not reach a tolerance of {tolerance}"),
+                format!("the best estimate is {value} ± {error:.1e}"),
+                "Try a larger tolerance, or split the interval where the integrand changes sharply.",
+            )),
+            Err(QuadratureError::NotFinite { at }) => Err(not_converged(
+                format!(
+                    "The integrand is not finite at {} = {at}",
+                    integral.variable
+                ),
+                "integrated here".to_owned(),
+                "Split the interval at the singularity, or move the bounds away from it.",
+            )),
+        }
+    }
+
+    fn eval_rpn(
+        src: &str,
+        tokens: &[Token],
+        symbol_table: &SymbolTable<Number>,
+        functions: &JitFunctions,
+    ) -> Result<Number, Box<EvalError>> {
+        // Each value with the location of the tokens it was computed from.
+        let mut stack: Vec<(Number, Option<Location>)> = vec![];
+        for (n, token) in tokens.iter().cloned().enumerate() {
+            // Only the printed result keeps what is printed alongside its value.
+            let last = n == tokens.len() - 1;
+            let Token {
+                token_type,
+                location,
+            } = token;
+            match token_type {
+                TokenType::Num(n) => stack.push((n, location)),
+                TokenType::Id(id) => {
+                    let val = match symbol_table
+                        .lookup(&id)
+                        .cloned()
+                        .or_else(|| lookup_builtin(&id))
+                    {
+                        Some(val) => val,
+                        None => {
+                            return Err(Box::new(EvalError::from(CompileError {
+                                src: NamedSource::new("input", src.to_owned()),
+                                message: format!("Unknown variable `{id}`"),
+                                span: span(location),
+                                label: "not defined".to_owned(),
+                                help: Some(Compiler::undefined_help(&id)),
+                            })));
                         }
                     };
-                    let mut x = match stack.pop() {
-                        Some(Token {
-                            token_type: TokenType::Fp(n),
-                            ..
-                        }) => n,
-                        _ => panic!("Ill-formed expression."),
-                    };
-                    match op {
-                        BinOp::Plus => {
-                            let result = unsafe { sum.call(x, y) };
-                            stack.push(Token {
-                                token_type: TokenType::Fp(result),
-                                location_col: None,
-                            });
-                        }
-                        BinOp::Minus => {
-                            let result = unsafe { sub.call(x, y) };
-                            stack.push(Token {
-                                token_type: TokenType::Fp(result),
-                                location_col: None,
-                            });
+                    // Like the expression that made it, a result such as `ans` is only printed in full on its own.
+                    stack.push((if last { val } else { val.into_value() }, location));
+                }
+                TokenType::Negate => {
+                    let [(x, x_location)] = Compiler::operands(src, &mut stack, location)?;
+                    let location = covering(location, x_location);
+                    let value = match x {
+                        Number::Float(a) => Number::Float(-a),
+                        Number::Decimal(d) => {
+                            Number::Decimal(Decimal::from_units(-d.units(), d.config()))
                         }
-                        BinOp::Times => {
-                            let result = unsafe { mul.call(x, y) };
-                            stack.push(Token {
-                                token_type: TokenType::Fp(result),
-                                location_col: None,
-                            });
+                        Number::Complex(c) => Number::Complex(-c),
+                        Number::Quantity(q) => Number::Quantity(Quantity {
+                            value: -q.value,
+                            ..q
+                        }),
+                        Number::Matrix(m) => Number::Matrix(Matrix {
+                            shape: m.shape,
+                            data: m.data.iter().map(|x| -x).collect(),
+                        }),
+                        x => {
+                            return Err(Compiler::type_error(src, "`-`", &[(&x, x_location)]));
                         }
-                        BinOp::Divide => {
-                            let result = unsafe { div.call(x, y) };
-                            stack.push(Token {
-                                token_type: TokenType::Fp(result),
-                                location_col: None,
-                            });
+                    };
+                    stack.push((value, location));
+                }
+                TokenType::Builtin(builtin) => {
+                    let call_location = location;
+                    let [(x, location)] = Compiler::operands(src, &mut stack, call_location)?;
+                    let value = match x {
+                        // Built-ins apply to each entry of a vector or matrix.
+                        Number::Matrix(m) => {
+                            let data = m
+                                .data
+                                .iter()
+                                .map(|entry| {
+                                    match Compiler::call_builtin(
+                                        functions,
+                                        builtin,
+                                        Number::Float(*entry),
+                                    ) {
+                                        Some(Number::Float(x)) => Ok(x),
+                                        n => Err(Compiler::shape_error(
+                                            src,
+                                            format!("`{builtin}` of an entry is not a real number"),
+                                            vec![(
+                                                location,
+                                                match n {
+                                                    Some(n) => {
+                                                        format!("`{builtin}({entry})` is {n}")
+                                                    }
+                                                    None => {
+                                                        format!("`{builtin}({entry})` is undefined")
+                                                    }
+                                                },
+                                            )],
+                                            Some("Vectors and matrices only hold real numbers."),
+                                        )),
+                                    }
+                                })
+                                .collect::<Result<_, _>>()?;
+                            Number::Matrix(Matrix {
+                                shape: m.shape,
+                                data,
+                            })
                         }
-                        BinOp::Equal => unreachable!(),
+                        Number::Decimal(d) => Number::Decimal(d.call(builtin).map_err(|e| {
+                            Compiler::decimal_error(src, e, covering(call_location, location))
+                        })?),
+                        x => match Compiler::call_builtin(functions, builtin, x.clone()) {
+                            Some(value) => value,
+                            None => {
+                                return Err(Compiler::type_error(
+                                    src,
+                                    &format!("`{builtin}`"),
+                                    &[(&x, location)],
+                                ));
+                            }
+                        },
+                    };
+                    stack.push((value, covering(call_location, location)));
+                }
+                TokenType::LinAlg(linalg) => {
+                    let args = Compiler::pop_operands(src, &mut stack, linalg.arity(), location)?;
+                    stack.push((Compiler::eval_linalg(src, linalg, args)?, location));
+                }
+                TokenType::Statistic(statistic, arity) => {
+                    let args = Compiler::pop_operands(src, &mut stack, arity, location)?;
+                    let value = Compiler::eval_statistic(src, statistic, args)?;
+                    // The bins of a histogram are only printed when it is the whole expression.
+                    let value = if last { value } else { value.into_value() };
+                    stack.push((value, location));
+                }
+                TokenType::Array(len) => {
+                    let entries = Compiler::pop_operands(src, &mut stack, len, location)?;
+                    stack.push((Compiler::eval_array(src, entries)?, location));
+                }
+                TokenType::Call(ref name, arity) => {
+                    let args = Compiler::pop_operands(src, &mut stack, arity, location)?;
+                    let args = Compiler::real_args(src, name, args)?;
+                    let value = unsafe { (functions.user[name])(args.as_ptr()) };
+                    stack.push((Number::Float(value), location));
+                }
+                TokenType::Grad(ref name, arity) => {
+                    let args = Compiler::pop_operands(src, &mut stack, arity, location)?;
+                    let args = Compiler::real_args(src, name, args)?;
+                    let mut out = vec![0.; arity + 1];
+                    if !unsafe { (functions.duals[name])(args.as_ptr(), out.as_mut_ptr()) } {
+                        let args: Vec<String> = args.iter().map(f32::to_string).collect();
+                        return Err(Box::new(EvalError::from(CompileError {
+                            src: NamedSource::new("input", src.to_owned()),
+                            message: format!(
+                                "Bounds of a sum or product in `{name}` must be whole numbers"
+                            ),
+                            span: span(location),
+                            label: format!("called with {}", args.join(", ")),
+                            help: None,
+                        })));
                     }
+                    stack.push((
+                        Number::Gradient(Gradient {
+                            value: out[0],
+                            gradient: out[1..].to_vec(),
+                        }),
+                        location,
+                    ));
+                }
+                TokenType::Loop(reduction) => stack.push((
+                    Compiler::eval_loop(src, &reduction, symbol_table, functions)?,
+                    location,
+                )),
+                TokenType::Integral(integral) => {
+                    let estimate =
+                        Compiler::eval_integral(src, &integral, symbol_table, functions)?;
+                    // The error estimate is only kept when the integral is the whole expression.
+                    let value = if last {
+                        Number::Estimate(estimate)
+                    } else {
+                        Number::Float(estimate.value)
+                    };
+                    stack.push((value, location));
+                }
+                TokenType::Simplified(node) => {
+                    // The simplified form is printed when `simplify` is the whole expression, and evaluated otherwise.
+                    let value = if last {
+                        Number::Symbolic(*node)
+                    } else {
+                        Compiler::eval_rpn(src, &node.to_rpn(location), symbol_table, functions)?
+                    };
+                    stack.push((value, location));
+                }
+                TokenType::Equation(equation) => {
+                    let mut roots =
+                        Compiler::eval_equation(src, &equation, symbol_table, functions)?;
+                    // Every root is printed when `solve` is the whole expression, but inside one there must be a single root.
+                    let value = if last {
+                        Number::Roots(Roots {
+                            variable: equation.variable.clone(),
+                            roots,
+                        })
+                    } else if roots.len() == 1 {
+                        roots.remove(0)
+                    } else {
+                        return Err(Box::new(EvalError::from(CompileError {
+                            src: NamedSource::new("input", src.to_owned()),
+                            message: format!("`solve` found {} roots", roots.len()),
+                            span: SourceSpan::from(equation.location),
+                            label: "only one can be used in an expression".to_owned(),
+                            help: Some(
+                                "Pass a guess as the third argument to pick one root.".to_owned(),
+                            ),
+                        })));
+                    };
+                    stack.push((value, location));
+                }
+                TokenType::Convert(unit) => {
+                    let [(x, x_location)] = Compiler::operands(src, &mut stack, location)?;
+                    let Some(quantity) = x.clone().to_quantity() else {
+                        return Err(Compiler::type_error(
+                            src,
+                            "a conversion",
+                            &[(&x, x_location)],
+                        ));
+                    };
+                    stack.push((Number::Quantity(quantity.convert(unit)), None));
+                }
+                TokenType::BinOp(op) => {
+                    let [(a, x_location), (b, y_location)] =
+                        Compiler::operands(src, &mut stack, location)?;
+                    let location = covering(x_location, y_location);
+                    let value = match (a, b) {
+                        (a @ Number::Matrix(_), b) | (a, b @ Number::Matrix(_)) => {
+                            Compiler::apply_array_op(
+                                src,
+                                functions,
+                                op,
+                                (a, x_location),
+                                (b, y_location),
+                            )?
+                        }
+                        (a, b) if op == BinOp::MatMul => {
+                            return Err(Compiler::shape_error(
+                                src,
+                                "`@` multiplies vectors and matrices".to_owned(),
+                                vec![
+                                    (x_location, format!("this is {a}")),
+                                    (y_location, format!("this is {b}")),
+                                ],
+                                Some("Use `*` to multiply numbers."),
+                            ));
+                        }
+                        (Number::Decimal(a), Number::Decimal(b)) => Number::Decimal(
+                            Compiler::apply_decimal_op(functions, op, a, b)
+                                .map_err(|e| Compiler::decimal_error(src, e, location))?,
+                        ),
+                        (a, b) => Compiler::apply_bin_op(
+                            src,
+                            functions,
+                            op,
+                            (a, x_location),
+                            (b, y_location),
+                        )?,
+                    };
+                    stack.push((value, location));
                 }
                 _ => (),
             }
         }
 
-        assert!(
-            stack.len() == 1,
-            "Evaluator stack is not of length 1. Either all variables introduced were not used, or there exists an ill-formed expression."
-        );
-        match stack.first().unwrap() {
-            Token {
-                token_type: TokenType::Fp(n),
-                ..
-            } => return n.clone(),
-            _ => panic!("Error: After eval, last token is NOT a number."),
-        };
+        match <[_; 1]>::try_from(stack) {
+            Ok([(n, _)]) => Ok(n),
+            Err(_) => Err(Compiler::ill_formed(
+                src,
+                tokens
+                    .iter()
+                    .fold(None, |all, token| covering(all, token.location)),
+            )),
+        }
+    }
+
+    /// The operands of an operation at `location`, taken off the top of `stack`.
+    fn operands<const N: usize>(
+        src: &str,
+        stack: &mut Vec<(Number, Option<Location>)>,
+        location: Option<Location>,
+    ) -> Result<[(Number, Option<Location>); N], Box<EvalError>> {
+        let operands = Compiler::pop_operands(src, stack, N, location)?;
+        Ok(operands
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("`pop_operands` takes exactly N.")))
+    }
+
+    /// The last `n` values on `stack`, which an ill-formed expression does not have.
+    fn pop_operands(
+        src: &str,
+        stack: &mut Vec<(Number, Option<Location>)>,
+        n: usize,
+        location: Option<Location>,
+    ) -> Result<Vec<(Number, Option<Location>)>, Box<EvalError>> {
+        match stack.len().checked_sub(n) {
+            Some(at) => Ok(stack.split_off(at)),
+            None => Err(Compiler::ill_formed(src, location)),
+        }
+    }
+
+    /// The error for RPN whose operators do not add up to one value, which the parser should never produce.
+    fn ill_formed(src: &str, location: Option<Location>) -> Box<EvalError> {
+        Compiler::compile_error(
+            src,
+            "Ill-formed expression".to_owned(),
+            span(location),
+            "here".to_owned(),
+            Some("Every operator needs its operands, and every operand an operator."),
+        )
+    }
+
+    /// Arguments for a compiled user function, which only takes real numbers.
+    fn real_args(
+        src: &str,
+        name: &str,
+        args: Vec<(Number, Option<Location>)>,
+    ) -> Result<Vec<f32>, Box<EvalError>> {
+        args.into_iter()
+            .map(|(arg, location)| match arg {
+                Number::Float(x) => Ok(x),
+                n => Err(Box::new(EvalError::from(CompileError {
+                    src: NamedSource::new("input", src.to_owned()),
+                    message: format!("`{name}` takes real numbers"),
+                    span: span(location),
+                    label: format!("this is {n}"),
+                    help: Some("Functions are compiled to float code.".to_owned()),
+                }))),
+            })
+            .collect()
+    }
+
+    /// What to do about `id`, which nothing defines. Results are only numbered within one run or
+    /// session, so one from an earlier run needs a name of its own.
+    fn undefined_help(id: &str) -> String {
+        let numbered = id
+            .strip_prefix('_')
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        if id == LAST_RESULT || numbered {
+            format!(
+                "`{id}` is a result printed earlier in the same run or session, and `--state` only keeps definitions. Name a result to keep it, as in `r = 2`."
+            )
+        } else {
+            format!("Define it first, as in `{id} = 2`.")
+        }
+    }
+
+    /// An error about the part of `src` at `span`, which is labelled with `label`.
+    fn compile_error(
+        src: &str,
+        message: String,
+        span: impl Into<SourceSpan>,
+        label: String,
+        help: Option<&str>,
+    ) -> Box<EvalError> {
+        Box::new(EvalError::from(CompileError {
+            src: NamedSource::new("input", src.to_owned()),
+            message,
+            span: span.into(),
+            label,
+            help: help.map(str::to_owned),
+        }))
+    }
+
+    fn shape_error(
+        src: &str,
+        message: String,
+        labels: Vec<(Option<Location>, String)>,
+        help: Option<&str>,
+    ) -> Box<EvalError> {
+        Box::new(EvalError::from(ShapeError {
+            src: NamedSource::new("input", src.to_owned()),
+            message,
+            labels: labels
+                .into_iter()
+                .map(|(location, label)| LabeledSpan::new_with_span(Some(label), span(location)))
+                .collect(),
+            help: help.map(str::to_owned),
+        }))
+    }
+
+    /// A `[...]` literal: a vector of real numbers, or a matrix whose rows are vectors of the same length.
+    fn eval_array(
+        src: &str,
+        entries: Vec<(Number, Option<Location>)>,
+    ) -> Result<Number, Box<EvalError>> {
+        let (first, first_location) = entries[0].clone();
+        let mut data = vec![];
+        for (n, location) in &entries {
+            match (&first, n) {
+                (Number::Float(_), Number::Float(x)) => data.push(*x),
+                (Number::Matrix(row), Number::Matrix(m))
+                    if matches!(row.shape, Shape::Vector(_)) && m.shape == row.shape =>
+                {
+                    data.extend(&m.data)
+                }
+                (Number::Float(_) | Number::Matrix(_), Number::Matrix(m)) => {
+                    return Err(Compiler::shape_error(
+                        src,
+                        "The entries of a vector or matrix do not fit together".to_owned(),
+                        vec![
+                            (
+                                first_location,
+                                format!("this is {}", Compiler::describe(&first)),
+                            ),
+                            (*location, format!("this is {}", m.shape)),
+                        ],
+                        Some("A vector holds numbers, and a matrix holds rows of the same length."),
+                    ));
+                }
+                (_, n) => {
+                    return Err(Compiler::shape_error(
+                        src,
+                        "Vectors and matrices only hold real numbers".to_owned(),
+                        vec![(*location, format!("this is {n}"))],
+                        None,
+                    ));
+                }
+            }
+        }
+        Ok(Number::Matrix(match first {
+            Number::Matrix(row) => Matrix::new(entries.len(), row.cols(), data),
+            _ => Matrix::vector(data),
+        }))
+    }
+
+    /// The shape of a vector or matrix, and the value of anything else.
+    fn describe(n: &Number) -> String {
+        match n {
+            Number::Matrix(m) => m.shape.to_string(),
+            n => n.to_string(),
+        }
+    }
+
+    /// `@`, or an element-wise operation where at least one side is a vector or matrix.
+    fn apply_array_op(
+        src: &str,
+        functions: &JitFunctions,
+        op: BinOp,
+        (x, x_location): (Number, Option<Location>),
+        (y, y_location): (Number, Option<Location>),
+    ) -> Result<Number, Box<EvalError>> {
+        let mismatch = |message: String, help: &str| {
+            Compiler::shape_error(
+                src,
+                message,
+                vec![
+                    (x_location, format!("this is {}", Compiler::describe(&x))),
+                    (y_location, format!("this is {}", Compiler::describe(&y))),
+                ],
+                Some(help),
+            )
+        };
+        if op == BinOp::MatMul {
+            return match (&x, &y) {
+                (Number::Matrix(a), Number::Matrix(b)) => a.matmul(b).map_err(|_| {
+                    mismatch(
+                        format!("Cannot multiply {} by {}", a.shape, b.shape),
+                        "The left side needs as many columns as the right side has rows.",
+                    )
+                }),
+                _ => Err(mismatch(
+                    "`@` multiplies vectors and matrices".to_owned(),
+                    "Use `*` to scale a vector or matrix by a number.",
+                )),
+            };
+        }
+
+        let pairs = match (&x, &y) {
+            (Number::Matrix(a), Number::Matrix(b)) => a.broadcast(b).map_err(|_| {
+                mismatch(
+                    format!("Cannot apply `{op}` to {} and {}", a.shape, b.shape),
+                    "Element-wise operations need operands of the same shape, or a matrix and a vector with one entry per column.",
+                )
+            })?,
+            (Number::Matrix(a), Number::Float(b)) => {
+                (a.shape, a.data.iter().map(|entry| (*entry, *b)).collect())
+            }
+            (Number::Float(a), Number::Matrix(b)) => {
+                (b.shape, b.data.iter().map(|entry| (*a, *entry)).collect())
+            }
+            (Number::Matrix(_), n) | (n, _) => {
+                let location = if matches!(x, Number::Matrix(_)) {
+                    y_location
+                } else {
+                    x_location
+                };
+                return Err(Compiler::shape_error(
+                    src,
+                    "Vectors and matrices only combine with real numbers".to_owned(),
+                    vec![(location, format!("this is {n}"))],
+                    None,
+                ));
+            }
+        };
+        let (shape, pairs) = pairs;
+        let data = pairs
+            .into_iter()
+            .map(|(a, b)| {
+                match Compiler::apply_bin_op(
+                    src,
+                    functions,
+                    op.clone(),
+                    (Number::Float(a), x_location),
+                    (Number::Float(b), y_location),
+                )? {
+                    Number::Float(value) => Ok(value),
+                    n => Err(Compiler::shape_error(
+                        src,
+                        format!("`{a} {op} {b}` is not a real number"),
+                        vec![(x_location, format!("this entry gives {n}"))],
+                        Some("Vectors and matrices only hold real numbers."),
+                    )),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Number::Matrix(Matrix { shape, data }))
+    }
+
+    fn eval_linalg(
+        src: &str,
+        linalg: LinAlg,
+        args: Vec<(Number, Option<Location>)>,
+    ) -> Result<Number, Box<EvalError>> {
+        let args = args
+            .into_iter()
+            .map(|(arg, location)| match arg {
+                Number::Matrix(m) => Ok((m, location)),
+                n => Err(Compiler::shape_error(
+                    src,
+                    format!("`{linalg}` takes vectors and matrices"),
+                    vec![(location, format!("this is {n}"))],
+                    None,
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mismatch = |message: &str| {
+            Compiler::shape_error(
+                src,
+                format!("`{linalg}` needs {message}"),
+                args.iter()
+                    .map(|(m, location)| (*location, format!("this is {}", m.shape)))
+                    .collect(),
+                None,
+            )
+        };
+        let value = match (linalg, args.as_slice()) {
+            (LinAlg::Dot, [(a, _), (b, _)]) => a
+                .dot(b)
+                .map(Number::Float)
+                .map_err(|_| mismatch("two vectors of the same length"))?,
+            (LinAlg::Cross, [(a, _), (b, _)]) => a
+                .cross(b)
+                .map(Number::Matrix)
+                .map_err(|_| mismatch("two vectors of length 3"))?,
+            (LinAlg::Transpose, [(m, _)]) => Number::Matrix(m.transpose()),
+            (LinAlg::Det, [(m, _)]) => m
+                .det()
+                .map(Number::Float)
+                .map_err(|_| mismatch("a square matrix"))?,
+            (LinAlg::Inv, [(m, m_location)]) => match m.inv() {
+                Ok(inverse) => Number::Matrix(inverse),
+                Err(MatrixError::Shape) => return Err(mismatch("a square matrix")),
+                Err(MatrixError::Singular) => {
+                    return Err(Compiler::shape_error(
+                        src,
+                        "The matrix has no inverse".to_owned(),
+                        vec![(*m_location, "its determinant is 0".to_owned())],
+                        None,
+                    ));
+                }
+            },
+            _ => unreachable!("Arity is checked by the parser."),
+        };
+        Ok(value)
+    }
+
+    /// A statistic of the entries of a vector, which is always the first argument.
+    fn eval_statistic(
+        src: &str,
+        statistic: Statistic,
+        args: Vec<(Number, Option<Location>)>,
+    ) -> Result<Number, Box<EvalError>> {
+        let mut args = args.into_iter();
+        let (list, list_location) = args.next().expect("Arity is checked by the parser.");
+        let values = match list {
+            Number::Matrix(Matrix {
+                shape: Shape::Vector(_),
+                data,
+            }) => data,
+            n => {
+                return Err(Compiler::shape_error(
+                    src,
+                    format!("`{statistic}` takes a vector"),
+                    vec![(list_location, format!("this is {}", Compiler::describe(&n)))],
+                    Some(&format!(
+                        "Write the values as a vector, as in `{statistic}([1, 2, 3])`."
+                    )),
+                ));
+            }
+        };
+        let parameter = match args.next() {
+            Some((Number::Float(x), location)) => Some((x, location)),
+            Some((n, location)) => {
+                return Err(Compiler::compile_error(
+                    src,
+                    format!("The second argument of `{statistic}` must be a real number"),
+                    span(location),
+                    format!("this is {n}"),
+                    None,
+                ));
+            }
+            None => None,
+        };
+        let failed = |e: StatsError| match e {
+            StatsError::TooFew(needed) => Compiler::compile_error(
+                src,
+                match needed {
+                    1 => format!("`{statistic}` needs at least one value"),
+                    needed => format!("`{statistic}` needs at least {needed} values"),
+                },
+                span(list_location),
+                format!("this has {}", values.len()),
+                None,
+            ),
+            StatsError::OutOfRange => {
+                let (x, location) = parameter.expect("Only the parameter can be out of range.");
+                let message = match statistic {
+                    Statistic::Histogram => "The number of bins must be a positive whole number",
+                    _ => "Percentiles are between 0 and 100",
+                };
+                Compiler::compile_error(
+                    src,
+                    message.to_owned(),
+                    span(location),
+                    format!("this is {x}"),
+                    None,
+                )
+            }
+        };
+
+        let value = match statistic {
+            Statistic::Mean => stats::mean(&values).map_err(failed)?,
+            Statistic::Median => stats::median(&values).map_err(failed)?,
+            Statistic::Mode => stats::mode(&values).map_err(failed)?,
+            Statistic::Variance | Statistic::PVariance | Statistic::Stddev | Statistic::PStddev => {
+                let population = matches!(statistic, Statistic::PVariance | Statistic::PStddev);
+                let variance = stats::variance(&values, population).map_err(failed)?;
+                match statistic {
+                    Statistic::Stddev | Statistic::PStddev => variance.sqrt(),
+                    _ => variance,
+                }
+            }
+            Statistic::Percentile => {
+                let (p, _) = parameter.expect("Arity is checked by the parser.");
+                stats::percentile(&values, p).map_err(failed)?
+            }
+            Statistic::Min => stats::min(&values).map_err(failed)?,
+            Statistic::Max => stats::max(&values).map_err(failed)?,
+            Statistic::Sum => stats::sum(&values),
+            Statistic::Count => values.len() as f32,
+            Statistic::Histogram => {
+                let histogram =
+                    Histogram::new(&values, parameter.map(|(bins, _)| bins)).map_err(failed)?;
+                return Ok(Number::Histogram(histogram));
+            }
+        };
+        Ok(Number::Float(value))
+    }
+
+    /// Arithmetic on real and complex numbers and quantities. Dimensions were already checked by
+    /// `units::check_dimensions`, so only the values of quantities need computing.
+    fn apply_bin_op(
+        src: &str,
+        functions: &JitFunctions,
+        op: BinOp,
+        (x, x_location): (Number, Option<Location>),
+        (y, y_location): (Number, Option<Location>),
+    ) -> Result<Number, Box<EvalError>> {
+        let index = match op {
+            BinOp::Plus => 0,
+            BinOp::Minus => 1,
+            BinOp::Times => 2,
+            BinOp::Divide => 3,
+            BinOp::Power => 4,
+            BinOp::Equal | BinOp::MatMul => unreachable!(),
+        };
+        let value = match (&x, &y) {
+            (Number::Float(a), Number::Float(b))
+                if op != BinOp::Power || *a >= 0. || b.fract() == 0. =>
+            {
+                Number::Float(unsafe { (functions.float[index])(*a, *b) })
+            }
+            // A negative base with a fractional exponent only has complex roots.
+            (Number::Float(a), Number::Float(b)) => {
+                Number::Complex(Complex::new(*a, 0.).pow(Complex::new(*b, 0.)))
+            }
+            (Number::Quantity(a), Number::Float(b)) if op == BinOp::Power => {
+                let Some(dimension) = a.dimension.powf(*b) else {
+                    return Err(Box::new(EvalError::from(CompileError {
+                        src: NamedSource::new("input", src.to_owned()),
+                        message: format!("Cannot raise a value measured in {} to the power {b}", a.dimension),
+                        span: span(covering(x_location, y_location)),
+                        label: "this power".to_owned(),
+                        help: Some("Units can only be raised to powers that leave whole powers of each unit.".to_owned()),
+                    })));
+                };
+                Quantity::new(a.value.powf(*b), dimension).into_number()
+            }
+            (Number::Quantity(a), Number::Float(b)) => {
+                let b = Quantity::new(*b, units::Dimension::NONE);
+                Compiler::apply_quantity_op(functions, op, index, a, &b)
+            }
+            (Number::Float(a), Number::Quantity(b)) if op != BinOp::Power => {
+                let a = Quantity::new(*a, units::Dimension::NONE);
+                Compiler::apply_quantity_op(functions, op, index, &a, b)
+            }
+            (Number::Quantity(a), Number::Quantity(b)) if op != BinOp::Power => {
+                Compiler::apply_quantity_op(functions, op, index, a, b)
+            }
+            _ if let (Some(a), Some(b)) = (x.clone().to_complex(), y.clone().to_complex()) => {
+                if op == BinOp::Power {
+                    Number::Complex(a.pow(b))
+                } else {
+                    let mut result = Complex::default();
+                    unsafe { (functions.complex[index])(a.re, a.im, b.re, b.im, &mut result) };
+                    Number::Complex(result)
+                }
+            }
+            _ => {
+                return Err(Compiler::type_error(
+                    src,
+                    &format!("`{op}`"),
+                    &[(&x, x_location), (&y, y_location)],
+                ));
+            }
+        };
+        Ok(value)
+    }
+
+    /// `+`, `-`, `*` or `/` of two quantities, whose dimensions were already checked.
+    fn apply_quantity_op(
+        functions: &JitFunctions,
+        op: BinOp,
+        index: usize,
+        a: &Quantity,
+        b: &Quantity,
+    ) -> Number {
+        let value = unsafe { (functions.float[index])(a.value, b.value) };
+        let dimension = match op {
+            BinOp::Times => a.dimension * b.dimension,
+            BinOp::Divide => a.dimension / b.dimension,
+            _ => a.dimension,
+        };
+        // A converted operand keeps its unit through sums and scaling, as in `(5 km in mi) * 2`.
+        let display_unit = [a, b]
+            .into_iter()
+            .filter_map(|q| q.display_unit.clone())
+            .find(|unit| unit.dimension == dimension);
+        Quantity {
+            value,
+            dimension,
+            display_unit,
+        }
+        .into_number()
+    }
+
+    /// The error for operands that `operation` does not apply to. Results that are only printed,
+    /// such as a gradient, the roots `solve` found or a simplified expression, say so.
+    fn type_error(
+        src: &str,
+        operation: &str,
+        operands: &[(&Number, Option<Location>)],
+    ) -> Box<EvalError> {
+        let printed = operands.iter().find_map(|(n, _)| match n {
+            Number::Gradient(_) => Some((
+                "The result of `grad` cannot be used in arithmetic",
+                "A gradient is only printed. Call the function itself for its value.",
+            )),
+            Number::Roots(_) => Some((
+                "`solve` returned several roots",
+                "Pass a guess as the third argument to pick one root.",
+            )),
+            Number::Symbolic(_) => Some((
+                "The result of `simplify` cannot be used in arithmetic",
+                "A simplified expression is only printed. Use the expression itself for its value.",
+            )),
+            _ => None,
+        });
+        let (message, help) = match printed {
+            Some((message, help)) => (message.to_owned(), Some(help.to_owned())),
+            None => {
+                let kinds: Vec<&str> = operands.iter().map(|(n, _)| n.kind()).collect();
+                (
+                    format!("Cannot apply {operation} to {}", kinds.join(" and ")),
+                    None,
+                )
+            }
+        };
+        Box::new(EvalError::from(TypeError {
+            src: NamedSource::new("input", src.to_owned()),
+            message,
+            labels: operands
+                .iter()
+                .map(|(n, location)| {
+                    LabeledSpan::new_with_span(
+                        Some(format!("this is {}", Compiler::describe(n))),
+                        span(*location),
+                    )
+                })
+                .collect(),
+            help,
+        }))
+    }
+
+    /// Decimal arithmetic, which reports a result that does not fit rather than wrapping it.
+    fn apply_decimal_op(
+        functions: &JitFunctions,
+        op: BinOp,
+        x: Decimal,
+        y: Decimal,
+    ) -> Result<Decimal, DecimalError> {
+        let index = match op {
+            BinOp::Plus => 0,
+            BinOp::Minus => 1,
+            BinOp::Times => 2,
+            BinOp::Divide => 3,
+            BinOp::Power => return x.checked_powi(y),
+            BinOp::Equal | BinOp::MatMul => unreachable!(),
+        };
+        // Integer division by zero is undefined behaviour in LLVM, so it is caught here.
+        if op == BinOp::Divide && y.is_zero() {
+            return Err(DecimalError::DivisionByZero);
+        }
+        let f = &functions
+            .decimal
+            .as_ref()
+            .expect("Decimal functions were not compiled.")[index];
+        let mut units = 0;
+        if unsafe { f(x.units(), y.units(), &mut units) } {
+            return Err(DecimalError::Overflow);
+        }
+        Ok(Decimal::from_units(units, x.config()))
+    }
+
+    fn decimal_error(src: &str, error: DecimalError, location: Option<Location>) -> Box<EvalError> {
+        let (label, help) = match error {
+            DecimalError::Overflow => (
+                "this does not fit",
+                Some("Decimals hold 18 digits, counting those after the point."),
+            ),
+            DecimalError::DivisionByZero => ("this divides by zero", None),
+            DecimalError::FractionalExponent => ("this power is not whole", None),
+            DecimalError::NotFinite => ("this is not finite", None),
+            DecimalError::Unsupported(_) => (
+                "called here",
+                Some("Decimal mode only has `abs`, besides `+`, `-`, `*`, `/` and `^`."),
+            ),
+        };
+        Box::new(EvalError::from(CompileError {
+            src: NamedSource::new("input", src.to_owned()),
+            message: error.to_string(),
+            span: span(location),
+            label: label.to_owned(),
+            help: help.map(str::to_owned),
+        }))
+    }
+
+    /// `builtin` of `x`, or `None` when it does not apply to `x`.
+    fn call_builtin(functions: &JitFunctions, builtin: Builtin, x: Number) -> Option<Number> {
+        match x {
+            // Negative arguments leave the reals, so those fall through to `Number::call`.
+            Number::Float(a) if a >= 0. || !matches!(builtin, Builtin::Sqrt | Builtin::Ln) => {
+                match FLOAT_BUILTINS.iter().position(|(b, _)| *b == builtin) {
+                    Some(index) => Some(Number::Float(unsafe { (functions.builtins[index])(a) })),
+                    None => x.call(builtin),
+                }
+            }
+            _ => x.call(builtin),
+        }
+    }
+}
+
+/// The diagnostic for an error the parser found in `src`.
+fn syntax_error(src: &str, e: nom::Err<ParseError>) -> miette::Report {
+    match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e.report(src).into(),
+        nom::Err::Incomplete(_) => miette!("The program is incomplete."),
     }
 }
 
@@ -155,12 +2063,15 @@ pub struct CodeGen<'ctx> {
     pub builder: Builder<'ctx>,
 }
 
-impl CodeGen<'_> {
+impl<'ctx> CodeGen<'ctx> {
     pub fn compile_all_fns(&self) {
         self.compile_sum();
         self.compile_sub();
         self.compile_mul();
         self.compile_div();
+        self.compile_pow();
+        self.compile_builtins();
+        self.compile_complex_fns();
     }
 
     pub fn compile_sum(&self) {
@@ -238,4 +2149,1251 @@ impl CodeGen<'_> {
 
         self.builder.build_return(Some(&div)).unwrap();
     }
+
+    /// Fixed-point counterparts of the float functions, matching `DecimalFunction`. Values are
+    /// `i64` units of `10^-scale`.
+    pub fn compile_decimal_fns(&self, scale: u32, rounding: RoundingMode) {
+        let i8_type = self.context.i8_type();
+        let i64_type = self.context.i64_type();
+        let i128_type = self.context.i128_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = i8_type.fn_type(&[i64_type.into(), i64_type.into(), ptr_type.into()], false);
+        let one = i128_type.const_int(pow10(scale) as u64, false);
+
+        for name in ["dec_add", "dec_sub", "dec_mul", "dec_div"] {
+            let function = self.module.add_function(name, fn_type, None);
+            let basic_block = self
+                .context
+                .append_basic_block(function, &format!("{name}_entry"));
+
+            self.builder.position_at_end(basic_block);
+
+            let operand_1 = function.get_nth_param(0).unwrap().into_int_value();
+            let operand_2 = function.get_nth_param(1).unwrap().into_int_value();
+            let out = function.get_nth_param(2).unwrap().into_pointer_value();
+
+            let (result, overflow) = match name {
+                "dec_add" | "dec_sub" => {
+                    let intrinsic = match name {
+                        "dec_add" => "llvm.sadd.with.overflow",
+                        _ => "llvm.ssub.with.overflow",
+                    };
+                    let declaration = Intrinsic::find(intrinsic)
+                        .unwrap()
+                        .get_declaration(&self.module, &[i64_type.into()])
+                        .unwrap();
+                    let pair = self
+                        .builder
+                        .build_call(declaration, &[operand_1.into(), operand_2.into()], name)
+                        .unwrap()
+                        .try_as_basic_value()
+                        .left()
+                        .unwrap()
+                        .into_struct_value();
+                    let result = self.builder.build_extract_value(pair, 0, name).unwrap();
+                    let overflow = self
+                        .builder
+                        .build_extract_value(pair, 1, "overflow")
+                        .unwrap();
+                    (result.into_int_value(), overflow.into_int_value())
+                }
+                _ => {
+                    // Widened so the intermediate product does not overflow before rounding.
+                    let x = self
+                        .builder
+                        .build_int_s_extend(operand_1, i128_type, "x")
+                        .unwrap();
+                    let y = self
+                        .builder
+                        .build_int_s_extend(operand_2, i128_type, "y")
+                        .unwrap();
+                    let wide = if name == "dec_mul" {
+                        let product = self.builder.build_int_mul(x, y, "product").unwrap();
+                        self.build_round_div(product, one, rounding)
+                    } else {
+                        let dividend = self.builder.build_int_mul(x, one, "dividend").unwrap();
+                        self.build_round_div(dividend, y, rounding)
+                    };
+                    // The result only fits if it is still in range of an `i64` after rounding.
+                    let min = i128_type.const_int(i64::MIN as u64, true);
+                    let max = i128_type.const_int(i64::MAX as u64, false);
+                    let below = self
+                        .builder
+                        .build_int_compare(IntPredicate::SLT, wide, min, "below")
+                        .unwrap();
+                    let above = self
+                        .builder
+                        .build_int_compare(IntPredicate::SGT, wide, max, "above")
+                        .unwrap();
+                    let overflow = self.builder.build_or(below, above, "overflow").unwrap();
+                    let result = self
+                        .builder
+                        .build_int_truncate(wide, i64_type, name)
+                        .unwrap();
+                    (result, overflow)
+                }
+            };
+
+            self.builder.build_store(out, result).unwrap();
+            let overflow = self
+                .builder
+                .build_int_z_extend(overflow, i8_type, "overflow")
+                .unwrap();
+            self.builder.build_return(Some(&overflow)).unwrap();
+        }
+    }
+
+    /// IR version of `number::round_div`.
+    fn build_round_div(
+        &self,
+        n: IntValue<'ctx>,
+        d: IntValue<'ctx>,
+        rounding: RoundingMode,
+    ) -> IntValue<'ctx> {
+        let int_type = n.get_type();
+        let zero = int_type.const_zero();
+        let one = int_type.const_int(1, false);
+        let b = &self.builder;
+
+        let q = b.build_int_signed_div(n, d, "q").unwrap();
+        if rounding == RoundingMode::Truncate {
+            return q;
+        }
+        let r = b.build_int_signed_rem(n, d, "r").unwrap();
+
+        let abs = |v: IntValue<'ctx>, name: &str| {
+            let is_neg = b
+                .build_int_compare(IntPredicate::SLT, v, zero, "is_neg")
+                .unwrap();
+            let neg = b.build_int_neg(v, "neg").unwrap();
+            b.build_select(is_neg, neg, v, name)
+                .unwrap()
+                .into_int_value()
+        };
+        let abs_r = abs(r, "abs_r");
+        let abs_d = abs(d, "abs_d");
+        let twice_r = b.build_int_add(abs_r, abs_r, "twice_r").unwrap();
+
+        let bump = match rounding {
+            RoundingMode::HalfUp => b
+                .build_int_compare(IntPredicate::SGE, twice_r, abs_d, "bump")
+                .unwrap(),
+            RoundingMode::HalfEven => {
+                let above = b
+                    .build_int_compare(IntPredicate::SGT, twice_r, abs_d, "above")
+                    .unwrap();
+                let halfway = b
+                    .build_int_compare(IntPredicate::EQ, twice_r, abs_d, "halfway")
+                    .unwrap();
+                let q_bit = b.build_and(q, one, "q_bit").unwrap();
+                let odd = b
+                    .build_int_compare(IntPredicate::NE, q_bit, zero, "odd")
+                    .unwrap();
+                let tie = b.build_and(halfway, odd, "tie").unwrap();
+                b.build_or(above, tie, "bump").unwrap()
+            }
+            RoundingMode::Truncate => unreachable!(),
+        };
+
+        let n_neg = b
+            .build_int_compare(IntPredicate::SLT, n, zero, "n_neg")
+            .unwrap();
+        let d_neg = b
+            .build_int_compare(IntPredicate::SLT, d, zero, "d_neg")
+            .unwrap();
+        let negative = b.build_xor(n_neg, d_neg, "negative").unwrap();
+        let down = b.build_int_sub(q, one, "down").unwrap();
+        let up = b.build_int_add(q, one, "up").unwrap();
+        let rounded = b
+            .build_select(negative, down, up, "rounded")
+            .unwrap()
+            .into_int_value();
+
+        b.build_select(bump, rounded, q, "round_div")
+            .unwrap()
+            .into_int_value()
+    }
+
+    fn compile_pow(&self) {
+        let f32_type = self.context.f32_type();
+        let fn_type = f32_type.fn_type(&[f32_type.into(), f32_type.into()], false);
+        let function = self.module.add_function("pow", fn_type, None);
+        let basic_block = self.context.append_basic_block(function, "pow_entry");
+
+        self.builder.position_at_end(basic_block);
+
+        let operand_1 = function.get_nth_param(0).unwrap().into_float_value();
+        let operand_2 = function.get_nth_param(1).unwrap().into_float_value();
+
+        let pow = self.build_intrinsic_call("llvm.pow", &[operand_1, operand_2]);
+
+        self.builder.build_return(Some(&pow)).unwrap();
+    }
+
+    /// One `f32 -> f32` function per entry of `FLOAT_BUILTINS`, named after the built-in.
+    fn compile_builtins(&self) {
+        let f32_type = self.context.f32_type();
+        let fn_type = f32_type.fn_type(&[f32_type.into()], false);
+
+        for (builtin, intrinsic) in FLOAT_BUILTINS {
+            let name = builtin.to_string();
+            let function = self.module.add_function(&name, fn_type, None);
+            let basic_block = self
+                .context
+                .append_basic_block(function, &format!("{name}_entry"));
+
+            self.builder.position_at_end(basic_block);
+
+            let operand = function.get_nth_param(0).unwrap().into_float_value();
+            let result = self.build_intrinsic_call(intrinsic, &[operand]);
+
+            self.builder.build_return(Some(&result)).unwrap();
+        }
+    }
+
+    pub(crate) fn build_intrinsic_call(
+        &self,
+        name: &str,
+        args: &[FloatValue<'ctx>],
+    ) -> FloatValue<'ctx> {
+        let f32_type = self.context.f32_type();
+        let intrinsic = Intrinsic::find(name).unwrap();
+        let declaration = intrinsic
+            .get_declaration(&self.module, &[f32_type.into()])
+            .unwrap();
+        let args: Vec<_> = args.iter().map(|&arg| arg.into()).collect();
+
+        self.builder
+            .build_call(declaration, &args, name)
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_float_value()
+    }
+
+    /// Complex arithmetic on `(re, im)` pairs, matching `ComplexFunction`.
+    fn compile_complex_fns(&self) {
+        let f32_type = self.context.f32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = self.context.void_type().fn_type(
+            &[
+                f32_type.into(),
+                f32_type.into(),
+                f32_type.into(),
+                f32_type.into(),
+                ptr_type.into(),
+            ],
+            false,
+        );
+
+        for name in ["c_sum", "c_sub", "c_mul", "c_div"] {
+            let function = self.module.add_function(name, fn_type, None);
+            let basic_block = self
+                .context
+                .append_basic_block(function, &format!("{name}_entry"));
+
+            self.builder.position_at_end(basic_block);
+
+            let param = |n| function.get_nth_param(n).unwrap().into_float_value();
+            let (a, b, c, d) = (param(0), param(1), param(2), param(3));
+            let out = function.get_nth_param(4).unwrap().into_pointer_value();
+            let b_ = &self.builder;
+
+            let (re, im) = match name {
+                "c_sum" => (
+                    b_.build_float_add(a, c, "re").unwrap(),
+                    b_.build_float_add(b, d, "im").unwrap(),
+                ),
+                "c_sub" => (
+                    b_.build_float_sub(a, c, "re").unwrap(),
+                    b_.build_float_sub(b, d, "im").unwrap(),
+                ),
+                _ => {
+                    // (a + bi)(c + di) = (ac - bd) + (ad + bc)i
+                    let ac = b_.build_float_mul(a, c, "ac").unwrap();
+                    let bd = b_.build_float_mul(b, d, "bd").unwrap();
+                    let ad = b_.build_float_mul(a, d, "ad").unwrap();
+                    let bc = b_.build_float_mul(b, c, "bc").unwrap();
+                    if name == "c_mul" {
+                        (
+                            b_.build_float_sub(ac, bd, "re").unwrap(),
+                            b_.build_float_add(ad, bc, "im").unwrap(),
+                        )
+                    } else {
+                        // Dividing multiplies by the conjugate of the divisor over its squared norm.
+                        let cc = b_.build_float_mul(c, c, "cc").unwrap();
+                        let dd = b_.build_float_mul(d, d, "dd").unwrap();
+                        let norm = b_.build_float_add(cc, dd, "norm").unwrap();
+                        let re = b_.build_float_add(ac, bd, "re_num").unwrap();
+                        let im = b_.build_float_sub(bc, ad, "im_num").unwrap();
+                        (
+                            b_.build_float_div(re, norm, "re").unwrap(),
+                            b_.build_float_div(im, norm, "im").unwrap(),
+                        )
+                    }
+                }
+            };
+
+            let im_ptr = unsafe {
+                b_.build_gep(
+                    f32_type,
+                    out,
+                    &[self.context.i32_type().const_int(1, false)],
+                    "im_ptr",
+                )
+                .unwrap()
+            };
+            b_.build_store(out, re).unwrap();
+            b_.build_store(im_ptr, im).unwrap();
+            b_.build_return(None).unwrap();
+        }
+    }
+
+    /// Compiles a `sum`/`prod` into `loop_<location>`, matching `LoopFunction`.
+    pub fn compile_loop(&self, src: &str, reduction: &Loop) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = f32_type.fn_type(&[f32_type.into(), f32_type.into(), ptr_type.into()], false);
+        let function =
+            self.module
+                .add_function(&format!("loop_{}", reduction.location), fn_type, None);
+        let basic_block = self.context.append_basic_block(function, "loop_entry");
+
+        self.builder.position_at_end(basic_block);
+
+        let from = function.get_nth_param(0).unwrap().into_float_value();
+        let to = function.get_nth_param(1).unwrap().into_float_value();
+        let captured = function.get_nth_param(2).unwrap().into_pointer_value();
+        let mut scope = HashMap::new();
+        for (n, id) in reduction.captures().into_iter().enumerate() {
+            let value = self.build_load_nth(captured, n, &id);
+            scope.insert(id, value);
+        }
+
+        let result = self.build_loop(src, function, reduction, from, to, &mut scope)?;
+        self.builder.build_return(Some(&result)).unwrap();
+        Ok(())
+    }
+
+    /// Compiles the body of an `integrate` into `integrand_<location>`, matching `KernelFunction`.
+    pub fn compile_integrand(
+        &self,
+        src: &str,
+        integral: &Integral,
+    ) -> Result<(), Box<CompileError>> {
+        let name = format!("integrand_{}", integral.location);
+        let captures = integral.captures();
+        self.compile_kernel(src, &name, &integral.variable, &captures, &integral.body)
+    }
+
+    /// Compiles the body of a `solve` into `solve_<location>`, and its derivatives into
+    /// `solve_<location>_d1` and `solve_<location>_d2`, all matching `KernelFunction`.
+    pub fn compile_equation(
+        &self,
+        src: &str,
+        equation: &Equation,
+    ) -> Result<(), Box<CompileError>> {
+        let name = format!("solve_{}", equation.location);
+        let captures = equation.captures();
+        self.compile_kernel(src, &name, &equation.variable, &captures, &equation.body)?;
+        if let Some([first, second]) = &equation.derivatives {
+            self.compile_kernel(
+                src,
+                &format!("{name}_d1"),
+                &equation.variable,
+                &captures,
+                first,
+            )?;
+            self.compile_kernel(
+                src,
+                &format!("{name}_d2"),
+                &equation.variable,
+                &captures,
+                second,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn compile_kernel(
+        &self,
+        src: &str,
+        name: &str,
+        variable: &str,
+        captures: &[String],
+        body: &[Token],
+    ) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = f32_type.fn_type(&[f32_type.into(), ptr_type.into()], false);
+        let function = self.module.add_function(name, fn_type, None);
+        let basic_block = self
+            .context
+            .append_basic_block(function, &format!("{name}_entry"));
+
+        self.builder.position_at_end(basic_block);
+
+        let x = function.get_nth_param(0).unwrap().into_float_value();
+        let captured = function.get_nth_param(1).unwrap().into_pointer_value();
+        let mut scope = HashMap::new();
+        for (n, id) in captures.iter().enumerate() {
+            let value = self.build_load_nth(captured, n, id);
+            scope.insert(id.clone(), value);
+        }
+        scope.insert(variable.to_owned(), x);
+
+        let result = self.build_rpn(src, function, body, &mut scope)?;
+        self.builder.build_return(Some(&result)).unwrap();
+        Ok(())
+    }
+
+    /// Compiles a user function into `fn_<name>`, matching `CompiledFunction`.
+    pub fn compile_function(
+        &self,
+        src: &str,
+        function: &parse::Function,
+    ) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = f32_type.fn_type(&[ptr_type.into()], false);
+        let compiled = self
+            .module
+            .add_function(&format!("fn_{}", function.name), fn_type, None);
+        let basic_block = self
+            .context
+            .append_basic_block(compiled, &format!("fn_{}_entry", function.name));
+
+        self.builder.position_at_end(basic_block);
+
+        let args = compiled.get_nth_param(0).unwrap().into_pointer_value();
+        let mut scope = HashMap::new();
+        for (n, param) in function.params.iter().enumerate() {
+            let value = self.build_load_nth(args, n, param);
+            scope.insert(param.clone(), value);
+        }
+
+        let result = self.build_rpn(src, compiled, &function.body, &mut scope)?;
+        self.builder.build_return(Some(&result)).unwrap();
+        Ok(())
+    }
+
+    /// Compiles an expression into `batch`, matching `BatchFunction`: a loop that reads the parameters
+    /// from their arrays, evaluates the expression in `f32` and writes each result to the output.
+    /// The body is emitted inline, so that once optimized the loop can be vectorized.
+    pub fn compile_batch(
+        &self,
+        src: &str,
+        params: &[String],
+        captures: &[String],
+        body: &[Token],
+    ) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let f64_type = self.context.f64_type();
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = self.context.void_type().fn_type(
+            &[
+                ptr_type.into(),
+                ptr_type.into(),
+                ptr_type.into(),
+                i64_type.into(),
+            ],
+            false,
+        );
+        let function = self.module.add_function(BATCH_FUNCTION, fn_type, None);
+        // Code for the host CPU, rather than a generic one, can use its widest vector registers.
+        for (key, value) in [
+            ("target-cpu", TargetMachine::get_host_cpu_name()),
+            ("target-features", TargetMachine::get_host_cpu_features()),
+        ] {
+            let attribute = self
+                .context
+                .create_string_attribute(key, value.to_str().unwrap_or_default());
+            function.add_attribute(AttributeLoc::Function, attribute);
+        }
+        let b = &self.builder;
+        let entry = self.context.append_basic_block(function, "batch_entry");
+        let header = self.context.append_basic_block(function, "batch_header");
+        let body_block = self.context.append_basic_block(function, "batch_body");
+        let exit = self.context.append_basic_block(function, "batch_exit");
+
+        b.position_at_end(entry);
+        let inputs = function.get_nth_param(0).unwrap().into_pointer_value();
+        let captured = function.get_nth_param(1).unwrap().into_pointer_value();
+        let out = function.get_nth_param(2).unwrap().into_pointer_value();
+        let len = function.get_nth_param(3).unwrap().into_int_value();
+        let mut scope = HashMap::new();
+        for (n, id) in captures.iter().enumerate() {
+            let value = self.build_load_nth(captured, n, id);
+            scope.insert(id.clone(), value);
+        }
+        let arrays: Vec<PointerValue<'ctx>> = params
+            .iter()
+            .enumerate()
+            .map(|(n, param)| {
+                let slot = unsafe {
+                    b.build_gep(
+                        ptr_type,
+                        inputs,
+                        &[i64_type.const_int(n as u64, false)],
+                        &format!("{param}_array_ptr"),
+                    )
+                    .unwrap()
+                };
+                b.build_load(ptr_type, slot, &format!("{param}_array"))
+                    .unwrap()
+                    .into_pointer_value()
+            })
+            .collect();
+        b.build_unconditional_branch(header).unwrap();
+
+        b.position_at_end(header);
+        let index = b.build_phi(i64_type, "index").unwrap();
+        let index_value = index.as_basic_value().into_int_value();
+        let in_range = b
+            .build_int_compare(IntPredicate::ULT, index_value, len, "in_range")
+            .unwrap();
+        b.build_conditional_branch(in_range, body_block, exit)
+            .unwrap();
+
+        b.position_at_end(body_block);
+        let entry_ptr = |array: PointerValue<'ctx>, name: &str| unsafe {
+            b.build_gep(f64_type, array, &[index_value], &format!("{name}_ptr"))
+                .unwrap()
+        };
+        for (param, array) in params.iter().zip(&arrays) {
+            let value = b
+                .build_load(f64_type, entry_ptr(*array, param), param)
+                .unwrap()
+                .into_float_value();
+            let value = b.build_float_trunc(value, f32_type, param).unwrap();
+            scope.insert(param.clone(), value);
+        }
+        let result = self.build_rpn(src, function, body, &mut scope)?;
+        let result = b.build_float_ext(result, f64_type, "result").unwrap();
+        b.build_store(entry_ptr(out, "out"), result).unwrap();
+        let next_index = b
+            .build_int_add(index_value, i64_type.const_int(1, false), "next_index")
+            .unwrap();
+        // The body may have ended in a loop's exit block rather than `body_block`.
+        let latch = b.get_insert_block().unwrap();
+        b.build_unconditional_branch(header).unwrap();
+        index.add_incoming(&[(&i64_type.const_zero(), entry), (&next_index, latch)]);
+
+        b.position_at_end(exit);
+        b.build_return(None).unwrap();
+        Ok(())
+    }
+
+    /// Compiles an expression into `scalar`, matching `ScalarFunction`.
+    pub fn compile_scalar(
+        &self,
+        src: &str,
+        params: &[String],
+        captures: &[String],
+        body: &[Token],
+    ) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let f64_type = self.context.f64_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = f64_type.fn_type(&[ptr_type.into(), ptr_type.into()], false);
+        let function = self.module.add_function(SCALAR_FUNCTION, fn_type, None);
+        let basic_block = self.context.append_basic_block(function, "scalar_entry");
+
+        self.builder.position_at_end(basic_block);
+
+        let args = function.get_nth_param(0).unwrap().into_pointer_value();
+        let captured = function.get_nth_param(1).unwrap().into_pointer_value();
+        let mut scope = HashMap::new();
+        for (n, id) in captures.iter().enumerate() {
+            let value = self.build_load_nth(captured, n, id);
+            scope.insert(id.clone(), value);
+        }
+        for (n, param) in params.iter().enumerate() {
+            let slot = unsafe {
+                self.builder
+                    .build_gep(
+                        f64_type,
+                        args,
+                        &[self.context.i64_type().const_int(n as u64, false)],
+                        &format!("{param}_ptr"),
+                    )
+                    .unwrap()
+            };
+            let value = self
+                .builder
+                .build_load(f64_type, slot, param)
+                .unwrap()
+                .into_float_value();
+            let value = self
+                .builder
+                .build_float_trunc(value, f32_type, param)
+                .unwrap();
+            scope.insert(param.clone(), value);
+        }
+
+        let result = self.build_rpn(src, function, body, &mut scope)?;
+        let result = self
+            .builder
+            .build_float_ext(result, f64_type, "result")
+            .unwrap();
+        self.builder.build_return(Some(&result)).unwrap();
+        Ok(())
+    }
+
+    /// Runs LLVM's `-O3` pipeline over the whole module, which inlines function calls and
+    /// vectorizes loops.
+    pub fn optimize(&self, machine: &TargetMachine) {
+        self.module
+            .run_passes("default<O3>", machine, PassBuilderOptions::create())
+            .unwrap();
+    }
+
+    /// Pointer to the `n`th `f32` of an array.
+    pub(crate) fn build_slot(
+        &self,
+        array: PointerValue<'ctx>,
+        n: usize,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        unsafe {
+            self.builder
+                .build_gep(
+                    self.context.f32_type(),
+                    array,
+                    &[self.context.i32_type().const_int(n as u64, false)],
+                    &format!("{name}_ptr"),
+                )
+                .unwrap()
+        }
+    }
+
+    /// Loads the `n`th `f32` of an array.
+    pub(crate) fn build_load_nth(
+        &self,
+        array: PointerValue<'ctx>,
+        n: usize,
+        name: &str,
+    ) -> FloatValue<'ctx> {
+        let slot = self.build_slot(array, n, name);
+        self.builder
+            .build_load(self.context.f32_type(), slot, name)
+            .unwrap()
+            .into_float_value()
+    }
+
+    /// An `f32` array allocated in the entry block, so calls inside loops reuse the same memory.
+    pub(crate) fn build_entry_array(
+        &self,
+        function: FunctionValue<'ctx>,
+        len: usize,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        let entry = function.get_first_basic_block().unwrap();
+        let alloca_builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(instruction) => alloca_builder.position_before(&instruction),
+            None => alloca_builder.position_at_end(entry),
+        }
+        alloca_builder
+            .build_array_alloca(
+                self.context.f32_type(),
+                self.context.i32_type().const_int(len as u64, false),
+                name,
+            )
+            .unwrap()
+    }
+
+    /// Emits the loop itself: the index counts up from `from` by one while it is at most `to`.
+    /// Bounds of nested loops are only known inside the loop, so an empty inner range just yields the identity.
+    fn build_loop(
+        &self,
+        src: &str,
+        function: FunctionValue<'ctx>,
+        reduction: &Loop,
+        from: FloatValue<'ctx>,
+        to: FloatValue<'ctx>,
+        scope: &mut HashMap<String, FloatValue<'ctx>>,
+    ) -> Result<FloatValue<'ctx>, Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let i64_type = self.context.i64_type();
+        let b = &self.builder;
+        // Counted in an `i64`, since an `f32` stops counting at 2^24, where adding one rounds back down.
+        let (from, to) = (self.build_loop_bound(from), self.build_loop_bound(to));
+        let preheader = b.get_insert_block().unwrap();
+        let header = self.context.append_basic_block(function, "loop_header");
+        let body = self.context.append_basic_block(function, "loop_body");
+        let exit = self.context.append_basic_block(function, "loop_exit");
+        b.build_unconditional_branch(header).unwrap();
+
+        b.position_at_end(header);
+        let index = b.build_phi(i64_type, "index").unwrap();
+        let acc = b.build_phi(f32_type, "acc").unwrap();
+        let index_value = index.as_basic_value().into_int_value();
+        let acc_value = acc.as_basic_value().into_float_value();
+        let in_range = b
+            .build_int_compare(IntPredicate::SLE, index_value, to, "in_range")
+            .unwrap();
+        b.build_conditional_branch(in_range, body, exit).unwrap();
+
+        b.position_at_end(body);
+        let index_float = b
+            .build_signed_int_to_float(index_value, f32_type, &reduction.index)
+            .unwrap();
+        let shadowed = scope.insert(reduction.index.clone(), index_float);
+        let term = self.build_rpn(src, function, &reduction.body, scope)?;
+        match shadowed {
+            Some(value) => scope.insert(reduction.index.clone(), value),
+            None => scope.remove(&reduction.index),
+        };
+        let next_acc = match reduction.reduction {
+            Reduction::Sum => b.build_float_add(acc_value, term, "next_acc").unwrap(),
+            Reduction::Prod => b.build_float_mul(acc_value, term, "next_acc").unwrap(),
+        };
+        let next_index = b
+            .build_int_add(index_value, i64_type.const_int(1, false), "next_index")
+            .unwrap();
+        // The body may have ended in a nested loop's exit block rather than `body`.
+        let latch = b.get_insert_block().unwrap();
+        b.build_unconditional_branch(header).unwrap();
+
+        let identity = f32_type.const_float(reduction.reduction.identity() as f64);
+        index.add_incoming(&[(&from, preheader), (&next_index, latch)]);
+        acc.add_incoming(&[(&identity, preheader), (&next_acc, latch)]);
+
+        b.position_at_end(exit);
+        Ok(acc_value)
+    }
+
+    /// A bound of a sum or product as the `i64` the loop counts with. Bounds outside the range of an
+    /// `i64` saturate, and `nan` is `0`, where a plain conversion would have no defined result.
+    pub(crate) fn build_loop_bound(&self, bound: FloatValue<'ctx>) -> IntValue<'ctx> {
+        let i64_type = self.context.i64_type();
+        let declaration = Intrinsic::find("llvm.fptosi.sat")
+            .unwrap()
+            .get_declaration(
+                &self.module,
+                &[i64_type.into(), self.context.f32_type().into()],
+            )
+            .unwrap();
+        self.builder
+            .build_call(declaration, &[bound.into()], "bound")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value()
+    }
+
+    /// Emits IR for an RPN expression inside a loop, where every value is an `f32`.
+    fn build_rpn(
+        &self,
+        src: &str,
+        function: FunctionValue<'ctx>,
+        tokens: &[Token],
+        scope: &mut HashMap<String, FloatValue<'ctx>>,
+    ) -> Result<FloatValue<'ctx>, Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let b = &self.builder;
+        let error = |message: String, token: &Token| {
+            Box::new(CompileError {
+                src: NamedSource::new("input", src.to_owned()),
+                message,
+                span: span(token.location),
+                label: "here".to_owned(),
+                help: Some("Sums, products and functions are compiled to float code.".to_owned()),
+            })
+        };
+        // RPN whose operators do not add up to one value, which the parser should never produce.
+        let ill_formed = |location: Option<Location>| {
+            Box::new(CompileError {
+                src: NamedSource::new("input", src.to_owned()),
+                message: "Ill-formed expression".to_owned(),
+                span: span(location),
+                label: "here".to_owned(),
+                help: Some(
+                    "Every operator needs its operands, and every operand an operator.".to_owned(),
+                ),
+            })
+        };
+
+        let mut stack: Vec<FloatValue<'ctx>> = vec![];
+        for token in tokens {
+            match &token.token_type {
+                TokenType::Num(Number::Float(x)) => stack.push(f32_type.const_float(*x as f64)),
+                TokenType::Num(n) => {
+                    return Err(error(
+                        format!("`{n}` cannot be used inside a sum, product or function"),
+                        token,
+                    ));
+                }
+                TokenType::Id(id) => stack.push(
+                    *scope
+                        .get(id)
+                        .expect("Every free variable of a loop is captured."),
+                ),
+                TokenType::BinOp(BinOp::MatMul)
+                | TokenType::Array(_)
+                | TokenType::LinAlg(_)
+                | TokenType::Statistic(..) => {
+                    return Err(error(
+                        "Vectors and matrices cannot be used inside a sum, product or function"
+                            .to_owned(),
+                        token,
+                    ));
+                }
+                TokenType::BinOp(op) => {
+                    let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                        return Err(ill_formed(token.location));
+                    };
+                    stack.push(match op {
+                        BinOp::Plus => b.build_float_add(x, y, "sum").unwrap(),
+                        BinOp::Minus => b.build_float_sub(x, y, "sub").unwrap(),
+                        BinOp::Times => b.build_float_mul(x, y, "mul").unwrap(),
+                        BinOp::Divide => b.build_float_div(x, y, "div").unwrap(),
+                        // The simplifier writes `x * x` as `x^2`, which is cheaper as a multiplication.
+                        BinOp::Power
+                            if let Some((n, _)) = y.get_constant()
+                                && [2., 3., 4.].contains(&n) =>
+                        {
+                            (1..n as usize).fold(x, |product, _| {
+                                b.build_float_mul(product, x, "pow").unwrap()
+                            })
+                        }
+                        BinOp::Power => self.build_intrinsic_call("llvm.pow", &[x, y]),
+                        BinOp::Equal | BinOp::MatMul => unreachable!(),
+                    });
+                }
+                TokenType::Negate => {
+                    let x = stack.pop().ok_or_else(|| ill_formed(token.location))?;
+                    stack.push(b.build_float_neg(x, "neg").unwrap());
+                }
+                TokenType::Builtin(builtin) => {
+                    let x = stack.pop().ok_or_else(|| ill_formed(token.location))?;
+                    match FLOAT_BUILTINS.iter().find(|(b, _)| b == builtin) {
+                        Some((_, intrinsic)) => {
+                            stack.push(self.build_intrinsic_call(intrinsic, &[x]))
+                        }
+                        None => {
+                            return Err(error(
+                                format!(
+                                    "`{builtin}` cannot be used inside a sum, product or function"
+                                ),
+                                token,
+                            ));
+                        }
+                    }
+                }
+                TokenType::Call(name, arity) => {
+                    let at = stack
+                        .len()
+                        .checked_sub(*arity)
+                        .ok_or_else(|| ill_formed(token.location))?;
+                    let args = stack.split_off(at);
+                    let Some(callee) = self.module.get_function(&format!("fn_{name}")) else {
+                        return Err(error(format!("`{name}` is not compiled yet"), token));
+                    };
+                    let array = self.build_entry_array(function, *arity, "args");
+                    for (n, arg) in args.into_iter().enumerate() {
+                        b.build_store(self.build_slot(array, n, "arg"), arg)
+                            .unwrap();
+                    }
+                    stack.push(
+                        b.build_call(callee, &[array.into()], name)
+                            .unwrap()
+                            .try_as_basic_value()
+                            .left()
+                            .unwrap()
+                            .into_float_value(),
+                    );
+                }
+                TokenType::Loop(inner) => {
+                    let from = self.build_rpn(src, function, &inner.from, scope)?;
+                    let to = self.build_rpn(src, function, &inner.to, scope)?;
+                    stack.push(self.build_loop(src, function, inner, from, to, scope)?);
+                }
+                TokenType::Convert(_) => {
+                    return Err(error(
+                        "Units cannot be converted inside a sum, product or function".to_owned(),
+                        token,
+                    ));
+                }
+                TokenType::Grad(..) => {
+                    return Err(error(
+                        "`grad` cannot be used inside a sum, product or function".to_owned(),
+                        token,
+                    ));
+                }
+                TokenType::Integral(_) => {
+                    return Err(error(
+                        "`integrate` cannot be used inside a sum, product, function or another integral"
+                            .to_owned(),
+                        token,
+                    ));
+                }
+                TokenType::Simplified(node) => {
+                    stack.push(self.build_rpn(
+                        src,
+                        function,
+                        &node.to_rpn(token.location),
+                        scope,
+                    )?);
+                }
+                TokenType::Equation(_) => {
+                    return Err(error(
+                        "`solve` cannot be used inside a sum, product, function, integral or another equation"
+                            .to_owned(),
+                        token,
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        stack
+            .pop()
+            .ok_or_else(|| ill_formed(tokens.last().and_then(|token| token.location)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::DecimalConfig;
+
+    /// The values a program prints in float mode.
+    fn run(src: &str) -> Vec<Number> {
+        run_in(src, NumberMode::Float).unwrap()
+    }
+
+    /// The values a program prints in `number_mode`, or why it could not be run.
+    fn run_in(src: &str, number_mode: NumberMode) -> miette::Result<Vec<Number>> {
+        let context = Context::create();
+        let codegen = CodeGen {
+            context: &context,
+            module: context.create_module("test"),
+            builder: context.create_builder(),
+        };
+        let lexer = Lexer::new(src).with_number_mode(number_mode);
+        let printed = Compiler::new(src, codegen, lexer).run_all()?;
+        Ok(printed.into_iter().map(|printed| printed.value).collect())
+    }
+
+    /// What a program prints in decimal mode at a scale of 2, as text.
+    fn run_decimal(src: &str, rounding: RoundingMode) -> miette::Result<Vec<String>> {
+        let config = DecimalConfig { scale: 2, rounding };
+        let values = run_in(src, NumberMode::Decimal(config))?;
+        Ok(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    fn floats(values: &[f32]) -> Vec<Number> {
+        values.iter().copied().map(Number::Float).collect()
+    }
+
+    #[test]
+    fn definitions_read_the_results_before_them() {
+        assert_eq!(run("2+3; y = ans*2; y"), floats(&[5., 10.]));
+        assert_eq!(run("2+3; 7; y = ans*2; y"), floats(&[5., 7., 14.]));
+        assert_eq!(
+            run("2; x = _1 + 1; x; z = ans + x; z"),
+            floats(&[2., 3., 6.])
+        );
+        // Definitions still read each other in any order.
+        assert_eq!(run("2; a = b + 1; b = ans * 2; a"), floats(&[2., 5.]));
+        // Results are not kept from one run to the next.
+        for src in ["ans + 1", "_1 * 2"] {
+            let report = run_in(src, NumberMode::Float).unwrap_err();
+            let help = report.help().unwrap().to_string();
+            assert!(help.contains("only keeps definitions"), "{help}");
+        }
+    }
+
+    #[test]
+    fn functions_read_the_variables_before_them() {
+        assert_eq!(run("a = 3; f(x) = x^2 + a; f(2)"), floats(&[7.]));
+        assert_eq!(run("b = 2; a = b*3; f(x) = x + a; f(1)"), floats(&[7.]));
+        assert_eq!(run("2+3; f(x) = x + ans; f(1)"), floats(&[5., 6.]));
+        // A variable assigned again later keeps the value the function was defined with.
+        assert_eq!(run("a = 1; f(x) = x + a; a = 5; f(0)"), floats(&[1.]));
+        // A loop index is not the variable of the same name.
+        assert_eq!(run("a = 2; f(x) = sum(a, 1, x, a); f(3)"), floats(&[6.]));
+        assert_eq!(
+            error("f(x) = x + q; f(1)"),
+            "`f` uses `q`, which is neither one of its parameters nor defined before it."
+        );
+    }
+
+    #[test]
+    fn derivatives() {
+        let shown =
+            |src: &str| -> Vec<String> { run(src).iter().map(|value| value.to_string()).collect() };
+        assert_eq!(run("x = 2; d/dx(x^3)"), floats(&[12.]));
+        assert_eq!(run("d/dx(x^3); x = 2"), floats(&[12.]));
+        assert_eq!(run("f(x) = x^2; g(x) = deriv(f, x); g(3)"), floats(&[6.]));
+        // Without a value for the variable, the derivative itself is printed.
+        assert_eq!(shown("d/dx(x^2)"), ["2 * x"]);
+        assert_eq!(shown("f(x) = x^2; deriv(f, x)"), ["2 * x"]);
+        assert_eq!(error("d/dx(x^2) + 1"), "Unknown variable `x`");
+    }
+
+    #[test]
+    fn compiled_decimals_round_like_decimal() {
+        let src = "1.25 * -0.5; -2/3; 0.05 * 0.5; 2^-2";
+        for (rounding, expected) in [
+            (RoundingMode::HalfEven, ["-0.62", "-0.67", "0.02", "0.25"]),
+            (RoundingMode::HalfUp, ["-0.63", "-0.67", "0.03", "0.25"]),
+            (RoundingMode::Truncate, ["-0.62", "-0.66", "0.02", "0.25"]),
+        ] {
+            assert_eq!(run_decimal(src, rounding).unwrap(), expected);
+        }
+        for src in ["1 / 0.001", "92233720368547758 * 2", "2^0.5"] {
+            assert!(
+                run_decimal(src, RoundingMode::HalfEven).is_err(),
+                "`{src}` ran"
+            );
+        }
+    }
+
+    #[test]
+    fn conversions_inside_expressions() {
+        let shown =
+            |src: &str| -> Vec<String> { run(src).iter().map(|value| value.to_string()).collect() };
+        assert_eq!(shown("(5 km in mi) * 2"), ["6.2137117 mi"]);
+        assert_eq!(shown("2 * (1 km to m) + 1 m"), ["2001 m"]);
+        assert_eq!(shown("-(1 km in m)"), ["-1000 m"]);
+        assert_eq!(shown("3 GiB / 12 s"), ["268435456 B/s"]);
+        assert_eq!(shown("2^28"), ["268435456"]);
+    }
+
+    /// The message of the error a program stops with.
+    fn error(src: &str) -> String {
+        run_in(src, NumberMode::Float).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            run("sum(k, 1, 4, k^2); prod(k, 1, 5, k)"),
+            floats(&[30., 120.])
+        );
+        assert_eq!(run("sum(k, 3, 3, k); sum(k, -2, 2, k)"), floats(&[3., 0.]));
+        assert_eq!(run("sum(k, 1, 3, sum(j, 1, k, j))"), floats(&[10.]));
+        assert_eq!(run("f(n) = prod(k, 1, n, k); f(4)"), floats(&[24.]));
+        // Bounds only known inside compiled code can describe no terms, which leaves the identity.
+        assert_eq!(run("f(n) = sum(k, n, 1, k); f(3)"), floats(&[0.]));
+        assert_eq!(run("sum(k, 1, 2, prod(j, k, 1, j))"), floats(&[2.]));
+        // An f32 index stops counting at 2^24, where adding 1 leaves it unchanged.
+        assert_eq!(run("sum(k, 16777215, 16777218, 1)"), floats(&[4.]));
+    }
+
+    #[test]
+    fn loop_bounds() {
+        assert_eq!(
+            error("sum(k, 5, 4, k)"),
+            "The lower bound of `sum` is greater than its upper bound"
+        );
+        assert_eq!(
+            error("prod(k, 1, 0, k)"),
+            "The lower bound of `prod` is greater than its upper bound"
+        );
+        assert_eq!(
+            error("sum(k, 1, 2.5, k)"),
+            "Bounds of `sum` must be whole numbers"
+        );
+        assert_eq!(
+            error("sum(k, 0.5, 2, k)"),
+            "Bounds of `sum` must be whole numbers"
+        );
+    }
+
+    #[test]
+    fn redefined_functions() {
+        // What called the earlier definition calls the new one.
+        assert_eq!(
+            run("f(x) = x; g(x) = f(x) + 1; f(x) = 2*x; g(1)"),
+            floats(&[3.])
+        );
+        assert_eq!(
+            run("f(x) = x; g(x) = sum(k, 1, 2, f(k)); f(x) = 2*x; g(0)"),
+            floats(&[6.])
+        );
+        // The new definition can call functions defined after the earlier one.
+        assert_eq!(
+            run("f(x) = x; h(x) = x + 1; f(x) = h(x) * 10; f(1)"),
+            floats(&[20.])
+        );
+        assert_eq!(
+            error("f(x) = x; g(x) = f(x) + 1; f(x, y) = x*y; g(1)"),
+            "`f` is called with 1 argument(s), but its new definition takes 2."
+        );
+        assert_eq!(
+            error("f(x) = x; y = f(2); f(x, z) = x; y"),
+            "`f` is called with 1 argument(s), but its new definition takes 2."
+        );
+        assert_eq!(
+            error("f(x) = x; g(x) = f(x) + 1; f(x) = g(x); f(1)"),
+            "`f` would call itself forever."
+        );
+        assert_eq!(
+            error("f(x) = x; f(x) = f(x) + 1; f(1)"),
+            "`f` would call itself forever."
+        );
+    }
+
+    #[test]
+    fn gradients_of_powers() {
+        let gradient = |src: &str| match run(src).pop() {
+            Some(Number::Gradient(gradient)) => gradient,
+            value => panic!("`{src}` printed {value:?}"),
+        };
+        // `n x^(n-1)`, although `ln x` in the derivative by `n` is `nan` for `x < 0`.
+        let g = gradient("f(x, n) = x^n; grad(f, -2, 3)");
+        assert_eq!((g.value, g.gradient[0]), (-8., 12.));
+        assert!(g.gradient[1].is_nan());
+        let g = gradient("f(x, n) = x^n; grad(f, 2, 3)");
+        assert_eq!(g.gradient[0], 12.);
+        assert!((g.gradient[1] - 8. * 2f32.ln()).abs() < 1e-5);
+        // `x^x` depends on `x` through both its base and its exponent.
+        let g = gradient("f(x) = x^x; grad(f, 2)");
+        assert!((g.gradient[0] - 4. * (2f32.ln() + 1.)).abs() < 1e-5);
+        assert_eq!(gradient("g(x) = x^(1 + 1); grad(g, -3)").gradient, [-6.]);
+    }
+
+    #[test]
+    fn integrals() {
+        let estimate = |src: &str| match run(src).pop() {
+            Some(Number::Estimate(estimate)) => (estimate.value, estimate.error),
+            value => panic!("`{src}` printed {value:?}"),
+        };
+        assert_eq!(estimate("integrate(x^2, x, 0, 1)"), (1. / 3., 0.));
+        assert_eq!(estimate("integrate(x^3, x, 1, 0)"), (-0.25, 0.));
+        let (value, bound) = estimate("integrate(abs(x - 1/3), x, 0, 1, 0.0001)");
+        assert!((value - 5. / 18.).abs() <= 1e-4 && bound <= 1e-4);
+        assert_eq!(
+            error("integrate(1/x, x, 0, 1)"),
+            "The integrand is not finite at x = 0"
+        );
+        assert_eq!(
+            error("integrate(x, x, 0, 1, -1)"),
+            "The tolerance of `integrate` must be positive"
+        );
+    }
+
+    #[test]
+    fn solving() {
+        let shown =
+            |src: &str| -> Vec<String> { run(src).iter().map(|value| value.to_string()).collect() };
+        assert_eq!(shown("solve(2*x + 1 = 5, x)"), ["x = 2"]);
+        assert_eq!(shown("solve(x^2 = 4, x)"), ["x = -2 or x = 2"]);
+        assert_eq!(shown("solve(x^2 - 2*x + 1 = 0, x)"), ["x = 1"]);
+        assert_eq!(shown("solve(x^2 + 1 = 0, x)"), ["x = 0 - 1i or x = 0 + 1i"]);
+        // A cubic term that cancels still leaves a quadratic.
+        assert_eq!(shown("solve(x^3 - x^3 + x^2 = 4, x)"), ["x = -2 or x = 2"]);
+        // A guess picks one root.
+        assert_eq!(shown("solve(x^2 = 4, x, 3)"), ["x = 2"]);
+        // Newton's method, from a guess and from zero. Inside an expression a single root is a number.
+        for src in ["solve(exp(x) = 2, x) + 0", "solve(exp(x) = 2, x, 1) + 0"] {
+            let [Number::Float(root)] = run(src)[..] else {
+                panic!("`{src}` did not find one root");
+            };
+            assert!((root - 2f32.ln()).abs() < 1e-6, "`{src}` found {root}");
+        }
+        // A sum cannot be differentiated, so its roots are only bracketed.
+        assert_eq!(run("solve(sum(k, 1, 3, k*x) = 12, x) + 0"), floats(&[2.]));
+        assert_eq!(run("solve(sum(k, 1, 2, x^k) = 6, x, 1) + 0"), floats(&[2.]));
+
+        assert_eq!(
+            error("solve(x - x = 1, x)"),
+            "This equation has no solution"
+        );
+        assert_eq!(
+            error("solve(x - x = 0, x)"),
+            "Every value of `x` solves this equation"
+        );
+        assert_eq!(error("solve(x^2 = 4, x) + 1"), "`solve` found 2 roots");
+        assert_eq!(
+            error("r = solve(x^2 = 4, x); r + 1"),
+            "`solve` returned several roots"
+        );
+    }
+
+    #[test]
+    fn rewritten_functions_keep_their_values() {
+        assert_eq!(
+            run(
+                "f(x) = 2*x + 3*x; f(2); g(x) = x*1 - 0 + x^1/1 + x; g(3); sum(k, 1, 4, 2*k + k*3)"
+            ),
+            floats(&[10., 9., 50.])
+        );
+        // Terms that cancel are not collected, so these are still `nan` where they overflow.
+        let [Number::Float(cancelled)] = run("f(x) = 2*x - 2*x; f(1/0)")[..] else {
+            panic!("`f` did not return a float");
+        };
+        assert!(cancelled.is_nan());
+        let [Number::Float(overflowed)] = run(
+            "f(x) = 1000000000000000000000000000000*x - 1000000000000000000000000000000*x; f(10000000000)",
+        )[..] else {
+            panic!("`f` did not return a float");
+        };
+        assert!(overflowed.is_nan());
+        // `x + 0` is kept, since it turns `-0` into `0`.
+        assert_eq!(run("f(x) = x + 0; f(0 * -1)")[0].to_string(), "0");
+        assert_eq!(run("f(x) = x - 0; f(0 * -1)")[0].to_string(), "-0");
+    }
+
+    #[test]
+    fn slices_match_calls() {
+        let context = Context::create();
+        let expr = compile(&context, "k = 3; sum(n, 1, 4, x^n) / k + sqrt(y) - 0.1 * x").unwrap();
+        assert_eq!(expr.params(), ["x", "y"]);
+        let xs: Vec<f64> = (0..37).map(|n| n as f64 / 7. - 2.).collect();
+        let ys: Vec<f64> = (0..37).map(|n| n as f64 * 0.3).collect();
+        let mut out = vec![0.; xs.len()];
+        expr.eval_slice(&[&xs, &ys], &mut out);
+        for ((x, y), value) in xs.iter().zip(&ys).zip(&out) {
+            assert_eq!(*value, expr.call(&[*x, *y]), "at x = {x}, y = {y}");
+        }
+        // An empty slice is not read at all.
+        expr.eval_slice(&[&[], &[]], &mut []);
+    }
+
+    #[test]
+    fn compiled_expressions() {
+        let context = Context::create();
+        let expr = compile(&context, "k = 2; f(t) = t^2; k * f(x) + y").unwrap();
+        // Variables the program assigns are not parameters.
+        assert_eq!(expr.params(), ["x", "y"]);
+        assert_eq!(expr.call(&[3., 1.]), 19.);
+        // The same handle is called again with other values.
+        for n in 0..1000 {
+            let x = n as f64 / 10.;
+            assert_eq!(expr.call(&[x, -1.]), (2. * (x as f32).powi(2) - 1.) as f64);
+        }
+        assert_eq!(
+            compile(&context, "pi * 2").unwrap().call(&[]),
+            std::f32::consts::TAU as f64
+        );
+
+        let message = |src: &str| match compile(&context, src) {
+            Ok(_) => panic!("`{src}` compiled"),
+            Err(report) => report.to_string(),
+        };
+        assert_eq!(message(" "), "Expression cannot be empty.");
+        assert_eq!(
+            message("x; y"),
+            "The program prints 2 expressions, but only one can be used here."
+        );
+        assert_eq!(
+            message("v = [1, 2]; v + x"),
+            "`v` is used in every entry, but is [1, 2] rather than a real number."
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected one argument per parameter.")]
+    fn compiled_expressions_take_every_parameter() {
+        let context = Context::create();
+        compile(&context, "x + y").unwrap().call(&[1.]);
+    }
 }
@@ -0,0 +1,145 @@
+/// A CSV or TSV file: a header naming each column, then rows of cells, all kept as text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub delimiter: char,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Reads a header line followed by one row per line. Cells can be quoted, but not span lines.
+    pub fn parse(text: &str, delimiter: char) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty());
+        let Some((_, header)) = lines.next() else {
+            return Err("The table is empty.".to_owned());
+        };
+        let header: Vec<String> = split_line(header, delimiter)
+            .into_iter()
+            .map(|name| name.trim().to_owned())
+            .collect();
+        let rows = lines
+            .map(|(n, line)| {
+                let cells = split_line(line, delimiter);
+                if cells.len() == header.len() {
+                    Ok(cells)
+                } else {
+                    Err(format!(
+                        "Line {} has {} cells, but the header names {} columns.",
+                        n + 1,
+                        cells.len(),
+                        header.len()
+                    ))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Table {
+            delimiter,
+            header,
+            rows,
+        })
+    }
+
+    /// The cells of a column as numbers, or the row, counted from 1, and text of the first one that is not a number.
+    pub fn numbers(&self, column: usize) -> Result<Vec<f32>, (usize, String)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(n, row)| {
+                let cell = row[column].trim();
+                cell.parse().map_err(|_| (n + 1, cell.to_owned()))
+            })
+            .collect()
+    }
+
+    /// The table with `values` added as its last column.
+    pub fn with_column(&self, name: &str, values: &[f32]) -> String {
+        let line = |cells: &mut dyn Iterator<Item = String>| {
+            cells
+                .map(|cell| quote(&cell, self.delimiter))
+                .collect::<Vec<_>>()
+                .join(&self.delimiter.to_string())
+        };
+        let mut out = line(&mut self.header.iter().cloned().chain([name.to_owned()]));
+        for (row, value) in self.rows.iter().zip(values) {
+            out.push('\n');
+            out.push_str(&line(&mut row.iter().cloned().chain([value.to_string()])));
+        }
+        out
+    }
+}
+
+/// Splits a line at every delimiter outside double quotes, where `""` stands for a quote.
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut cells = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cells.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+    cells
+}
+
+fn quote(cell: &str, delimiter: char) -> String {
+    if cell.contains(delimiter) || cell.contains('"') {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(split_line("a,\"b,c\",d", ','), ["a", "b,c", "d"]);
+        assert_eq!(split_line("\"say \"\"hi\"\"\",2", ','), ["say \"hi\"", "2"]);
+        assert_eq!(split_line("a\t\"b\tc\"\t", '\t'), ["a", "b\tc", ""]);
+        assert_eq!(quote("plain", ','), "plain");
+        assert_eq!(quote("a,b", ','), "\"a,b\"");
+        assert_eq!(quote("a,b", '\t'), "a,b");
+        assert_eq!(quote("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn written_tables_read_back() {
+        let table = Table::parse("name, x\n\"Smith, J\",1\n\n\"\"\"Q\"\"\",2.5\n", ',').unwrap();
+        assert_eq!(table.header, ["name", "x"]);
+        assert_eq!(table.rows, [["Smith, J", "1"], ["\"Q\"", "2.5"]]);
+        assert_eq!(table.numbers(1), Ok(vec![1., 2.5]));
+        assert_eq!(table.numbers(0), Err((1, "Smith, J".to_owned())));
+
+        let written = table.with_column("y, doubled", &[2., 5.]);
+        assert_eq!(
+            written,
+            "name,x,\"y, doubled\"\n\"Smith, J\",1,2\n\"\"\"Q\"\"\",2.5,5"
+        );
+        let read = Table::parse(&written, ',').unwrap();
+        assert_eq!(read.header, ["name", "x", "y, doubled"]);
+        assert_eq!(read.numbers(2), Ok(vec![2., 5.]));
+    }
+
+    #[test]
+    fn ragged_and_empty_tables() {
+        assert_eq!(
+            Table::parse("a,b\n1,2\n3\n", ','),
+            Err("Line 3 has 1 cells, but the header names 2 columns.".to_owned())
+        );
+        assert_eq!(
+            Table::parse("\n\n", ','),
+            Err("The table is empty.".to_owned())
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+
+use inkwell::{
+    AddressSpace, FloatPredicate, IntPredicate,
+    values::{FloatValue, FunctionValue, IntValue, PointerValue},
+};
+use miette::NamedSource;
+
+use crate::{
+    Location, Token, TokenType,
+    codegen::{CodeGen, FLOAT_BUILTINS},
+    error::CompileError,
+    lexer::{BinOp, Builtin, Reduction},
+    number::Number,
+    parse::{Function, Loop},
+    span,
+};
+
+/// A value in a dual-number function: the value itself and its derivative along each parameter.
+#[derive(Clone)]
+struct Dual<'ctx> {
+    value: FloatValue<'ctx>,
+    tangents: Vec<FloatValue<'ctx>>,
+    /// Set for literals, so `x^2` can use the power rule instead of going through `ln(x)`.
+    literal: bool,
+}
+
+impl<'ctx> CodeGen<'ctx> {
+    /// Compiles the dual-number version of a user function into `fn_<name>_dual`, matching `DualFunction`.
+    /// One call writes the value followed by the partial derivative along every parameter.
+    pub fn compile_dual(&self, src: &str, function: &Function) -> Result<(), Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let bool_type = self.context.bool_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = self
+            .context
+            .i8_type()
+            .fn_type(&[ptr_type.into(), ptr_type.into()], false);
+        let name = format!("fn_{}_dual", function.name);
+        let compiled = self.module.add_function(&name, fn_type, None);
+        let basic_block = self
+            .context
+            .append_basic_block(compiled, &format!("{name}_entry"));
+
+        self.builder.position_at_end(basic_block);
+
+        let args = compiled.get_nth_param(0).unwrap().into_pointer_value();
+        let out = compiled.get_nth_param(1).unwrap().into_pointer_value();
+        // Cleared by any sum or product whose bounds turn out not to be whole numbers, since those
+        // are only known once the arguments are.
+        let whole = self.builder.build_alloca(bool_type, "whole").unwrap();
+        self.builder
+            .build_store(whole, bool_type.const_all_ones())
+            .unwrap();
+        let width = function.params.len();
+        let mut scope = HashMap::new();
+        for (n, param) in function.params.iter().enumerate() {
+            // Seeded with the unit vector along its own parameter.
+            let tangents = (0..width)
+                .map(|k| f32_type.const_float(if k == n { 1. } else { 0. }))
+                .collect();
+            let dual = Dual {
+                value: self.build_load_nth(args, n, param),
+                tangents,
+                literal: false,
+            };
+            scope.insert(param.clone(), dual);
+        }
+
+        let result =
+            self.build_dual_rpn(src, compiled, &function.body, &mut scope, width, whole)?;
+        self.builder
+            .build_store(self.build_slot(out, 0, "value"), result.value)
+            .unwrap();
+        for (k, tangent) in result.tangents.into_iter().enumerate() {
+            self.builder
+                .build_store(self.build_slot(out, k + 1, "partial"), tangent)
+                .unwrap();
+        }
+        let whole = self
+            .builder
+            .build_load(bool_type, whole, "whole")
+            .unwrap()
+            .into_int_value();
+        let whole = self
+            .builder
+            .build_int_z_extend(whole, self.context.i8_type(), "whole")
+            .unwrap();
+        self.builder.build_return(Some(&whole)).unwrap();
+        Ok(())
+    }
+
+    fn build_dual_rpn(
+        &self,
+        src: &str,
+        function: FunctionValue<'ctx>,
+        tokens: &[Token],
+        scope: &mut HashMap<String, Dual<'ctx>>,
+        width: usize,
+        whole: PointerValue<'ctx>,
+    ) -> Result<Dual<'ctx>, Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let b = &self.builder;
+        let error = |message: String, token: &Token| {
+            Box::new(CompileError {
+                src: NamedSource::new("input", src.to_owned()),
+                message,
+                span: span(token.location),
+                label: "here".to_owned(),
+                help: Some("`grad` compiles functions to dual-number float code.".to_owned()),
+            })
+        };
+        // RPN whose operators do not add up to one value, which the parser should never produce.
+        let ill_formed = |location: Option<Location>| {
+            Box::new(CompileError {
+                src: NamedSource::new("input", src.to_owned()),
+                message: "Ill-formed expression".to_owned(),
+                span: span(location),
+                label: "here".to_owned(),
+                help: Some(
+                    "Every operator needs its operands, and every operand an operator.".to_owned(),
+                ),
+            })
+        };
+
+        let mut stack: Vec<Dual<'ctx>> = vec![];
+        for token in tokens {
+            match &token.token_type {
+                TokenType::Num(Number::Float(x)) => {
+                    let mut dual = self.dual_constant(f32_type.const_float(*x as f64), width);
+                    dual.literal = true;
+                    stack.push(dual);
+                }
+                TokenType::Num(n) => {
+                    return Err(error(
+                        format!("`{n}` cannot be used in a function passed to `grad`"),
+                        token,
+                    ));
+                }
+                TokenType::Id(id) => stack.push(
+                    scope
+                        .get(id)
+                        .expect("Functions only use their parameters.")
+                        .clone(),
+                ),
+                TokenType::BinOp(op) => {
+                    let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                        return Err(ill_formed(token.location));
+                    };
+                    stack.push(self.build_dual_bin_op(op, x, y));
+                }
+                TokenType::Negate => {
+                    let x = stack.pop().ok_or_else(|| ill_formed(token.location))?;
+                    stack.push(Dual {
+                        value: b.build_float_neg(x.value, "neg").unwrap(),
+                        tangents: x
+                            .tangents
+                            .iter()
+                            .map(|t| b.build_float_neg(*t, "neg_tangent").unwrap())
+                            .collect(),
+                        literal: x.literal,
+                    });
+                }
+                TokenType::Builtin(builtin) => {
+                    let x = stack.pop().ok_or_else(|| ill_formed(token.location))?;
+                    if !FLOAT_BUILTINS.iter().any(|(b, _)| b == builtin) {
+                        return Err(error(
+                            format!("`{builtin}` cannot be used in a function passed to `grad`"),
+                            token,
+                        ));
+                    }
+                    stack.push(self.build_dual_builtin(*builtin, x));
+                }
+                TokenType::Call(name, arity) => {
+                    // The callee's own gradient, combined with the arguments' tangents by the chain rule.
+                    let at = stack
+                        .len()
+                        .checked_sub(*arity)
+                        .ok_or_else(|| ill_formed(token.location))?;
+                    let args = stack.split_off(at);
+                    let Some(callee) = self.module.get_function(&format!("fn_{name}_dual")) else {
+                        return Err(error(format!("`{name}` is not compiled yet"), token));
+                    };
+                    let array = self.build_entry_array(function, *arity, "args");
+                    let out = self.build_entry_array(function, arity + 1, "out");
+                    for (n, arg) in args.iter().enumerate() {
+                        b.build_store(self.build_slot(array, n, "arg"), arg.value)
+                            .unwrap();
+                    }
+                    let callee_whole = b
+                        .build_call(callee, &[array.into(), out.into()], name)
+                        .unwrap()
+                        .try_as_basic_value()
+                        .left()
+                        .unwrap()
+                        .into_int_value();
+                    let callee_whole = b
+                        .build_int_compare(
+                            IntPredicate::NE,
+                            callee_whole,
+                            callee_whole.get_type().const_zero(),
+                            "callee_whole",
+                        )
+                        .unwrap();
+                    self.build_clear_whole(whole, callee_whole);
+                    let value = self.build_load_nth(out, 0, name);
+                    let partials: Vec<_> = (0..*arity)
+                        .map(|n| self.build_load_nth(out, n + 1, "partial"))
+                        .collect();
+                    let tangents = (0..width)
+                        .map(|k| {
+                            partials.iter().zip(&args).fold(
+                                f32_type.const_zero(),
+                                |acc, (partial, arg)| {
+                                    let term = b
+                                        .build_float_mul(*partial, arg.tangents[k], "chain")
+                                        .unwrap();
+                                    b.build_float_add(acc, term, "chain_sum").unwrap()
+                                },
+                            )
+                        })
+                        .collect();
+                    stack.push(Dual {
+                        value,
+                        tangents,
+                        literal: false,
+                    });
+                }
+                TokenType::Loop(inner) => {
+                    let from =
+                        self.build_dual_rpn(src, function, &inner.from, scope, width, whole)?;
+                    let to = self.build_dual_rpn(src, function, &inner.to, scope, width, whole)?;
+                    stack.push(self.build_dual_loop(
+                        src, function, inner, from.value, to.value, scope, width, whole,
+                    )?);
+                }
+                TokenType::Simplified(node) => stack.push(self.build_dual_rpn(
+                    src,
+                    function,
+                    &node.to_rpn(token.location),
+                    scope,
+                    width,
+                    whole,
+                )?),
+                TokenType::Convert(_)
+                | TokenType::Grad(..)
+                | TokenType::Integral(_)
+                | TokenType::Equation(_) => {
+                    return Err(error(
+                        "This cannot be used in a function passed to `grad`".to_owned(),
+                        token,
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        stack
+            .pop()
+            .ok_or_else(|| ill_formed(tokens.last().and_then(|token| token.location)))
+    }
+
+    fn dual_constant(&self, value: FloatValue<'ctx>, width: usize) -> Dual<'ctx> {
+        Dual {
+            value,
+            tangents: vec![self.context.f32_type().const_zero(); width],
+            literal: false,
+        }
+    }
+
+    fn build_dual_bin_op(&self, op: &BinOp, x: Dual<'ctx>, y: Dual<'ctx>) -> Dual<'ctx> {
+        let b = &self.builder;
+        let add = |u, v| b.build_float_add(u, v, "add").unwrap();
+        let sub = |u, v| b.build_float_sub(u, v, "sub").unwrap();
+        let mul = |u, v| b.build_float_mul(u, v, "mul").unwrap();
+        let div = |u, v| b.build_float_div(u, v, "div").unwrap();
+        let tangents = |f: &dyn Fn(FloatValue<'ctx>, FloatValue<'ctx>) -> FloatValue<'ctx>| {
+            x.tangents
+                .iter()
+                .zip(&y.tangents)
+                .map(|(dx, dy)| f(*dx, *dy))
+                .collect()
+        };
+
+        let (value, tangents) = match op {
+            BinOp::Plus => (add(x.value, y.value), tangents(&|dx, dy| add(dx, dy))),
+            BinOp::Minus => (sub(x.value, y.value), tangents(&|dx, dy| sub(dx, dy))),
+            BinOp::Times => (
+                mul(x.value, y.value),
+                tangents(&|dx, dy| add(mul(dx, y.value), mul(x.value, dy))),
+            ),
+            BinOp::Divide => {
+                let q = div(x.value, y.value);
+                (q, tangents(&|dx, dy| div(sub(dx, mul(q, dy)), y.value)))
+            }
+            BinOp::Power => {
+                let w = self.build_intrinsic_call("llvm.pow", &[x.value, y.value]);
+                if y.literal {
+                    let one = self.context.f32_type().const_float(1.);
+                    let lower =
+                        self.build_intrinsic_call("llvm.pow", &[x.value, sub(y.value, one)]);
+                    let scale = mul(y.value, lower);
+                    (w, tangents(&|dx, _| mul(scale, dx)))
+                } else {
+                    // `x^y = exp(y ln x)`, so its derivative is `y x^(y-1) x' + x^y ln x y'`. The second
+                    // term is only added where `y'` is not zero, since `ln x` is `nan` for `x < 0`
+                    // although `x^y` has a derivative there when `y` is whole.
+                    let f32_type = self.context.f32_type();
+                    let one = f32_type.const_float(1.);
+                    let lower =
+                        self.build_intrinsic_call("llvm.pow", &[x.value, sub(y.value, one)]);
+                    let scale = mul(y.value, lower);
+                    let ln_x = self.build_intrinsic_call("llvm.log", &[x.value]);
+                    let log_scale = mul(w, ln_x);
+                    (
+                        w,
+                        tangents(&|dx, dy| {
+                            let power = mul(scale, dx);
+                            if dy.get_constant().is_some_and(|(dy, _)| dy == 0.) {
+                                return power;
+                            }
+                            let constant = b
+                                .build_float_compare(
+                                    FloatPredicate::OEQ,
+                                    dy,
+                                    f32_type.const_zero(),
+                                    "constant_exponent",
+                                )
+                                .unwrap();
+                            b.build_select(
+                                constant,
+                                power,
+                                add(power, mul(log_scale, dy)),
+                                "pow_tangent",
+                            )
+                            .unwrap()
+                            .into_float_value()
+                        }),
+                    )
+                }
+            }
+            BinOp::Equal | BinOp::MatMul => unreachable!(),
+        };
+
+        Dual {
+            value,
+            tangents,
+            literal: x.literal && y.literal,
+        }
+    }
+
+    fn build_dual_builtin(&self, builtin: Builtin, x: Dual<'ctx>) -> Dual<'ctx> {
+        let b = &self.builder;
+        let f32_type = self.context.f32_type();
+        let (_, intrinsic) = FLOAT_BUILTINS.iter().find(|(b, _)| *b == builtin).unwrap();
+        let value = self.build_intrinsic_call(intrinsic, &[x.value]);
+        // Derivative of the built-in at `x`, which the chain rule multiplies every tangent by.
+        let scale = match builtin {
+            Builtin::Sqrt => {
+                let twice = b
+                    .build_float_mul(f32_type.const_float(2.), value, "twice")
+                    .unwrap();
+                b.build_float_div(f32_type.const_float(1.), twice, "d_sqrt")
+                    .unwrap()
+            }
+            Builtin::Exp => value,
+            Builtin::Ln => b
+                .build_float_div(f32_type.const_float(1.), x.value, "d_ln")
+                .unwrap(),
+            Builtin::Abs => {
+                self.build_intrinsic_call("llvm.copysign", &[f32_type.const_float(1.), x.value])
+            }
+            _ => unreachable!(),
+        };
+        let tangents = x
+            .tangents
+            .iter()
+            .map(|dx| b.build_float_mul(scale, *dx, "chain").unwrap())
+            .collect();
+
+        Dual {
+            value,
+            tangents,
+            literal: x.literal,
+        }
+    }
+
+    /// `CodeGen::build_loop` over dual numbers: the accumulator carries a tangent per parameter.
+    #[allow(clippy::too_many_arguments)]
+    fn build_dual_loop(
+        &self,
+        src: &str,
+        function: FunctionValue<'ctx>,
+        reduction: &Loop,
+        from: FloatValue<'ctx>,
+        to: FloatValue<'ctx>,
+        scope: &mut HashMap<String, Dual<'ctx>>,
+        width: usize,
+        whole: PointerValue<'ctx>,
+    ) -> Result<Dual<'ctx>, Box<CompileError>> {
+        let f32_type = self.context.f32_type();
+        let i64_type = self.context.i64_type();
+        let b = &self.builder;
+        for bound in [from, to] {
+            let is_whole = self.build_is_whole(bound);
+            self.build_clear_whole(whole, is_whole);
+        }
+        let (from, to) = (self.build_loop_bound(from), self.build_loop_bound(to));
+        let preheader = b.get_insert_block().unwrap();
+        let header = self.context.append_basic_block(function, "loop_header");
+        let body = self.context.append_basic_block(function, "loop_body");
+        let exit = self.context.append_basic_block(function, "loop_exit");
+        b.build_unconditional_branch(header).unwrap();
+
+        b.position_at_end(header);
+        let index = b.build_phi(i64_type, "index").unwrap();
+        let acc = b.build_phi(f32_type, "acc").unwrap();
+        let acc_tangents: Vec<_> = (0..width)
+            .map(|_| b.build_phi(f32_type, "acc_tangent").unwrap())
+            .collect();
+        let index_value = index.as_basic_value().into_int_value();
+        let acc_dual = Dual {
+            value: acc.as_basic_value().into_float_value(),
+            tangents: acc_tangents
+                .iter()
+                .map(|phi| phi.as_basic_value().into_float_value())
+                .collect(),
+            literal: false,
+        };
+        let in_range = b
+            .build_int_compare(IntPredicate::SLE, index_value, to, "in_range")
+            .unwrap();
+        b.build_conditional_branch(in_range, body, exit).unwrap();
+
+        b.position_at_end(body);
+        let index_float = b
+            .build_signed_int_to_float(index_value, f32_type, &reduction.index)
+            .unwrap();
+        // The index does not depend on any parameter.
+        let shadowed = scope.insert(
+            reduction.index.clone(),
+            self.dual_constant(index_float, width),
+        );
+        let term = self.build_dual_rpn(src, function, &reduction.body, scope, width, whole)?;
+        match shadowed {
+            Some(dual) => scope.insert(reduction.index.clone(), dual),
+            None => scope.remove(&reduction.index),
+        };
+        let op = match reduction.reduction {
+            Reduction::Sum => BinOp::Plus,
+            Reduction::Prod => BinOp::Times,
+        };
+        let next = self.build_dual_bin_op(&op, acc_dual.clone(), term);
+        let next_index = b
+            .build_int_add(index_value, i64_type.const_int(1, false), "next_index")
+            .unwrap();
+        let latch = b.get_insert_block().unwrap();
+        b.build_unconditional_branch(header).unwrap();
+
+        let identity = f32_type.const_float(reduction.reduction.identity() as f64);
+        let zero = f32_type.const_zero();
+        index.add_incoming(&[(&from, preheader), (&next_index, latch)]);
+        acc.add_incoming(&[(&identity, preheader), (&next.value, latch)]);
+        for (phi, tangent) in acc_tangents.iter().zip(&next.tangents) {
+            phi.add_incoming(&[(&zero, preheader), (tangent, latch)]);
+        }
+
+        b.position_at_end(exit);
+        Ok(acc_dual)
+    }
+
+    /// Whether `x` is a finite whole number, as `Compiler::eval_loop` requires of bounds.
+    fn build_is_whole(&self, x: FloatValue<'ctx>) -> IntValue<'ctx> {
+        let b = &self.builder;
+        let truncated = self.build_intrinsic_call("llvm.trunc", &[x]);
+        let magnitude = self.build_intrinsic_call("llvm.fabs", &[x]);
+        let infinity = self.context.f32_type().const_float(f64::INFINITY);
+        let same = b
+            .build_float_compare(FloatPredicate::OEQ, x, truncated, "same")
+            .unwrap();
+        let finite = b
+            .build_float_compare(FloatPredicate::ONE, magnitude, infinity, "finite")
+            .unwrap();
+        b.build_and(same, finite, "is_whole").unwrap()
+    }
+
+    /// Clears the flag behind `whole` unless `condition` holds.
+    fn build_clear_whole(&self, whole: PointerValue<'ctx>, condition: IntValue<'ctx>) {
+        let b = &self.builder;
+        let bool_type = self.context.bool_type();
+        let current = b
+            .build_load(bool_type, whole, "whole")
+            .unwrap()
+            .into_int_value();
+        let next = b.build_and(current, condition, "whole").unwrap();
+        b.build_store(whole, next).unwrap();
+    }
+}
@@ -1,17 +1,21 @@
 use std::error::Error;
 
-use crate::Span;
+use miette::{Diagnostic, LabeledSpan, NamedSource, SourceSpan};
+use thiserror::Error;
+
+use crate::{Location, Span};
 
 #[derive(Debug)]
 pub struct ParseError<'a> {
+    /// Where nom stopped, which only the `Debug` output shows.
+    #[allow(dead_code)]
     input: Span<'a>,
     message: Option<String>,
-}
-
-enum ParseErrorKind {
-    UnexpectedToken,
-    BadParentheses,
-    IncompleteFP,
+    /// Characters that are not part of any token, by offset. Lexing skips each of them and carries on,
+    /// so that they are all reported at once.
+    unexpected: Vec<(usize, char)>,
+    /// Where in the source the error is, with a label for it, when it is about one token.
+    location: Option<Box<(Location, String)>>,
 }
 
 impl<'a> ParseError<'a> {
@@ -19,8 +23,78 @@ impl<'a> ParseError<'a> {
         Self {
             input,
             message: Some(message),
+            unexpected: vec![],
+            location: None,
+        }
+    }
+
+    /// An error about the token at `location`, which is reported with `label` under it.
+    pub fn at(location: Location, message: String, label: &str) -> Self {
+        Self {
+            input: Span::new(""),
+            message: Some(message),
+            unexpected: vec![],
+            location: Some(Box::new((location, label.to_owned()))),
+        }
+    }
+
+    pub fn unexpected(unexpected: Vec<(usize, char)>) -> Self {
+        Self {
+            input: Span::new(""),
+            message: None,
+            unexpected,
+            location: None,
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn unexpected_chars(&self) -> &[(usize, char)] {
+        &self.unexpected
+    }
+
+    pub fn location(&self) -> Option<(Location, &str)> {
+        self.location
+            .as_ref()
+            .map(|labelled| (labelled.0, labelled.1.as_str()))
+    }
+
+    /// The diagnostic for this error in the program `src`, pointing at its location if it has one.
+    pub fn report(&self, src: &str) -> SyntaxError {
+        let (span, label) = match self.location() {
+            Some((location, label)) => (Some(location.into()), label.to_owned()),
+            None => (None, String::new()),
+        };
+        SyntaxError {
+            src: NamedSource::new("input", src.to_owned()),
+            message: self.message().unwrap_or("Invalid syntax").to_owned(),
+            span,
+            label,
+        }
+    }
+
+    /// The same error without the input it was found in, so it can outlive that input.
+    pub fn into_owned(self) -> ParseError<'static> {
+        ParseError {
+            input: Span::new(""),
+            message: self.message,
+            unexpected: self.unexpected,
+            location: self.location,
         }
     }
+
+    /// The same error, for an expression that is the text of `input` rather than a source of its own.
+    pub fn within(mut self, input: &Span) -> Self {
+        for (at, _) in &mut self.unexpected {
+            *at += input.location_offset();
+        }
+        if let Some(labelled) = &mut self.location {
+            labelled.0 = labelled.0.within(input);
+        }
+        self
+    }
 }
 
 impl<'a> std::fmt::Display for ParseError<'a> {
@@ -48,6 +122,8 @@ impl<'a> nom::error::ParseError<Span<'a>> for ParseError<'a> {
         Self {
             input,
             message: Some(format!("Parse Error: {:?}", kind)),
+            unexpected: vec![],
+            location: None,
         }
     }
 
@@ -55,51 +131,155 @@ impl<'a> nom::error::ParseError<Span<'a>> for ParseError<'a> {
         Self {
             input,
             message: Some(format!("Parse Error: {:?} + {:?}", kind, other.message)),
+            unexpected: other.unexpected,
+            location: other.location,
         }
     }
 }
 
 impl<'a, E: Error> nom::error::FromExternalError<Span<'a>, E> for ParseError<'a> {
-    fn from_external_error(input: Span<'a>, kind: nom::error::ErrorKind, e: E) -> Self {
+    fn from_external_error(input: Span<'a>, kind: nom::error::ErrorKind, _e: E) -> Self {
         Self {
             input,
             message: Some(format!("External Error: {:?}", kind.description())),
+            unexpected: vec![],
+            location: None,
         }
     }
 }
 
-// #[derive(Error, Debug, Diagnostic)]
-// #[error("Unexpected token here")]
-// #[diagnostic(code("Unexpected token found while lexing"), help("Try removing this"))]
-// pub struct UnexpectedTokenError {
-//     #[source_code]
-//     pub src: NamedSource<String>,
-//     #[label("here")]
-//     pub err_span: SourceSpan,
-// }
-
-// #[derive(Error, Debug, Diagnostic)]
-// #[error("Mismatched parenthesis here")]
-// #[diagnostic(
-//     code("Mismatched parenthesis found during evaluation"),
-//     help("Try removing this")
-// )]
-// pub struct BadParenthesesError {
-//     #[source_code]
-//     pub src: NamedSource<String>,
-//     #[label("here")]
-//     pub err_span: SourceSpan,
-// }
-
-// #[derive(Error, Debug, Diagnostic)]
-// #[error("Incomplete instance of a floating point number")]
-// #[diagnostic(
-//     code("Lone period found without both an integer and a fractional part"),
-//     help("Try completing this")
-// )]
-// pub struct IncompleteFPError {
-//     #[source_code]
-//     pub src: NamedSource<String>,
-//     #[label("here")]
-//     pub err_span: SourceSpan,
-// }
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Incompatible dimensions found during evaluation"))]
+pub struct DimensionError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label(collection)]
+    pub labels: Vec<LabeledSpan>,
+    #[help]
+    pub help: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("`{name}` is a built-in constant and cannot be reassigned")]
+#[diagnostic(
+    code("Assignment to a built-in constant"),
+    help("Try a different variable name, or run with --constants to list the reserved names")
+)]
+pub struct ConstantAssignmentError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub name: String,
+    #[label("{description}")]
+    pub span: SourceSpan,
+    pub description: &'static str,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Cannot compile expression"))]
+pub struct CompileError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label("{label}")]
+    pub span: SourceSpan,
+    pub label: String,
+    #[help]
+    pub help: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Integral did not converge"))]
+pub struct IntegrationError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label("{label}")]
+    pub span: SourceSpan,
+    pub label: String,
+    #[help]
+    pub help: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Incompatible shapes found during evaluation"))]
+pub struct ShapeError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label(collection)]
+    pub labels: Vec<LabeledSpan>,
+    #[help]
+    pub help: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Incompatible operands found during evaluation"))]
+pub struct TypeError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label(collection)]
+    pub labels: Vec<LabeledSpan>,
+    #[help]
+    pub help: Option<String>,
+}
+
+/// Anything that can go wrong while evaluating a parsed program.
+#[derive(Error, Debug, Diagnostic)]
+pub enum EvalError {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Compile(#[from] CompileError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Integration(#[from] IntegrationError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Shape(#[from] ShapeError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Type(#[from] TypeError),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Cannot lex expression"))]
+pub struct LexError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label(collection)]
+    pub labels: Vec<LabeledSpan>,
+    #[help]
+    pub help: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Cannot parse expression"))]
+pub struct SyntaxError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label("{label}")]
+    pub span: Option<SourceSpan>,
+    pub label: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code("Cannot format program"))]
+pub struct FormatError {
+    #[source_code]
+    pub src: NamedSource<String>,
+    pub message: String,
+    #[label("{label}")]
+    pub span: SourceSpan,
+    pub label: String,
+}
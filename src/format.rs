@@ -0,0 +1,476 @@
+use std::ops::Range;
+
+use miette::NamedSource;
+
+use crate::{
+    Location, Token, TokenType,
+    error::FormatError,
+    lexer::{self, BinOp, Builtin, Format, Lexer, Statement, from_superscript, to_superscript},
+};
+
+/// Prints programs the same way whoever wrote them: one statement per line, one space around
+/// operators and after commas, and only the parentheses that change how an expression is read.
+/// Comments are kept, either on their own line or after the statement they followed.
+pub struct Formatter<'a> {
+    src: &'a str,
+    unicode: bool,
+}
+
+/// An expression as it is written, without the parentheses around its parts.
+enum Tree {
+    /// A number, with its unit if it has one, or a name.
+    Atom(String),
+    BinOp(BinOp, Box<Tree>, Box<Tree>),
+    /// `-` in front of an operand, which takes a power after it along with it, as in `-x^2`.
+    Negate(Box<Tree>),
+    Call(String, Vec<Tree>),
+    Array(Vec<Tree>),
+    /// An expression followed by `in` or `to` and a unit, as written.
+    Convert(Box<Tree>, String),
+}
+
+impl<'a> Formatter<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            unicode: false,
+        }
+    }
+
+    /// Writes `×`, `÷`, `−`, `√` and superscript powers instead of their ASCII.
+    pub fn with_unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// The formatted program, which ends with a line break unless it is empty.
+    pub fn format(&self) -> miette::Result<String> {
+        if self.src.trim().is_empty() {
+            return Ok(String::new());
+        }
+        let lexer = Lexer::new(self.src);
+        // Reports every lexing error at once, with where it is. There is one lexed statement for each
+        // of `statements`, in the same order.
+        let lexed = lexer.lex().map_err(|e| *e)?;
+        let statements: Vec<Range<usize>> = lexer
+            .statements()
+            .iter()
+            .map(|statement| {
+                statement.location_offset()..statement.location_offset() + statement.len()
+            })
+            .collect();
+
+        // Each line with where it was in the source, and whether it is a comment that can follow a
+        // statement on its line.
+        let comments = lexer::comments(self.src);
+        let mut lines: Vec<(Range<usize>, String, bool)> = vec![];
+        for (range, statement) in statements.iter().zip(&lexed) {
+            // Comments inside a statement that spans lines go on their own lines before it.
+            for comment in comments.iter().filter(|c| range.contains(&c.start)) {
+                lines.push((range.start..range.start, self.comment(comment), false));
+            }
+            lines.push((range.clone(), self.statement(statement)?, false));
+        }
+        for comment in &comments {
+            if !statements
+                .iter()
+                .any(|range| range.contains(&comment.start))
+            {
+                lines.push((comment.clone(), self.comment(comment), true));
+            }
+        }
+        lines.sort_by_key(|(range, ..)| range.start);
+
+        let mut formatted = String::new();
+        let mut last: Option<(usize, bool)> = None;
+        for (range, line, is_comment) in lines {
+            if let Some((end, after_comment)) = last {
+                let gap = &self.src[end..range.start];
+                if is_comment && !after_comment && !gap.contains('\n') {
+                    formatted.push(' ');
+                } else {
+                    formatted.push('\n');
+                    // Blank lines are kept, though only one at a time.
+                    if gap.matches('\n').count() > 1 {
+                        formatted.push('\n');
+                    }
+                }
+            }
+            formatted.push_str(&line);
+            last = Some((range.end, is_comment));
+        }
+        if !formatted.is_empty() {
+            formatted.push('\n');
+        }
+        Ok(formatted)
+    }
+
+    fn comment(&self, range: &Range<usize>) -> String {
+        self.src[range.clone()].trim_end().to_owned()
+    }
+
+    fn statement(&self, statement: &Statement) -> miette::Result<String> {
+        let (prefix, tokens) = match statement {
+            Statement::Assign(id, tokens) => (format!("{} = ", self.text(id)), tokens),
+            Statement::Function(name, params, tokens) => (
+                format!(
+                    "{}({}) = ",
+                    self.text(name),
+                    params
+                        .iter()
+                        .map(|param| self.text(param))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                tokens,
+            ),
+            Statement::Print(tokens, format, _) => match format {
+                Format::Result => (String::new(), tokens),
+                Format::Value => ("print ".to_owned(), tokens),
+                Format::Labelled => ("display ".to_owned(), tokens),
+            },
+        };
+        let mut reader = Reader { tokens, at: 0 };
+        let tree = reader
+            .statement(self.src)
+            .map_err(|location| self.error(tokens, location))?;
+        Ok(format!("{prefix}{}", self.expression(&tree)))
+    }
+
+    /// Points at the token that could not be read, or at the end of the statement.
+    fn error(&self, tokens: &[Token], location: Option<Location>) -> FormatError {
+        let end = tokens
+            .last()
+            .and_then(|token| token.location)
+            .map(|location| Location::new(location.end, location.end));
+        let (span, label) = match location {
+            Some(location) => (location.into(), "unexpected here"),
+            None => (crate::span(end), "the statement ends too soon"),
+        };
+        FormatError {
+            src: NamedSource::new("input", self.src.to_owned()),
+            message: "Cannot format this statement".to_owned(),
+            span,
+            label: label.to_owned(),
+        }
+    }
+
+    fn text(&self, token: &Token) -> String {
+        match &token.token_type {
+            TokenType::Id(id) => id.clone(),
+            _ => token
+                .location
+                .map(|l| self.src[l.start..l.end].to_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn expression(&self, tree: &Tree) -> String {
+        match tree {
+            Tree::Atom(text) => text.clone(),
+            Tree::BinOp(op, u, v) => {
+                let left = match &**u {
+                    Tree::BinOp(child, ..) if !child.has_greater_precedence_than(op) => {
+                        format!("({})", self.expression(u))
+                    }
+                    Tree::Negate(_) if *op == BinOp::Power => format!("({})", self.expression(u)),
+                    Tree::Convert(..) => format!("({})", self.expression(u)),
+                    _ => self.expression(u),
+                };
+                if *op == BinOp::Power
+                    && let Some(power) = self.superscript(v)
+                {
+                    return format!("{left}{power}");
+                }
+                let right = match &**v {
+                    Tree::BinOp(child, ..) if op.has_greater_precedence_than(child) => {
+                        format!("({})", self.expression(v))
+                    }
+                    Tree::Convert(..) => format!("({})", self.expression(v)),
+                    _ => self.expression(v),
+                };
+                match (op, self.unicode) {
+                    (BinOp::Power, _) => format!("{left}^{right}"),
+                    (_, true) => format!("{left} {op:#} {right}"),
+                    (_, false) => format!("{left} {op} {right}"),
+                }
+            }
+            Tree::Negate(u) => {
+                let minus = if self.unicode { "−" } else { "-" };
+                match &**u {
+                    Tree::BinOp(op, ..) if *op != BinOp::Power => {
+                        format!("{minus}({})", self.expression(u))
+                    }
+                    Tree::Convert(..) => format!("{minus}({})", self.expression(u)),
+                    _ => format!("{minus}{}", self.expression(u)),
+                }
+            }
+            Tree::Call(name, args) if self.unicode && matches!(name.parse(), Ok(Builtin::Sqrt)) => {
+                match args.as_slice() {
+                    [arg @ (Tree::Atom(_) | Tree::Call(..))] => {
+                        format!("√{}", self.expression(arg))
+                    }
+                    _ => format!("√({})", self.list(args)),
+                }
+            }
+            Tree::Call(name, args) => format!("{name}({})", self.list(args)),
+            Tree::Array(entries) => format!("[{}]", self.list(entries)),
+            Tree::Convert(tree, unit) => format!("{} {unit}", self.expression(tree)),
+        }
+    }
+
+    /// A whole power in superscript, as in `x²` or `x⁻¹`, which is only written in Unicode.
+    fn superscript(&self, power: &Tree) -> Option<String> {
+        let text = match power {
+            Tree::Atom(text) => text.clone(),
+            Tree::Negate(u) if let Tree::Atom(text) = &**u => format!("-{text}"),
+            _ => return None,
+        };
+        let digits = text.strip_prefix('-').unwrap_or(&text);
+        let whole = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit());
+        (whole && self.unicode).then(|| text.chars().filter_map(to_superscript).collect())
+    }
+
+    fn list(&self, trees: &[Tree]) -> String {
+        trees
+            .iter()
+            .map(|tree| self.expression(tree))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Reads the tokens of a statement back into a `Tree`, applying operators in the order the parser
+/// does. Errors are the location of the token that could not be read, or `None` at the end.
+struct Reader<'t> {
+    tokens: &'t [Token],
+    at: usize,
+}
+
+impl<'t> Reader<'t> {
+    fn statement(&mut self, src: &str) -> Result<Tree, Option<Location>> {
+        let tree = self.expression(src)?;
+        match self.next() {
+            Some(token) => Err(token.location),
+            None => Ok(tree),
+        }
+    }
+
+    fn expression(&mut self, src: &str) -> Result<Tree, Option<Location>> {
+        let mut operands = vec![self.operand(src)?];
+        let mut operators: Vec<BinOp> = vec![];
+        loop {
+            match self.peek() {
+                Some(TokenType::BinOp(op)) => {
+                    let op = op.clone();
+                    self.at += 1;
+                    while operators
+                        .last()
+                        .is_some_and(|top| top.has_greater_precedence_than(&op))
+                    {
+                        Reader::reduce(&mut operands, &mut operators);
+                    }
+                    operators.push(op);
+                    operands.push(self.operand(src)?);
+                }
+                // Converting applies to everything before it, and what follows applies to the result.
+                Some(TokenType::Convert(_)) => {
+                    while !operators.is_empty() {
+                        Reader::reduce(&mut operands, &mut operators);
+                    }
+                    let location = self.next().and_then(|token| token.location).ok_or(None)?;
+                    let text = &src[location.start..location.end];
+                    let (keyword, unit) =
+                        text.split_once(char::is_whitespace).unwrap_or((text, ""));
+                    let tree = operands.pop().unwrap();
+                    operands.push(Tree::Convert(
+                        Box::new(tree),
+                        format!("{keyword} {}", unit.trim()),
+                    ));
+                }
+                _ => break,
+            }
+        }
+        while !operators.is_empty() {
+            Reader::reduce(&mut operands, &mut operators);
+        }
+        Ok(operands.pop().unwrap())
+    }
+
+    fn reduce(operands: &mut Vec<Tree>, operators: &mut Vec<BinOp>) {
+        let (op, v, u) = (
+            operators.pop().unwrap(),
+            operands.pop().unwrap(),
+            operands.pop().unwrap(),
+        );
+        operands.push(Tree::BinOp(op, Box::new(u), Box::new(v)));
+    }
+
+    fn operand(&mut self, src: &str) -> Result<Tree, Option<Location>> {
+        let token = self.next().ok_or(None)?;
+        let name = match &token.token_type {
+            TokenType::Num(_) => {
+                let location = token.location.ok_or(None)?;
+                let text = &src[location.start..location.end];
+                // A power written in superscript, whose `^` is implied.
+                let text: String = if text.chars().all(|c| from_superscript(c).is_some()) {
+                    text.chars().filter_map(from_superscript).collect()
+                } else {
+                    text.split_whitespace().collect::<Vec<_>>().join(" ")
+                };
+                return Ok(Tree::Atom(text));
+            }
+            TokenType::Id(id) => return Ok(Tree::Atom(id.clone())),
+            TokenType::Negate => return Ok(Tree::Negate(Box::new(self.power(src)?))),
+            TokenType::LeftParen => {
+                let tree = self.expression(src)?;
+                self.expect(TokenType::RightParen)?;
+                return Ok(tree);
+            }
+            TokenType::LeftBracket => {
+                let entries = self.list(src, TokenType::RightBracket)?;
+                return Ok(Tree::Array(entries));
+            }
+            TokenType::Builtin(builtin) => builtin.to_string(),
+            TokenType::LinAlg(linalg) => linalg.to_string(),
+            TokenType::Statistic(statistic, _) => statistic.to_string(),
+            TokenType::Reduction(reduction) => reduction.to_string(),
+            TokenType::Integrate => "integrate".to_owned(),
+            TokenType::Solve => "solve".to_owned(),
+            TokenType::Simplify => "simplify".to_owned(),
+            TokenType::Grad(..) => "grad".to_owned(),
+            TokenType::Derivative(Some(variable)) => format!("d/d{variable}"),
+            TokenType::Derivative(None) => "deriv".to_owned(),
+            TokenType::Call(name, _) => name.clone(),
+            _ => return Err(token.location),
+        };
+        self.expect(TokenType::LeftParen)?;
+        let args = self.list(src, TokenType::RightParen)?;
+        Ok(Tree::Call(name, args))
+    }
+
+    /// An operand and the powers it is raised to, which is what a `-` before it negates.
+    fn power(&mut self, src: &str) -> Result<Tree, Option<Location>> {
+        let base = self.operand(src)?;
+        if self.peek() != Some(&TokenType::BinOp(BinOp::Power)) {
+            return Ok(base);
+        }
+        self.at += 1;
+        let exponent = self.power(src)?;
+        Ok(Tree::BinOp(
+            BinOp::Power,
+            Box::new(base),
+            Box::new(exponent),
+        ))
+    }
+
+    /// Expressions separated by commas, up to and including `close`.
+    fn list(&mut self, src: &str, close: TokenType) -> Result<Vec<Tree>, Option<Location>> {
+        let mut trees = vec![];
+        if self.peek() == Some(&close) {
+            self.at += 1;
+            return Ok(trees);
+        }
+        loop {
+            trees.push(self.expression(src)?);
+            let token = self.next().ok_or(None)?;
+            match &token.token_type {
+                TokenType::Comma => (),
+                token_type if *token_type == close => return Ok(trees),
+                _ => return Err(token.location),
+            }
+        }
+    }
+
+    fn expect(&mut self, token_type: TokenType) -> Result<(), Option<Location>> {
+        let token = self.next().ok_or(None)?;
+        if token.token_type == token_type {
+            Ok(())
+        } else {
+            Err(token.location)
+        }
+    }
+
+    fn peek(&self) -> Option<&'t TokenType> {
+        self.tokens.get(self.at).map(|token| &token.token_type)
+    }
+
+    fn next(&mut self) -> Option<&'t Token> {
+        let token = self.tokens.get(self.at);
+        self.at += 1;
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(src: &str) -> String {
+        Formatter::new(src).format().unwrap()
+    }
+
+    #[test]
+    fn only_needed_parentheses() {
+        assert_eq!(format("((x+y))*(x-(y-1))"), "(x + y) * (x - (y - 1))\n");
+        assert_eq!(format("(a*b)+c"), "a * b + c\n");
+        // `^` is right-associative.
+        assert_eq!(format("2^(3^2); (2^3)^2"), "2^3^2\n(2^3)^2\n");
+        assert_eq!(format("f(x,y)=x*(y+1)"), "f(x, y) = x * (y + 1)\n");
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(format("-(x^2); (-x)^2"), "-x^2\n(-x)^2\n");
+        assert_eq!(
+            format("-(x+1)*2; 2*-3; 2^-x"),
+            "-(x + 1) * 2\n2 * -3\n2^-x\n"
+        );
+        assert_eq!(format("x⁻¹"), "x^-1\n");
+        let unicode = Formatter::new("x^-1 - -y").with_unicode(true).format();
+        assert_eq!(unicode.unwrap(), "x⁻¹ − −y\n");
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let src = "# Totals.\nrate=0.05 # yearly\n\n\n/* block */\ntotal=rate*2\n";
+        assert_eq!(
+            format(src),
+            "# Totals.\nrate = 0.05 # yearly\n\n/* block */\ntotal = rate * 2\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let src = "# Totals.\nrate=0.05 # yearly\nf(x)=(x+1)*x\n\n(a*b)+c; [1,2] @ [3,4]\n";
+        let formatted = format(src);
+        assert_eq!(format(&formatted), formatted);
+        let unicode = Formatter::new(src).with_unicode(true).format().unwrap();
+        assert_eq!(
+            Formatter::new(&unicode)
+                .with_unicode(true)
+                .format()
+                .unwrap(),
+            unicode
+        );
+    }
+
+    #[test]
+    fn lexing_errors_keep_their_span() {
+        let report = Formatter::new("1 + $").format().unwrap_err();
+        let labels: Vec<_> = report
+            .labels()
+            .unwrap()
+            .map(|label| (label.offset(), label.len()))
+            .collect();
+        assert_eq!(labels, [(4, 1)]);
+    }
+
+    #[test]
+    fn conversions_inside_expressions() {
+        assert_eq!(format("5 km in mi"), "5 km in mi\n");
+        assert_eq!(format("(5 km  in mi)*2"), "(5 km in mi) * 2\n");
+        assert_eq!(format("2*(x to m/s)"), "2 * (x to m/s)\n");
+        assert_eq!(format("[1 km in m,2]"), "[1 km in m, 2]\n");
+    }
+}
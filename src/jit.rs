@@ -0,0 +1,115 @@
+use std::ffi::{CStr, CString, c_char};
+
+use inkwell::OptimizationLevel;
+use inkwell::targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine};
+use llvm_sys::core::LLVMCreateMemoryBufferWithMemoryRangeCopy;
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMErrorRef, LLVMGetErrorMessage};
+use llvm_sys::orc2::lljit::{
+    LLVMOrcCreateLLJIT, LLVMOrcDisposeLLJIT, LLVMOrcLLJITAddObjectFile,
+    LLVMOrcLLJITGetGlobalPrefix, LLVMOrcLLJITGetMainJITDylib, LLVMOrcLLJITLookup, LLVMOrcLLJITRef,
+};
+use llvm_sys::orc2::{
+    LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess, LLVMOrcJITDylibAddGenerator,
+};
+
+/// How hard LLVM optimizes the machine code it emits.
+pub const OPTIMIZATION_LEVEL: OptimizationLevel = OptimizationLevel::Aggressive;
+
+/// A target machine for the architecture this runs on, which is what object code is emitted for. It
+/// targets a generic CPU of that architecture, which is much quicker to emit code for than the host's
+/// own; functions that are worth it ask for the host CPU through their attributes instead.
+pub fn host_machine() -> TargetMachine {
+    Target::initialize_native(&InitializationConfig::default())
+        .expect("Could not initialize the native target.");
+    let triple = TargetMachine::get_default_triple();
+    Target::from_triple(&triple)
+        .unwrap()
+        .create_target_machine(
+            &triple,
+            "",
+            "",
+            OPTIMIZATION_LEVEL,
+            RelocMode::Default,
+            CodeModel::JITDefault,
+        )
+        .expect("Could not create a target machine for the host.")
+}
+
+/// Object code linked into the running process, so that the functions in it can be called. It
+/// starts from object code rather than a module, which is what lets compiled programs be cached.
+pub struct Jit {
+    lljit: LLVMOrcLLJITRef,
+}
+
+impl Jit {
+    /// Links `object` against the symbols of the process, such as the `libm` functions that
+    /// intrinsics are lowered to.
+    pub fn load(object: &[u8]) -> Result<Self, String> {
+        Target::initialize_native(&InitializationConfig::default())?;
+        unsafe {
+            let mut lljit = std::ptr::null_mut();
+            check(LLVMOrcCreateLLJIT(&mut lljit, std::ptr::null_mut()))?;
+            // Disposed of by `drop` if anything below fails.
+            let jit = Jit { lljit };
+            let mut generator = std::ptr::null_mut();
+            check(LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                &mut generator,
+                LLVMOrcLLJITGetGlobalPrefix(lljit),
+                None,
+                std::ptr::null_mut(),
+            ))?;
+            let dylib = LLVMOrcLLJITGetMainJITDylib(lljit);
+            LLVMOrcJITDylibAddGenerator(dylib, generator);
+            // The JIT takes ownership of the buffer.
+            let buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
+                object.as_ptr() as *const c_char,
+                object.len(),
+                c"object".as_ptr(),
+            );
+            check(LLVMOrcLLJITAddObjectFile(lljit, dylib, buffer))?;
+            Ok(jit)
+        }
+    }
+
+    /// The function called `name`, which stays callable for as long as `self` is alive.
+    ///
+    /// # Safety
+    /// `F` must be the function pointer type the function was compiled with.
+    pub unsafe fn get<F: Copy>(&self, name: &str) -> F {
+        assert_eq!(
+            size_of::<F>(),
+            size_of::<usize>(),
+            "Not a function pointer."
+        );
+        let c_name = CString::new(name).unwrap();
+        let mut address = 0;
+        unsafe {
+            check(LLVMOrcLLJITLookup(
+                self.lljit,
+                &mut address,
+                c_name.as_ptr(),
+            ))
+            .unwrap_or_else(|e| panic!("Could not find `{name}`: {e}"));
+            std::mem::transmute_copy(&(address as usize))
+        }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // Nothing can be done about an error while the code is being unloaded.
+        let _ = unsafe { check(LLVMOrcDisposeLLJIT(self.lljit)) };
+    }
+}
+
+fn check(error: LLVMErrorRef) -> Result<(), String> {
+    if error.is_null() {
+        return Ok(());
+    }
+    unsafe {
+        let message = LLVMGetErrorMessage(error);
+        let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+        LLVMDisposeErrorMessage(message);
+        Err(text)
+    }
+}
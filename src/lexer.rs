@@ -1,25 +1,26 @@
-#![allow(unused_imports)]
+use std::ops::Range;
 
-use std::ops::Deref;
-use std::{collections::HashMap, fmt::Write, ops::Range};
-
-use nom::bytes::complete::{is_not, tag, take_until1};
+use nom::bytes::complete::tag;
 use nom::combinator::opt;
-use nom::multi::{many1, separated_list0};
-use nom::sequence::preceded;
+use nom::multi::separated_list1;
+use nom::sequence::terminated;
 use nom::{
-    Parser,
+    Input, Parser,
     branch::alt,
-    bytes::is_a,
-    character::complete::{alpha1, alphanumeric0, alphanumeric1, digit1},
-    combinator::{recognize, rest},
-    multi::many0,
+    character::complete::space1,
+    combinator::{peek, rest},
     sequence::{delimited, separated_pair},
 };
 
-use crate::Token;
+use miette::{LabeledSpan, NamedSource};
+
 use crate::math_lexing::MathLexer;
-use crate::{IResult, Span, error::ParseError, util::ws_tag};
+use crate::number::NumberMode;
+use crate::{
+    IResult, Location, Span, Token, TokenType,
+    error::LexError,
+    util::{identifier, ws_tag},
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinOp {
@@ -27,47 +28,361 @@ pub enum BinOp {
     Minus,
     Times,
     Divide,
+    Power,
     Equal,
+    /// `@`, matrix multiplication.
+    MatMul,
 }
 
+/// `{:#}` writes `×`, `÷` and `−` instead of their ASCII.
 impl std::fmt::Display for BinOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            &BinOp::Plus => f.write_str("+"),
-            &BinOp::Minus => f.write_str("-"),
-            &BinOp::Times => f.write_str("*"),
-            &BinOp::Divide => f.write_str("/"),
-            &BinOp::Equal => f.write_str("="),
+            BinOp::Plus => f.write_str("+"),
+            BinOp::Minus if f.alternate() => f.write_str("−"),
+            BinOp::Minus => f.write_str("-"),
+            BinOp::Times if f.alternate() => f.write_str("×"),
+            BinOp::Times => f.write_str("*"),
+            BinOp::Divide if f.alternate() => f.write_str("÷"),
+            BinOp::Divide => f.write_str("/"),
+            BinOp::Power => f.write_str("^"),
+            BinOp::Equal => f.write_str("="),
+            BinOp::MatMul => f.write_str("@"),
+        }
+    }
+}
+
+/// Unicode operators are read as their ASCII counterparts.
+impl std::str::FromStr for BinOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "+" => Ok(BinOp::Plus),
+            "-" | "−" => Ok(BinOp::Minus),
+            "*" | "×" => Ok(BinOp::Times),
+            "/" | "÷" => Ok(BinOp::Divide),
+            "^" => Ok(BinOp::Power),
+            "=" => Ok(BinOp::Equal),
+            "@" => Ok(BinOp::MatMul),
+            _ => Err(()),
         }
     }
 }
 
 impl BinOp {
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Equal => 0,
+            BinOp::Plus | BinOp::Minus => 1,
+            BinOp::Times | BinOp::Divide | BinOp::MatMul => 2,
+            BinOp::Power => 3,
+        }
+    }
+
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, BinOp::Power)
+    }
+
+    /// Whether `self`, already on the operator stack, must be applied before `other_op`.
     pub fn has_greater_precedence_than(&self, other_op: &Self) -> bool {
+        self.precedence() > other_op.precedence()
+            || (self.precedence() == other_op.precedence() && !other_op.is_right_associative())
+    }
+}
+
+/// Symbols pasted from documents, and the ASCII they stand for. There are no comparisons yet, so
+/// `≤`, `≥` and `≠` are only recognized to say so.
+pub const UNICODE_OPERATORS: &[(char, &str)] = &[
+    ('×', "*"),
+    ('÷', "/"),
+    ('−', "-"),
+    ('√', "sqrt"),
+    ('≤', "<="),
+    ('≥', ">="),
+    ('≠', "!="),
+];
+
+/// Superscripts, which write powers as in `x²`, and the characters they stand for.
+const SUPERSCRIPTS: [(char, char); 11] = [
+    ('⁰', '0'),
+    ('¹', '1'),
+    ('²', '2'),
+    ('³', '3'),
+    ('⁴', '4'),
+    ('⁵', '5'),
+    ('⁶', '6'),
+    ('⁷', '7'),
+    ('⁸', '8'),
+    ('⁹', '9'),
+    ('⁻', '-'),
+];
+
+/// The digit or minus sign a superscript stands for.
+pub fn from_superscript(c: char) -> Option<char> {
+    SUPERSCRIPTS
+        .iter()
+        .find(|(superscript, _)| *superscript == c)
+        .map(|(_, c)| *c)
+}
+
+/// The superscript of a digit or minus sign.
+pub fn to_superscript(c: char) -> Option<char> {
+    SUPERSCRIPTS
+        .iter()
+        .find(|(_, ascii)| *ascii == c)
+        .map(|(superscript, _)| *superscript)
+}
+
+/// Whether `c` is `≤`, `≥` or `≠`, which are only recognized to report that there are no
+/// comparisons.
+fn is_comparison(c: char) -> bool {
+    matches!(ascii_operator(c), Some("<=" | ">=" | "!="))
+}
+
+/// The ASCII that a Unicode operator stands for.
+pub fn ascii_operator(c: char) -> Option<&'static str> {
+    UNICODE_OPERATORS
+        .iter()
+        .find(|(unicode, _)| *unicode == c)
+        .map(|(_, ascii)| *ascii)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Re,
+    Im,
+    Abs,
+    Arg,
+    Conj,
+    Sqrt,
+    Exp,
+    Ln,
+}
+
+impl std::str::FromStr for Builtin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "re" => Ok(Builtin::Re),
+            "im" => Ok(Builtin::Im),
+            "abs" => Ok(Builtin::Abs),
+            "arg" => Ok(Builtin::Arg),
+            "conj" => Ok(Builtin::Conj),
+            "sqrt" => Ok(Builtin::Sqrt),
+            "exp" => Ok(Builtin::Exp),
+            "ln" => Ok(Builtin::Ln),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Builtin::Re => "re",
+            Builtin::Im => "im",
+            Builtin::Abs => "abs",
+            Builtin::Arg => "arg",
+            Builtin::Conj => "conj",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Exp => "exp",
+            Builtin::Ln => "ln",
+        })
+    }
+}
+
+/// Functions of vectors and matrices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinAlg {
+    Dot,
+    Cross,
+    Transpose,
+    Det,
+    Inv,
+}
+
+impl LinAlg {
+    pub fn arity(&self) -> usize {
+        match self {
+            LinAlg::Dot | LinAlg::Cross => 2,
+            LinAlg::Transpose | LinAlg::Det | LinAlg::Inv => 1,
+        }
+    }
+}
+
+impl std::str::FromStr for LinAlg {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(LinAlg::Dot),
+            "cross" => Ok(LinAlg::Cross),
+            "transpose" => Ok(LinAlg::Transpose),
+            "det" => Ok(LinAlg::Det),
+            "inv" => Ok(LinAlg::Inv),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for LinAlg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LinAlg::Dot => "dot",
+            LinAlg::Cross => "cross",
+            LinAlg::Transpose => "transpose",
+            LinAlg::Det => "det",
+            LinAlg::Inv => "inv",
+        })
+    }
+}
+
+/// Functions that summarise the entries of a vector. `sum` of a single vector is lexed as a `Reduction`
+/// and told apart by the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Mean,
+    Median,
+    Mode,
+    /// Sample variance and standard deviation, with `n - 1` in the denominator.
+    Variance,
+    Stddev,
+    /// Population variance and standard deviation, with `n` in the denominator.
+    PVariance,
+    PStddev,
+    Percentile,
+    Min,
+    Max,
+    Sum,
+    Count,
+    Histogram,
+}
+
+impl Statistic {
+    /// The fewest and most arguments, the first of which is always the vector.
+    pub fn arity(&self) -> (usize, usize) {
+        match self {
+            Statistic::Percentile => (2, 2),
+            Statistic::Histogram => (1, 2),
+            _ => (1, 1),
+        }
+    }
+}
+
+impl std::str::FromStr for Statistic {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Statistic::Mean),
+            "median" => Ok(Statistic::Median),
+            "mode" => Ok(Statistic::Mode),
+            "variance" => Ok(Statistic::Variance),
+            "stddev" => Ok(Statistic::Stddev),
+            "pvariance" => Ok(Statistic::PVariance),
+            "pstddev" => Ok(Statistic::PStddev),
+            "percentile" => Ok(Statistic::Percentile),
+            "min" => Ok(Statistic::Min),
+            "max" => Ok(Statistic::Max),
+            "count" => Ok(Statistic::Count),
+            "histogram" => Ok(Statistic::Histogram),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Statistic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Statistic::Mean => "mean",
+            Statistic::Median => "median",
+            Statistic::Mode => "mode",
+            Statistic::Variance => "variance",
+            Statistic::Stddev => "stddev",
+            Statistic::PVariance => "pvariance",
+            Statistic::PStddev => "pstddev",
+            Statistic::Percentile => "percentile",
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+            Statistic::Sum => "sum",
+            Statistic::Count => "count",
+            Statistic::Histogram => "histogram",
+        })
+    }
+}
+
+/// `sum(index, from, to, body)` and `prod(index, from, to, body)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reduction {
+    Sum,
+    Prod,
+}
+
+impl Reduction {
+    /// The value of an empty sum or product, which the loop starts from.
+    pub fn identity(&self) -> f32 {
         match self {
-            BinOp::Plus | BinOp::Minus => false,
-            BinOp::Times => !matches!(other_op, BinOp::Divide | BinOp::Times),
-            BinOp::Divide => !matches!(other_op, BinOp::Divide),
-            BinOp::Equal => false,
+            Reduction::Sum => 0.,
+            Reduction::Prod => 1.,
         }
     }
 }
 
+impl std::str::FromStr for Reduction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(Reduction::Sum),
+            "prod" => Ok(Reduction::Prod),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Reduction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Reduction::Sum => "sum",
+            Reduction::Prod => "prod",
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
-    Assign(String, Vec<Token>),
-    Print(Vec<Token>),
+    /// The target is kept as an `Id` token so errors can point at it.
+    Assign(Token, Vec<Token>),
+    /// `f(x, y) = ...`: the function's name, its parameters and its body. The name and parameters are
+    /// `Id` tokens.
+    Function(Token, Vec<Token>, Vec<Token>),
+    /// An expression whose value is printed, how it is printed, and its source text.
+    Print(Vec<Token>, Format, String),
+}
+
+/// How a printed value is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// An expression on its own, which prints as a result.
+    Result,
+    /// `print x`: the value alone.
+    Value,
+    /// `display x`: the expression, then its value.
+    Labelled,
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Value(f32),
     Id(String),
     BinOp(String),
 }
 
 pub struct Lexer<'a> {
     src: Span<'a>,
+    number_mode: NumberMode,
+    strict_ascii: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -75,76 +390,421 @@ impl<'a> Lexer<'a> {
         assert!(!src.is_empty());
         Self {
             src: Span::new(src),
+            number_mode: NumberMode::default(),
+            strict_ascii: false,
+        }
+    }
+
+    /// Selects the number type literals are lexed into.
+    pub fn with_number_mode(mut self, number_mode: NumberMode) -> Self {
+        self.number_mode = number_mode;
+        self
+    }
+
+    /// Rejects Unicode operators such as `×` and `x²` instead of reading them as their ASCII
+    /// counterparts.
+    pub fn with_strict_ascii(mut self, strict_ascii: bool) -> Self {
+        self.strict_ascii = strict_ascii;
+        self
+    }
+
+    /// Lexes every statement, and reports every character that is not part of a token at once. A
+    /// program of nothing but comments has no statements.
+    pub fn lex(&self) -> Result<Vec<Statement>, Box<LexError>> {
+        if self.strict_ascii {
+            self.check_ascii()?;
+        }
+        let mut statements = vec![];
+        let mut unexpected = vec![];
+        for statement in self.statements() {
+            match Lexer::lex_statement(statement, self.number_mode) {
+                Ok((_, lexed)) => statements.push(lexed),
+                Err(nom::Err::Error(e) | nom::Err::Failure(e))
+                    if !e.unexpected_chars().is_empty() =>
+                {
+                    unexpected.extend_from_slice(e.unexpected_chars());
+                }
+                Err(nom::Err::Error(e) | nom::Err::Failure(e)) if e.location().is_some() => {
+                    let (location, label) = e.location().unwrap();
+                    return Err(self.error(
+                        e.message().unwrap_or_default().to_owned(),
+                        vec![LabeledSpan::at(location.start..location.end, label)],
+                        None,
+                    ));
+                }
+                Err(e) => {
+                    let help = match &e {
+                        nom::Err::Error(e) | nom::Err::Failure(e) => e.message().map(str::to_owned),
+                        nom::Err::Incomplete(_) => None,
+                    };
+                    return Err(self.error(
+                        format!("`{}` is not a statement", statement.fragment()),
+                        vec![LabeledSpan::at(
+                            statement.location_offset()
+                                ..statement.location_offset() + statement.len(),
+                            "this statement",
+                        )],
+                        help,
+                    ));
+                }
+            }
+        }
+        match unexpected.as_slice() {
+            [] => Ok(statements),
+            _ if unexpected.iter().all(|(_, c)| is_comparison(*c)) => {
+                Err(self.unexpected("Comparisons are not supported".to_owned(), &unexpected))
+            }
+            [(_, c)] => Err(self.unexpected(format!("Unexpected character `{c}`"), &unexpected)),
+            _ => Err(self.unexpected("Unexpected characters".to_owned(), &unexpected)),
         }
     }
 
-    pub fn lex(mut self) -> IResult<'a, Vec<Statement>> {
-        let (_, statements) = self.lex_all()?;
-        assert!(!statements.is_empty());
-        Ok((Span::new(""), statements))
+    /// Reports every Unicode operator outside comments, each with the ASCII to write instead.
+    fn check_ascii(&self) -> Result<(), Box<LexError>> {
+        let text = strip_comments(self.src.fragment());
+        let mut labels = vec![];
+        let mut chars = text.char_indices().peekable();
+        while let Some((at, c)) = chars.next() {
+            if let Some(ascii) = ascii_operator(c) {
+                labels.push(LabeledSpan::at(
+                    at..at + c.len_utf8(),
+                    format!("write `{ascii}`"),
+                ));
+            } else if let Some(power) = from_superscript(c) {
+                // A power like `x¹⁰` is reported once.
+                let mut ascii = format!("^{power}");
+                let mut end = at + c.len_utf8();
+                while let Some(&(next, c)) = chars.peek()
+                    && let Some(power) = from_superscript(c)
+                {
+                    ascii.push(power);
+                    end = next + c.len_utf8();
+                    chars.next();
+                }
+                labels.push(LabeledSpan::at(at..end, format!("write `{ascii}`")));
+            }
+        }
+        let message = match labels.len() {
+            0 => return Ok(()),
+            1 => "Unicode operator with `--strict-ascii`",
+            _ => "Unicode operators with `--strict-ascii`",
+        };
+        Err(self.error(
+            message.to_owned(),
+            labels,
+            Some("Only ASCII operators are accepted.".to_owned()),
+        ))
     }
 
-    fn lex_all(mut self) -> IResult<'a, Vec<Statement>> {
-        let (_, statements) = separated_list0(ws_tag(";"), is_not(";")).parse(self.src)?;
-        let statements: Vec<Statement> = statements
+    fn unexpected(&self, message: String, unexpected: &[(usize, char)]) -> Box<LexError> {
+        let labels = unexpected
             .iter()
-            .map(|st| {
-                let (_, res) = Lexer::lex_statement(*st).unwrap();
-                res
+            .map(|(at, c)| {
+                let label = match ascii_operator(*c) {
+                    Some(ascii) => format!("`{ascii}` comparisons are not supported"),
+                    None => "not part of any expression".to_owned(),
+                };
+                LabeledSpan::at(*at..at + c.len_utf8(), label)
             })
             .collect();
+        let help = match unexpected.len() {
+            _ if unexpected.iter().all(|(_, c)| is_comparison(*c)) => {
+                "There are no comparisons yet, only arithmetic."
+            }
+            1 => "Try removing it.",
+            _ => "Try removing them.",
+        };
+        self.error(message, labels, Some(help.to_owned()))
+    }
 
-        Ok((Span::new(""), statements))
+    fn error(
+        &self,
+        message: String,
+        labels: Vec<LabeledSpan>,
+        help: Option<String>,
+    ) -> Box<LexError> {
+        Box::new(LexError {
+            src: NamedSource::new("input", self.src.fragment().to_string()),
+            message,
+            labels,
+            help,
+        })
     }
 
-    fn lex_statement(input: Span) -> IResult<Statement> {
+    pub(crate) fn lex_statement(input: Span, number_mode: NumberMode) -> IResult<Statement> {
         assert!(!input.is_empty());
 
-        if let Ok((input, (id, remaining))) =
-            separated_pair(alpha1::<Span, ParseError>, ws_tag("="), rest).parse(input)
+        let params = delimited(
+            ws_tag("("),
+            separated_list1(ws_tag(","), identifier),
+            ws_tag(")"),
+        );
+        if let Ok((input, ((name, params), remaining))) =
+            separated_pair((identifier, params), ws_tag("="), rest).parse(input)
+        {
+            // Function definition.
+            let (_, tokens) = Lexer::lex_math(remaining, number_mode)?;
+            let name = Token {
+                token_type: TokenType::Id(name.to_string()),
+                location: Some(Location::of(&name)),
+            };
+            let params = params
+                .iter()
+                .map(|param| Token {
+                    token_type: TokenType::Id(param.to_string()),
+                    location: Some(Location::of(param)),
+                })
+                .collect();
+            Ok((input, Statement::Function(name, params, tokens)))
+        } else if let Ok((input, (id, remaining))) =
+            separated_pair(identifier, ws_tag("="), rest).parse(input)
         {
             // Assignment.
-            let (_, value_expr) = Lexer::lex_expr(remaining)?;
-            let (_, tokens) = MathLexer::new(value_expr).lex()?;
-            Ok((input, Statement::Assign(id.to_string(), tokens)))
+            let (_, tokens) = Lexer::lex_math(remaining, number_mode)?;
+            let id = Token {
+                token_type: TokenType::Id(id.to_string()),
+                location: Some(Location::of(&id)),
+            };
+            Ok((input, Statement::Assign(id, tokens)))
         } else {
-            // Print (final statement).
+            // Print, either of a bare expression or with `print` or `display`.
+            let (input, keyword) = opt(terminated(
+                alt((tag("print"), tag("display"))),
+                alt((space1, peek(tag("(")))),
+            ))
+            .parse(input)?;
+            let format = match keyword.map(|keyword| *keyword.fragment()) {
+                Some("print") => Format::Value,
+                Some(_) => Format::Labelled,
+                None => Format::Result,
+            };
             let (_, input) = rest(input)?;
-            let (_, print_expr) = Lexer::lex_expr(input).unwrap();
-            let (_, tokens) = MathLexer::new(print_expr).lex()?;
-            Ok((input, Statement::Print(tokens)))
+            let (_, tokens) = Lexer::lex_math(input, number_mode)?;
+            let text = input.fragment().trim().to_owned();
+            Ok((input, Statement::Print(tokens, format, text)))
         }
     }
 
-    // TODO: handle recursion and fp.
+    /// The statements of the source, which end at a `;` or at the end of a line. A line carries on
+    /// onto the next when it ends inside brackets, after an operator or comma, or with a `\\`. Each
+    /// statement keeps its place in the source, and comments between statements are left out.
+    pub fn statements(&self) -> Vec<Span<'a>> {
+        let text = strip_comments(self.src.fragment());
+        let mut statements = vec![];
+        // The bytes of the statement so far, from its first character to its last.
+        let mut statement: Option<Range<usize>> = None;
+        let mut depth = 0;
+        let mut last = ' ';
+        let mut continued = false;
+        for (i, c) in text.char_indices().chain([(text.len(), ';')]) {
+            let unfinished = depth > 0 || continued || "+-*/^=@,×÷−√".contains(last);
+            if c == ';' || (c == '\n' && !unfinished) {
+                if let Some(range) = statement.take() {
+                    statements.push(self.src.take_from(range.start).take(range.len()));
+                }
+                (depth, last, continued) = (0, ' ', false);
+            } else if c == '\\' {
+                continued = true;
+            } else if !c.is_whitespace() {
+                match c {
+                    '(' | '[' => depth += 1,
+                    ')' | ']' => depth -= 1,
+                    _ => (),
+                }
+                let start = statement.map_or(i, |range| range.start);
+                statement = Some(start..i + c.len_utf8());
+                (last, continued) = (c, false);
+            }
+        }
+        statements
+    }
+
+    /// Lexes the expression `input`, with columns counted from the start of the whole source.
+    fn lex_math(input: Span, number_mode: NumberMode) -> IResult<Vec<Token>> {
+        let (_, expr) = Lexer::lex_expr(input)?;
+        let (_, tokens) = MathLexer::new(expr, number_mode)
+            .lex()
+            .map_err(|e| e.map(|e| e.within(&input)))?;
+        Ok((Span::new(""), Lexer::offset_tokens(tokens, input)))
+    }
+
+    /// `MathLexer` counts locations from the start of the expression `input`; this makes them relative
+    /// to the whole source.
+    fn offset_tokens(tokens: Vec<Token>, input: Span) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                token.location = token.location.map(|location| location.within(&input));
+                token
+            })
+            .collect()
+    }
+
+    // TODO: handle recursion.
     fn lex_expr(input: Span) -> IResult<Expr> {
-        if let Ok((input, val)) = digit1::<Span, ParseError>(input)
-            && input.is_empty()
-        {
-            // Is floating point.
-            Ok((Span::new(""), Expr::Value(input.parse::<f32>().unwrap())))
-        } else if let Ok((input, id)) = alpha1::<Span, ParseError>(input)
+        // Numbers are left to `MathLexer::lex_fp` so that every literal goes through the number mode.
+        if let Ok((input, id)) = identifier(input)
             && input.is_empty()
         {
             Ok((Span::new(""), Expr::Id(id.to_string())))
         } else {
             // Assume binary operation.
-            Ok((Span::new(""), Expr::BinOp(input.to_string())))
+            Ok((Span::new(""), Expr::BinOp(join_lines(input.fragment()))))
+        }
+    }
+
+    pub fn src(&self) -> Span<'a> {
+        self.src
+    }
+
+    pub fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+}
+
+/// An expression that spans several lines as a single line of the same length, without its comments
+/// or the `\\` that end lines.
+fn join_lines(text: &str) -> String {
+    let text = strip_comments(text);
+    let mut joined = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        let ends_line = || {
+            text[i + 1..]
+                .trim_start_matches([' ', '\t'])
+                .starts_with(['\n', '\r'])
+        };
+        match c {
+            '\n' | '\r' => joined.push(' '),
+            '\\' if ends_line() => joined.push(' '),
+            _ => joined.push(c),
         }
     }
+    joined
+}
+
+/// Where the `# ...` and `/* ... */` comments of `text` are. A `#` comment ends before its line
+/// break, and an unclosed `/*` comment runs to the end.
+pub fn comments(text: &str) -> Vec<Range<usize>> {
+    let mut comments = vec![];
+    let mut at = 0;
+    while let Some(start) = text[at..].find(['#', '/']).map(|start| at + start) {
+        let from = &text[start..];
+        let len = if from.starts_with('#') {
+            from.find('\n').unwrap_or(from.len())
+        } else if let Some(body) = from.strip_prefix("/*") {
+            body.find("*/").map_or(from.len(), |end| end + 4)
+        } else {
+            at = start + 1;
+            continue;
+        };
+        comments.push(start..start + len);
+        at = start + len;
+    }
+    comments
+}
 
-    fn lex_op(&self, input: Span<'a>) -> BinOp {
-        match *input.fragment() {
-            "+" => BinOp::Plus,
-            "-" => BinOp::Minus,
-            "*" => BinOp::Times,
-            "/" => BinOp::Divide,
-            "=" => BinOp::Equal,
-            _ => unreachable!("weird op"),
+/// Replaces `# ...` and `/* ... */` comments with spaces, keeping line breaks, so that every
+/// character outside them stays where it was.
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut at = 0;
+    for comment in comments(text) {
+        stripped.push_str(&text[at..comment.start]);
+        for c in text[comment.clone()].chars() {
+            match c {
+                '\n' | '\r' => stripped.push(c),
+                _ => stripped.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            }
         }
+        at = comment.end;
     }
+    stripped.push_str(&text[at..]);
+    stripped
+}
 
-    pub fn src(&self) -> Span<'a> {
-        self.src
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message of the error lexing `src`, and the offset and length of each of its labels.
+    fn error_spans(src: &str) -> (String, Vec<(usize, usize)>) {
+        let error = Lexer::new(src).lex().unwrap_err();
+        let spans = error
+            .labels
+            .iter()
+            .map(|label| (label.offset(), label.len()))
+            .collect();
+        (error.message, spans)
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let (message, spans) = error_spans("1 + $2");
+        assert_eq!(message, "Unexpected character `$`");
+        assert_eq!(spans, [(4, 1)]);
+    }
+
+    #[test]
+    fn every_unexpected_character() {
+        // Lexing carries on past each one to the end of the input, where the last one is.
+        let (message, spans) = error_spans("1 $ 2 ? x + ¤ &");
+        assert_eq!(message, "Unexpected characters");
+        assert_eq!(spans, [(2, 1), (6, 1), (12, 2), (15, 1)]);
+    }
+
+    #[test]
+    fn unexpected_characters_in_several_statements() {
+        let (message, spans) = error_spans("a = 1 $\nb = ?2; a + b &");
+        assert_eq!(message, "Unexpected characters");
+        assert_eq!(spans, [(6, 1), (12, 1), (22, 1)]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let (message, spans) = error_spans("1 ≤ 2; 3 ≠ 4");
+        assert_eq!(message, "Comparisons are not supported");
+        assert_eq!(spans, [(2, 3), (11, 3)]);
+        // Among other unexpected characters, a comparison is only labelled as one.
+        let (message, spans) = error_spans("1 ≥ 2 $");
+        assert_eq!(message, "Unexpected characters");
+        assert_eq!(spans, [(2, 3), (8, 1)]);
+    }
+
+    #[test]
+    fn keyword_as_a_name() {
+        let (message, spans) = error_spans("a = 1\nb = 2 * else");
+        assert_eq!(
+            message,
+            "`else` is a reserved keyword and cannot be used as a name"
+        );
+        assert_eq!(spans, [(14, 4)]);
+    }
+
+    #[test]
+    fn locations_are_counted_from_the_start_of_the_source() {
+        let statements = Lexer::new("x = 1\nx * 2").lex().unwrap();
+        let Statement::Print(tokens, ..) = &statements[1] else {
+            panic!("`x * 2` is not printed");
+        };
+        let spans: Vec<_> = tokens
+            .iter()
+            .map(|token| token.location.map(|location| location.start..location.end))
+            .collect();
+        assert_eq!(spans, [Some(6..7), Some(8..9), Some(10..11)]);
+    }
+
+    #[test]
+    fn lines_and_columns() {
+        let statements = Lexer::new("x = 1\n√x × 2").lex().unwrap();
+        let Statement::Print(tokens, ..) = &statements[1] else {
+            panic!("`√x × 2` is not printed");
+        };
+        let positions: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| token.location)
+            .map(|location| (location.line, location.column))
+            .collect();
+        // `√x` is `sqrt(x)`, and columns count characters, so `×` is at 4 although `√` takes three bytes.
+        assert_eq!(positions, [(2, 1), (2, 1), (2, 2), (2, 3), (2, 4), (2, 6)]);
     }
 }
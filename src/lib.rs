@@ -1,36 +1,174 @@
-#![allow(unused_variables)]
-#![allow(unused_mut)]
-#![allow(unused_imports)]
-#![allow(dead_code)]
-
 use error::ParseError;
-use nom::{Input, Parser};
+use miette::SourceSpan;
 use nom_locate::LocatedSpan;
 
-use crate::lexer::BinOp;
+use crate::{
+    ast::Node,
+    lexer::{BinOp, Builtin, LinAlg, Reduction, Statistic},
+    number::Number,
+    parse::{Equation, Integral, Loop},
+    units::Unit,
+};
 
+pub mod ast;
+pub mod cache;
 pub mod codegen;
+pub mod csv;
+pub mod dual;
 pub mod error;
+pub mod format;
+pub mod jit;
 pub mod lexer;
 pub mod math_lexing;
+pub mod matrix;
+pub mod number;
 pub mod parse;
+pub mod quadrature;
+pub mod roots;
+pub mod session;
+pub mod stats;
 pub mod symbols;
+pub mod units;
 pub mod util;
 
 type Span<'a> = LocatedSpan<&'a str>;
 pub type IResult<'a, O> = nom::IResult<Span<'a>, O, ParseError<'a>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     token_type: TokenType,
-    location_col: Option<usize>,
+    /// Where the token was written, or for a result, the expression it was computed from. Values that
+    /// do not come from the source, such as inputs, have none.
+    location: Option<Location>,
+}
+
+/// A range of bytes in the source, and the line and column it starts at, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    /// Counted in characters rather than bytes.
+    pub column: usize,
+}
+
+impl Location {
+    /// A range of a source that is a single line.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            line: 1,
+            column: start + 1,
+        }
+    }
+
+    pub fn of(span: &Span) -> Self {
+        Self {
+            start: span.location_offset(),
+            end: span.location_offset() + span.fragment().len(),
+            line: span.location_line(),
+            column: span.get_utf8_column(),
+        }
+    }
+
+    /// `self`, which was counted from the start of `span`'s text, counted from the start of the
+    /// source `span` is part of instead.
+    pub fn within(self, span: &Span) -> Self {
+        let before = &span.fragment()[..self.start];
+        let (line, column) = match before.rfind('\n') {
+            Some(newline) => (
+                span.location_line() + before.matches('\n').count() as u32,
+                before[newline + 1..].chars().count() + 1,
+            ),
+            None => (
+                span.location_line(),
+                span.get_utf8_column() + before.chars().count(),
+            ),
+        };
+        Self {
+            start: span.location_offset() + self.start,
+            end: span.location_offset() + self.end,
+            line,
+            column,
+        }
+    }
+
+    /// From the start of whichever of `self` and `other` comes first to the end of the other, which is
+    /// what a result computed from both of them covers.
+    pub fn to(self, other: Location) -> Self {
+        let (first, last) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Self {
+            end: first.end.max(last.end),
+            ..first
+        }
+    }
+}
+
+impl From<Location> for SourceSpan {
+    fn from(location: Location) -> Self {
+        (location.start, location.end - location.start).into()
+    }
+}
+
+/// Where a diagnostic points for a token at `location`, which is the start of the source if it has none.
+pub fn span(location: Option<Location>) -> SourceSpan {
+    location.map_or(SourceSpan::from(0), SourceSpan::from)
+}
+
+/// The byte offset of `location`, which is the start of the source if there is none.
+pub fn start(location: Option<Location>) -> usize {
+    location.map_or(0, |location| location.start)
+}
+
+/// What a result computed from operands at `x` and `y` covers.
+pub fn covering(x: Option<Location>, y: Option<Location>) -> Option<Location> {
+    match (x, y) {
+        (Some(x), Some(y)) => Some(x.to(y)),
+        (x, y) => x.or(y),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum TokenType {
-    Fp(f32),
+    Num(Number),
     Id(String),
     BinOp(BinOp),
+    /// `-` in front of an operand rather than between two, as in `-3` or `2^-x`.
+    Negate,
+    Builtin(Builtin),
+    LinAlg(LinAlg),
+    /// A statistic and, once parsed, the number of arguments it was called with.
+    Statistic(Statistic, usize),
+    /// `sum`/`prod` as lexed, before the parser gathers its arguments into a `Loop`.
+    Reduction(Reduction),
+    Loop(Box<Loop>),
+    /// `integrate` as lexed, before the parser gathers its arguments into an `Integral`.
+    Integrate,
+    Integral(Box<Integral>),
+    /// `solve` as lexed, before the parser gathers its arguments into an `Equation`.
+    Solve,
+    Equation(Box<Equation>),
+    /// `simplify` as lexed, and the simplified tree of its argument once parsed.
+    Simplify,
+    Simplified(Box<Node>),
+    /// A user function and, once parsed, the number of arguments it was called with.
+    Call(String, usize),
+    /// `grad(f, ...)`: the function and the number of arguments, filled in by the parser.
+    Grad(String, usize),
+    /// `d/dx` with its variable, or `deriv`, whose variable is its second argument.
+    Derivative(Option<String>),
+    Comma,
+    LeftBracket,
+    RightBracket,
+    /// A `[...]` literal once parsed, with its number of entries.
+    Array(usize),
+    /// `in`/`to` followed by the unit to display the result in.
+    Convert(Unit),
     LeftParen,
     RightParen,
 }
@@ -38,52 +176,23 @@ pub enum TokenType {
 impl PartialEq for TokenType {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::Fp(l0), Self::Fp(r0)) => l0 == r0,
+            (Self::Num(l0), Self::Num(r0)) => l0 == r0,
             (Self::Id(l0), Self::Id(r0)) => l0 == r0,
             (Self::BinOp(l0), Self::BinOp(r0)) => l0 == r0,
+            (Self::Builtin(l0), Self::Builtin(r0)) => l0 == r0,
+            (Self::LinAlg(l0), Self::LinAlg(r0)) => l0 == r0,
+            (Self::Statistic(l0, l1), Self::Statistic(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Reduction(l0), Self::Reduction(r0)) => l0 == r0,
+            (Self::Loop(l0), Self::Loop(r0)) => l0 == r0,
+            (Self::Integral(l0), Self::Integral(r0)) => l0 == r0,
+            (Self::Equation(l0), Self::Equation(r0)) => l0 == r0,
+            (Self::Simplified(l0), Self::Simplified(r0)) => l0 == r0,
+            (Self::Call(l0, l1), Self::Call(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Grad(l0, l1), Self::Grad(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Derivative(l0), Self::Derivative(r0)) => l0 == r0,
+            (Self::Array(l0), Self::Array(r0)) => l0 == r0,
+            (Self::Convert(l0), Self::Convert(r0)) => l0 == r0,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
 }
-
-// fn token_arr_to_number(numbers: &[Token]) -> (f32, usize) {
-//     assert!(!numbers.is_empty());
-//     let first_col = numbers[0].column;
-//     let grouped_number = numbers
-//         .iter()
-//         .map(|t| {
-//             if let Token {
-//                 kind: TokenKind::Numeric(Number(fp)),
-//                 ..
-//             } = t
-//             {
-//                 *fp
-//             } else {
-//                 panic!("Error: somehow pushed a not-number in tokens_to_group.");
-//             }
-//         })
-//         .enumerate()
-//         .fold(0, |acc: i32, n: (usize, f32)| acc * 10 + n.1 as i32);
-
-//     (grouped_number as f32, first_col)
-// }
-
-// fn alphabetical_arr_to_identifier(letters: &[Token]) -> Token {
-//     let col_to_use = letters.first().unwrap().col();
-//     let identifier = letters
-//         .iter()
-//         .map(|t| {
-//             if let TokenKind::Alphabetical(c) = t.kind {
-//                 c.to_string()
-//             } else {
-//                 unreachable!()
-//             }
-//         })
-//         .collect::<Vec<String>>()
-//         .join("");
-
-//     Token {
-//         kind: TokenKind::Identifier(identifier),
-//         column: col_to_use,
-//     }
-// }
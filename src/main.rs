@@ -1,19 +1,98 @@
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use inkwell::context::Context;
 use mathling::{
-    codegen::{CodeGen, Compiler},
-    lexer::Lexer,
+    cache::Cache,
+    codegen::{CodeGen, Compiler, Printed, RowsOutput},
+    csv::Table,
+    format::Formatter,
+    lexer::{Format, Lexer},
+    matrix::Matrix,
+    number::{DecimalConfig, Number, NumberMode},
+    session::Session,
+    symbols::CONSTANTS,
 };
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        panic!("usage: need a quoted math expression to evaluate");
+/// Variable bound to the numbers piped into standard input.
+const STDIN_VARIABLE: &str = "stdin";
+
+/// Name of the column `--csv` and `--tsv` add when `--column` does not give one.
+const DEFAULT_COLUMN: &str = "result";
+
+/// How mathling is run, shown when the arguments are not.
+const USAGE: &str = "usage: mathling [<option>...] [--] [<expression>]";
+
+/// Reports a mistake in how mathling was run, or in what it was given to read, and exits.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+/// `fail` for a diagnostic, which is shown with the source it points into.
+fn fail_report(report: miette::Report) -> ! {
+    fail(format!("{report:?}"))
+}
+
+/// Ends the program if writing to standard output failed: quietly when whatever was reading it has
+/// gone away, as `head` does, and with the error otherwise.
+fn written(result: std::io::Result<()>) {
+    match result {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => fail(format!("Could not write to standard output: {e}")),
+    }
+}
+
+/// `println!`, through `written`.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        written(writeln!(std::io::stdout().lock(), $($arg)*))
+    };
+}
+
+/// Takes `src` as the expression to run, of which there can only be one.
+fn set_expr(expr: &mut Option<String>, src: String) {
+    if expr.is_some() {
+        fail(format!(
+            "Only one expression can be given; separate statements with `;`.\n{USAGE}"
+        ));
     }
+    *expr = Some(src);
+}
 
-    let expr = args[1].clone();
-    assert!(!expr.is_empty(), "Expression cannot be empty.");
+fn read_stdin() -> String {
+    let mut input = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut input) {
+        fail(format!("Could not read standard input: {e}"));
+    }
+    input
+}
+
+/// Numbers separated by whitespace or commas, as in a column of data.
+fn stdin_numbers() -> Vec<f32> {
+    read_stdin()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.parse()
+                .unwrap_or_else(|_| fail(format!("`{word}` in standard input is not a number.")))
+        })
+        .collect()
+}
 
-    let lexer = Lexer::new(&expr);
+/// Sets up a compiler for `src` with the options every mode shares, and hands it to `run`.
+fn with_compiler<T>(
+    src: &str,
+    number_mode: NumberMode,
+    strict_ascii: bool,
+    cache: Option<&Cache>,
+    run: impl FnOnce(Compiler) -> T,
+) -> T {
+    let lexer = Lexer::new(src)
+        .with_number_mode(number_mode)
+        .with_strict_ascii(strict_ascii);
     let ctx = Context::create();
     let module = ctx.create_module("primary");
     let codegen = CodeGen {
@@ -21,9 +100,348 @@ fn main() {
         module,
         builder: ctx.create_builder(),
     };
+    let mut compiler = Compiler::new(src, codegen, lexer);
+    if let Some(cache) = cache {
+        compiler = compiler.with_cache(cache.clone());
+    }
+    run(compiler)
+}
+
+/// The line `printed` is shown as. Results follow `prefix`, or their expression when `label` is set.
+fn show(printed: &Printed, prefix: &str, label: bool) -> String {
+    match printed.format {
+        Format::Result if !label => format!("{prefix}{}", printed.value),
+        Format::Value => printed.value.to_string(),
+        Format::Result | Format::Labelled => format!("{} = {}", printed.text, printed.value),
+    }
+}
+
+/// Runs one program per line, each of which can use what the earlier ones defined.
+fn repl(
+    mut session: Session,
+    number_mode: NumberMode,
+    strict_ascii: bool,
+    cache: Option<Cache>,
+    label: bool,
+) {
+    let interactive = std::io::stdin().is_terminal();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            written(write!(std::io::stdout().lock(), "> "));
+            written(std::io::stdout().flush());
+        }
+        line.clear();
+        if std::io::stdin()
+            .read_line(&mut line)
+            .unwrap_or_else(|e| fail(format!("Could not read standard input: {e}")))
+            == 0
+        {
+            return;
+        }
+        let (command, argument) = match line.trim().split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, Some(argument.trim())),
+            None => (line.trim(), None),
+        };
+        match (command, argument) {
+            ("", _) => (),
+            (":quit", None) => return,
+            (":save", Some(path)) => {
+                if let Err(e) = session.save(Path::new(path)) {
+                    eprintln!("Could not write `{path}`: {e}");
+                }
+            }
+            (":load", Some(path)) => {
+                if let Err(report) = session.load(Path::new(path)) {
+                    eprintln!("{report:?}");
+                }
+            }
+            _ if command.starts_with(':') => {
+                eprintln!("The commands are `:save <file>`, `:load <file>` and `:quit`.");
+            }
+            _ => {
+                let src = line.trim();
+                let program = session.program(src);
+                let results = session.results();
+                let run = |compiler: Compiler| compiler.with_results(results).run_all();
+                match with_compiler(&program, number_mode, strict_ascii, cache.as_ref(), run) {
+                    Ok(outputs) => {
+                        for printed in outputs {
+                            out!("{}", show(&printed, "", label));
+                            session.push_result(printed.value);
+                        }
+                        for refused in session.record(src) {
+                            eprintln!("{refused}");
+                        }
+                    }
+                    Err(report) => eprintln!("{:?}", session.in_source(src, report)),
+                }
+            }
+        }
+    }
+}
+
+/// `mathling fmt [--check] [--unicode] [<file>...]` formats each file in place, or standard input
+/// onto standard output. With `--check`, nothing is written: the files that are not formatted are
+/// listed, and the exit status is 1 if there are any.
+fn fmt(args: impl Iterator<Item = String>) {
+    let mut check = false;
+    let mut unicode = false;
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--unicode" => unicode = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        paths.push("-".to_owned());
+    }
+    let mut unformatted = 0;
+    for path in &paths {
+        let src = match path.as_str() {
+            "-" => read_stdin(),
+            path => std::fs::read_to_string(path).unwrap_or_else(|e| fail(format!("{path}: {e}"))),
+        };
+        let formatted = match Formatter::new(&src).with_unicode(unicode).format() {
+            Ok(formatted) => formatted,
+            Err(report) => fail(format!("{path}: {report:?}")),
+        };
+        if check {
+            if formatted != src {
+                out!("{path}");
+                unformatted += 1;
+            }
+        } else if path == "-" {
+            written(write!(std::io::stdout().lock(), "{formatted}"));
+        } else if formatted != src
+            && let Err(e) = std::fs::write(path, formatted)
+        {
+            fail(format!("{path}: {e}"));
+        }
+    }
+    if unformatted > 0 {
+        fail(format!(
+            "{unformatted} of {} files are not formatted.",
+            paths.len()
+        ));
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        fmt(args);
+        return;
+    }
+    let mut expr = None;
+    let mut decimal = false;
+    let mut symbolic = false;
+    let mut config = DecimalConfig::default();
+    let mut table = None;
+    let mut column = DEFAULT_COLUMN.to_owned();
+    let mut cache = Cache::user();
+    let mut state = None;
+    let mut label = false;
+    let mut strict_ascii = false;
+    let mut unicode = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // `-` reads the table from standard input.
+            "--csv" => {
+                table = Some((
+                    args.next().unwrap_or_else(|| fail("usage: --csv <file>")),
+                    ',',
+                ))
+            }
+            "--tsv" => {
+                table = Some((
+                    args.next().unwrap_or_else(|| fail("usage: --tsv <file>")),
+                    '\t',
+                ))
+            }
+            "--file" => {
+                let path = args.next().unwrap_or_else(|| fail("usage: --file <path>"));
+                let src = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| fail(format!("Could not read `{path}`: {e}")));
+                set_expr(&mut expr, src);
+            }
+            "--column" => {
+                column = args
+                    .next()
+                    .unwrap_or_else(|| fail("usage: --column <name>"))
+            }
+            "--state" => {
+                state = Some(PathBuf::from(
+                    args.next().unwrap_or_else(|| fail("usage: --state <file>")),
+                ))
+            }
+            "--no-cache" => cache = None,
+            "--clear-cache" | "--prune-cache" => {
+                let age = match arg.as_str() {
+                    "--prune-cache" => {
+                        let days: u64 = args
+                            .next()
+                            .and_then(|days| days.parse().ok())
+                            .unwrap_or_else(|| fail("usage: --prune-cache <days unused>"));
+                        Duration::from_secs(days * 24 * 60 * 60)
+                    }
+                    _ => Duration::ZERO,
+                };
+                let cache = Cache::user().unwrap_or_else(|| fail("There is no cache directory."));
+                match cache.prune(age) {
+                    Ok(removed) => out!("Removed {removed} cached programs."),
+                    Err(e) => fail(format!("Could not prune the cache: {e}")),
+                }
+                return;
+            }
+            "--label" => label = true,
+            "--strict-ascii" => strict_ascii = true,
+            "--unicode" => unicode = true,
+            "--decimal" => decimal = true,
+            "--symbolic" => symbolic = true,
+            "--constants" => {
+                for constant in CONSTANTS {
+                    out!(
+                        "{:<10} {:<20} {}",
+                        constant.names.join(", "),
+                        constant.value,
+                        constant.description
+                    );
+                }
+                return;
+            }
+            "--scale" => {
+                let scale = args
+                    .next()
+                    .unwrap_or_else(|| fail("usage: --scale <digits>"));
+                config.scale = scale
+                    .parse()
+                    .unwrap_or_else(|_| fail("--scale expects a whole number."));
+                if config.scale > 18 {
+                    fail("--scale can be at most 18.");
+                }
+            }
+            "--rounding" => {
+                let rounding = args
+                    .next()
+                    .unwrap_or_else(|| fail("usage: --rounding <half-even|half-up|truncate>"));
+                config.rounding = rounding.parse().unwrap_or_else(|e| fail(e));
+            }
+            // Everything after `--` is the expression, even if it starts with `-`.
+            "--" => {
+                for arg in args.by_ref() {
+                    set_expr(&mut expr, arg);
+                }
+            }
+            _ if arg.strip_prefix('-').is_some_and(|rest| {
+                rest.starts_with(|c: char| c == '-' || c.is_ascii_alphabetic())
+            }) =>
+            {
+                fail(format!(
+                    "Unknown option `{arg}`. Put `--` before an expression that starts with `-`.\n{USAGE}"
+                ))
+            }
+            _ => set_expr(&mut expr, arg),
+        }
+    }
+    let number_mode = if decimal {
+        NumberMode::Decimal(config)
+    } else {
+        NumberMode::Float
+    };
 
-    let compiler = Compiler::new(&expr, codegen, lexer);
-    let (_, output) = compiler.run().unwrap();
+    let mut session = Session::new();
+    if let Some(path) = &state
+        && path.exists()
+        && let Err(report) = session.load(path)
+    {
+        fail_report(report);
+    }
+    // Without an expression, programs are read from standard input one line at a time.
+    let Some(expr) = expr else {
+        repl(session, number_mode, strict_ascii, cache, label);
+        return;
+    };
+    if expr.is_empty() {
+        fail("Expression cannot be empty.");
+    }
+    let program = session.program(&expr);
 
-    println!("Result: {}", output);
+    let uses_stdin = expr
+        .split(|c: char| !unicode_ident::is_xid_continue(c))
+        .any(|word| word == STDIN_VARIABLE);
+    if let Some((path, delimiter)) = table {
+        let text = match path.as_str() {
+            "-" => read_stdin(),
+            path => std::fs::read_to_string(path)
+                .unwrap_or_else(|e| fail(format!("Could not read `{path}`: {e}"))),
+        };
+        let table = Table::parse(&text, delimiter).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+        match with_compiler(
+            &program,
+            number_mode,
+            strict_ascii,
+            cache.as_ref(),
+            |compiler| compiler.run_rows(&table),
+        ) {
+            Ok(RowsOutput::Column(values)) => out!("{}", table.with_column(&column, &values)),
+            Ok(RowsOutput::Aggregate(number)) => out!("Result: {number}"),
+            Err(report) => fail_report(session.in_source(&expr, report)),
+        }
+    } else {
+        let input = (uses_stdin && !std::io::stdin().is_terminal()).then(|| {
+            let values = stdin_numbers();
+            if values.is_empty() {
+                fail("Standard input has no numbers.");
+            }
+            Number::Matrix(Matrix::vector(values))
+        });
+        let output = with_compiler(
+            &program,
+            number_mode,
+            strict_ascii,
+            cache.as_ref(),
+            |mut compiler| {
+                if let Some(values) = input {
+                    compiler = compiler.with_input(STDIN_VARIABLE, values);
+                }
+                if symbolic {
+                    compiler.simplified().map(|node| {
+                        let text = if unicode {
+                            format!("{node:#}")
+                        } else {
+                            node.to_string()
+                        };
+                        vec![(format!("Result: {text}"), Number::Symbolic(node))]
+                    })
+                } else {
+                    compiler.run_all().map(|outputs| {
+                        outputs
+                            .into_iter()
+                            .map(|printed| (show(&printed, "Result: ", label), printed.value))
+                            .collect()
+                    })
+                }
+            },
+        );
+        match output {
+            Ok(outputs) => {
+                for (line, value) in outputs {
+                    out!("{line}");
+                    session.push_result(value);
+                }
+            }
+            Err(report) => fail_report(session.in_source(&expr, report)),
+        }
+    }
+    if let Some(path) = &state {
+        for refused in session.record(&expr) {
+            eprintln!("{refused}");
+        }
+        session
+            .save(path)
+            .unwrap_or_else(|e| fail(format!("Could not write `{}`: {e}", path.display())));
+    }
 }
@@ -1,157 +1,713 @@
 use nom::{
-    Parser,
+    Input, Parser,
     branch::alt,
-    bytes::complete::is_a,
-    character::complete::{alpha0, alpha1, digit1},
-    combinator::{map_res, opt, recognize},
-    error::Error,
+    bytes::complete::{tag, take_while1},
+    character::complete::{alphanumeric1, digit1, one_of, space0, space1},
+    combinator::{not, opt, peek},
     multi::many0,
-    sequence::preceded,
+    sequence::{preceded, terminated},
 };
 
 use crate::{
-    IResult, Span, Token, TokenType,
+    IResult, Location, Span, Token, TokenType,
     error::ParseError,
-    lexer::{BinOp, Expr},
-    util::ws_tag,
+    lexer::{BinOp, Builtin, Expr, LinAlg, Reduction, Statistic, from_superscript},
+    number::{Complex, Decimal, Number, NumberMode},
+    symbols::lookup_constant,
+    units::{Quantity, Unit},
+    util::{identifier, word, ws_tag},
 };
 
 pub struct MathLexer {
     input: Expr,
+    number_mode: NumberMode,
 }
 
 impl MathLexer {
-    pub fn new(input: Expr) -> Self {
-        Self { input }
+    pub fn new(input: Expr, number_mode: NumberMode) -> Self {
+        Self { input, number_mode }
     }
 
-    pub fn lex(mut self) -> IResult<'static, Vec<Token>> {
+    pub fn lex(self) -> IResult<'static, Vec<Token>> {
         match self.input {
-            Expr::Value(v) => {
-                return Ok((
-                    Span::new(""),
-                    vec![Token {
-                        token_type: crate::TokenType::Fp(v),
-                        location_col: None,
-                    }],
-                ));
-            }
             Expr::Id(id) => {
-                return Ok((
+                let location = Location::new(0, id.len());
+                let token_type = match MathLexer::constant(&id, location, self.number_mode)? {
+                    Some(n) => TokenType::Num(n),
+                    None => TokenType::Id(id),
+                };
+                Ok((
                     Span::new(""),
                     vec![Token {
-                        token_type: crate::TokenType::Id(id),
-                        location_col: None,
+                        token_type,
+                        location: Some(location),
                     }],
-                ));
+                ))
             }
-            Expr::BinOp(bin_op) => MathLexer::lex_bin_op(bin_op),
+            Expr::BinOp(bin_op) => MathLexer::lex_bin_op(bin_op, self.number_mode),
         }
     }
 
-    fn lex_bin_op(input: String) -> IResult<'static, Vec<Token>> {
+    fn lex_bin_op(input: String, number_mode: NumberMode) -> IResult<'static, Vec<Token>> {
         let mut tokens = Vec::new();
+        let mut unexpected = vec![];
         let mut rest = Span::new(&input);
+        // How deep in parentheses the tokens so far are, and the depths at which each `√` still
+        // waits for its operand to end.
+        let mut depth = 0;
+        let mut roots = vec![];
         while !rest.fragment().is_empty() {
-            // Number.
-            match MathLexer::lex_fp(rest) {
-                Ok((input, fp)) => {
+            let start = rest.location_offset();
+            if let Ok((input, _)) = space1::<Span, ParseError>(rest) {
+                rest = input;
+            }
+            // Unit conversion, which applies to everything before it inside the same parentheses.
+            if let Ok((input, _)) =
+                terminated(alt((tag("in"), tag("to"))), space1::<Span, ParseError>).parse(rest)
+            {
+                let Ok((after, unit)) = MathLexer::lex_unit(input) else {
+                    let text = input
+                        .fragment()
+                        .split(|c: char| c.is_whitespace() || matches!(c, ')' | ']' | ','))
+                        .next()
+                        .unwrap_or_default();
+                    let start = input.location_offset();
+                    return Err(nom::Err::Failure(ParseError::at(
+                        Location::new(start, start + text.len()),
+                        format!("`{text}` is not a unit"),
+                        "unknown unit",
+                    )));
+                };
+                let location = Location::new(rest.location_offset(), after.location_offset());
+                if let NumberMode::Decimal(_) = number_mode {
+                    return Err(nom::Err::Failure(ParseError::at(
+                        Location::new(input.location_offset(), after.location_offset()),
+                        "Units are not supported in decimal mode".to_owned(),
+                        "this is a unit",
+                    )));
+                }
+                tokens.push(Token {
+                    token_type: TokenType::Convert(unit),
+                    location: Some(location),
+                });
+                rest = after;
+                continue;
+            }
+            // `d/dx(...)`.
+            if let Ok((input, (start, variable))) =
+                terminated((tag("d/d"), identifier), peek(tag("("))).parse(rest)
+            {
+                rest = input;
+                tokens.push(Token {
+                    token_type: TokenType::Derivative(Some(variable.to_string())),
+                    location: Some(Location::new(
+                        start.location_offset(),
+                        input.location_offset(),
+                    )),
+                });
+            }
+            // `√x`, which is `sqrt(x)` for the operand that follows.
+            if let Ok((input, root)) = ws_tag("√").parse(rest) {
+                rest = input;
+                for token_type in [TokenType::Builtin(Builtin::Sqrt), TokenType::LeftParen] {
+                    tokens.push(Token {
+                        token_type,
+                        location: Some(Location::of(&root)),
+                    });
+                }
+                depth += 1;
+                roots.push(depth);
+                continue;
+            }
+            // Number, optionally followed by a unit.
+            match MathLexer::lex_fp(rest, number_mode) {
+                Err(nom::Err::Failure(e)) => return Err(nom::Err::Failure(e.into_owned())),
+                Ok((input, mut fp)) => {
                     rest = input;
+                    if let Ok((input, unit)) = MathLexer::lex_unit(rest) {
+                        rest = input;
+                        if let Some(location) = &mut fp.location {
+                            location.end = rest.location_offset();
+                        }
+                        if let (NumberMode::Decimal(_), Some(location)) = (number_mode, fp.location)
+                        {
+                            return Err(nom::Err::Failure(ParseError::at(
+                                location,
+                                "Units are not supported in decimal mode".to_owned(),
+                                "this has a unit",
+                            )));
+                        }
+                        if let TokenType::Num(n) = &fp.token_type
+                            && let Some(q) = n.clone().to_quantity()
+                        {
+                            fp.token_type = TokenType::Num(Number::Quantity(Quantity::new(
+                                q.value * unit.factor,
+                                unit.dimension,
+                            )));
+                        }
+                    }
                     tokens.push(fp);
+                    MathLexer::close_roots(&mut tokens, &mut roots, &mut depth);
                 }
                 _ => (),
             }
-            // Identifier.
-            match alpha1::<Span, ParseError>.parse(rest) {
+            // Constant, identifier or built-in function call.
+            match identifier(rest) {
                 Ok((input, id)) => {
                     rest = input;
+                    let is_call = input.fragment().starts_with('(');
+                    let token_type = if is_call && let Ok(builtin) = id.parse::<Builtin>() {
+                        TokenType::Builtin(builtin)
+                    } else if is_call && let Ok(linalg) = id.parse::<LinAlg>() {
+                        TokenType::LinAlg(linalg)
+                    } else if is_call && let Ok(statistic) = id.parse::<Statistic>() {
+                        TokenType::Statistic(statistic, 0)
+                    } else if is_call && let Ok(reduction) = id.parse::<Reduction>() {
+                        TokenType::Reduction(reduction)
+                    } else if is_call && *id.fragment() == "deriv" {
+                        TokenType::Derivative(None)
+                    } else if is_call && *id.fragment() == "integrate" {
+                        TokenType::Integrate
+                    } else if is_call && *id.fragment() == "simplify" {
+                        TokenType::Simplify
+                    } else if is_call && *id.fragment() == "solve" {
+                        TokenType::Solve
+                    } else if is_call && *id.fragment() == "grad" {
+                        TokenType::Grad(String::new(), 0)
+                    } else if is_call {
+                        TokenType::Call(id.to_string(), 0)
+                    } else if let Some(n) =
+                        MathLexer::constant(id.fragment(), Location::of(&id), number_mode)?
+                    {
+                        TokenType::Num(n)
+                    } else {
+                        TokenType::Id(id.to_string())
+                    };
                     let val = Token {
-                        token_type: crate::TokenType::Id(id.to_string()),
-                        location_col: Some(input.location_offset() - id.to_string().len()),
+                        token_type,
+                        location: Some(Location::of(&id)),
                     };
                     tokens.push(val);
+                    if !is_call {
+                        MathLexer::close_roots(&mut tokens, &mut roots, &mut depth);
+                    }
                 }
+                // A keyword where a name was expected.
+                Err(nom::Err::Failure(e)) => return Err(nom::Err::Failure(e.into_owned())),
                 _ => (),
             }
             // Operator.
-            match map_res(
-                alt((ws_tag("+"), ws_tag("-"), ws_tag("*"), ws_tag("/"))),
-                |op| match *op {
-                    "+" => Ok::<BinOp, ParseError>(BinOp::Plus),
-                    "-" => Ok(BinOp::Minus),
-                    "*" => Ok(BinOp::Times),
-                    "/" => Ok(BinOp::Divide),
-                    _ => Err(ParseError::new(
-                        Span::new(""), // TODO: get real input.
-                        "found invalid operator".to_owned(),
-                    )),
-                },
-            )
+            let op_start =
+                rest.location_offset() + rest.fragment().len() - rest.fragment().trim_start().len();
+            let op_len = rest
+                .fragment()
+                .trim_start()
+                .chars()
+                .next()
+                .map_or(1, char::len_utf8);
+            if let Ok((input, op)) = alt((
+                ws_tag("+"),
+                ws_tag("-"),
+                ws_tag("*"),
+                ws_tag("/"),
+                ws_tag("^"),
+                ws_tag("="),
+                ws_tag("@"),
+                ws_tag("×"),
+                ws_tag("÷"),
+                ws_tag("−"),
+            ))
             .parse(rest)
             {
-                Ok((input, op)) => {
-                    rest = input;
-                    let val = Token {
-                        token_type: crate::TokenType::BinOp(op.clone()),
-                        location_col: Some(input.location_offset() - op.to_string().len()),
-                    };
-                    tokens.push(val);
-                }
-                _ => (),
+                rest = input;
+                let location = Location::new(op_start, op_start + op_len);
+                let Ok(op) = op.trim().parse::<BinOp>() else {
+                    return Err(nom::Err::Failure(ParseError::at(
+                        location,
+                        format!("`{}` is not an operator", op.trim()),
+                        "this operator",
+                    )));
+                };
+                // A minus sign with nothing to subtract from negates what follows it.
+                let prefix = tokens.last().is_none_or(|token| {
+                    matches!(
+                        token.token_type,
+                        TokenType::BinOp(_)
+                            | TokenType::Negate
+                            | TokenType::LeftParen
+                            | TokenType::LeftBracket
+                            | TokenType::Comma
+                    )
+                });
+                tokens.push(Token {
+                    token_type: match op {
+                        BinOp::Minus if prefix => TokenType::Negate,
+                        op => TokenType::BinOp(op),
+                    },
+                    location: Some(location),
+                });
             }
-            match many0(ws_tag("(")).parse(rest) {
-                Ok((input, captures)) => {
-                    rest = input;
-                    let val = Token {
+            // Brackets of vector and matrix literals, one at a time so each keeps its own location.
+            if let Ok((input, bracket)) = alt((ws_tag("["), ws_tag("]"))).parse(rest) {
+                rest = input;
+                tokens.push(Token {
+                    token_type: match *bracket.fragment() {
+                        "[" => TokenType::LeftBracket,
+                        _ => TokenType::RightBracket,
+                    },
+                    location: Some(Location::of(&bracket)),
+                });
+            }
+            // Argument separator of functions, and entry separator of vectors and matrices.
+            if let Ok((input, comma)) = ws_tag(",").parse(rest) {
+                rest = input;
+                tokens.push(Token {
+                    token_type: TokenType::Comma,
+                    location: Some(Location::of(&comma)),
+                });
+            }
+            if let Ok((input, captures)) = many0(ws_tag("(")).parse(rest) {
+                rest = input;
+                for paren in captures {
+                    tokens.push(Token {
                         token_type: crate::TokenType::LeftParen,
-                        location_col: Some(
-                            input.location_offset()
-                                - captures.first().unwrap_or(&Span::new("")).to_string().len(),
-                        ),
-                    };
-                    for _ in 0..captures.len() {
-                        tokens.push(val.clone());
-                    }
+                        location: Some(Location::of(&paren)),
+                    });
+                    depth += 1;
                 }
-                _ => (),
             }
-            match many0(ws_tag(")")).parse(rest) {
-                Ok((input, captures)) => {
-                    rest = input;
-                    let val = Token {
+            if let Ok((input, captures)) = many0(ws_tag(")")).parse(rest) {
+                rest = input;
+                for paren in captures {
+                    tokens.push(Token {
                         token_type: crate::TokenType::RightParen,
-                        location_col: Some(
-                            input.location_offset()
-                                - captures.first().unwrap_or(&Span::new("")).to_string().len(),
-                        ),
-                    };
-                    for _ in 0..captures.len() {
-                        tokens.push(val.clone());
+                        location: Some(Location::of(&paren)),
+                    });
+                    depth = depth.saturating_sub(1);
+                    MathLexer::close_roots(&mut tokens, &mut roots, &mut depth);
+                }
+            }
+            // A power written in superscript, as in `x²` or `x⁻¹`.
+            if let Ok((input, power)) =
+                take_while1::<_, _, ParseError>(|c| from_superscript(c).is_some()).parse(rest)
+            {
+                rest = input;
+                let ascii: String = power
+                    .fragment()
+                    .chars()
+                    .filter_map(from_superscript)
+                    .collect();
+                let location = Some(Location::of(&power));
+                let (negative, digits) = match ascii.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, ascii.as_str()),
+                };
+                let exponent = match MathLexer::lex_fp(Span::new(digits), number_mode) {
+                    Ok((rest, fp)) if rest.is_empty() => fp.token_type,
+                    Err(nom::Err::Failure(e)) => {
+                        let message = e.message().unwrap_or_default().to_owned();
+                        return Err(nom::Err::Failure(ParseError::at(
+                            Location::of(&power),
+                            message,
+                            "this power",
+                        )));
+                    }
+                    // A minus sign without digits, or in the middle of them.
+                    _ => {
+                        let c = power.fragment().chars().next().unwrap();
+                        unexpected.push((power.location_offset(), c));
+                        continue;
+                    }
+                };
+                // The sign belongs to the exponent, which is a single number.
+                let exponent = match exponent {
+                    TokenType::Num(Number::Float(n)) if negative => {
+                        TokenType::Num(Number::Float(-n))
                     }
+                    TokenType::Num(Number::Decimal(n)) if negative => {
+                        TokenType::Num(Number::Decimal(Decimal::from_units(-n.units(), n.config())))
+                    }
+                    exponent => exponent,
+                };
+                for token_type in [TokenType::BinOp(BinOp::Power), exponent] {
+                    tokens.push(Token {
+                        token_type,
+                        location,
+                    });
                 }
-                _ => (),
+            }
+            // Nothing above starts with this character, so it is skipped to lex the rest.
+            if rest.location_offset() == start {
+                let c = rest.fragment().chars().next().unwrap();
+                unexpected.push((start, c));
+                rest = rest.take_from(c.len_utf8());
             }
         }
 
+        if !unexpected.is_empty() {
+            return Err(nom::Err::Failure(ParseError::unexpected(unexpected)));
+        }
         Ok((Span::new(""), tokens))
     }
 
-    fn lex_fp(input: Span) -> IResult<Token> {
+    /// Ends each `√` whose operand has just ended at `depth`, as in `√x` or `√(x + 1)`.
+    fn close_roots(tokens: &mut Vec<Token>, roots: &mut Vec<usize>, depth: &mut usize) {
+        while roots.last() == Some(depth) {
+            roots.pop();
+            *depth -= 1;
+            let end = tokens
+                .last()
+                .and_then(|token| token.location)
+                .map(|l| l.end);
+            tokens.push(Token {
+                token_type: TokenType::RightParen,
+                location: end.map(|end| Location::new(end, end)),
+            });
+        }
+    }
+
+    /// A unit written after a number, as in `3 GiB` or `9.8 m/s^2`. Units joined by `*` or `/`
+    /// without spaces are one unit, each with an optional whole power. Anything that is not a known
+    /// unit is left alone.
+    fn lex_unit(input: Span) -> IResult<Unit> {
+        let is_unit = |name: Span, rest: Span| {
+            Unit::lookup(name.fragment()).is_some() && !rest.fragment().starts_with('(')
+        };
+        let power = || opt((tag("^"), opt(tag("-")), digit1::<Span, ParseError>));
+        let (mut rest, name) = preceded(space0, word).parse(input)?;
+        if !is_unit(name, rest) {
+            return Err(nom::Err::Error(ParseError::new(
+                input,
+                format!("`{name}` is not a unit"),
+            )));
+        }
+        (rest, _) = power().parse(rest)?;
+        while let Ok((after, (_, name))) = (one_of("*/"), word).parse(rest)
+            && is_unit(name, after)
+        {
+            (rest, _) = power().parse(after)?;
+        }
+        let text = &input.fragment()[name.location_offset() - input.location_offset()
+            ..rest.location_offset() - input.location_offset()];
+        match Unit::parse(text) {
+            Some(unit) => Ok((rest, unit)),
+            None => Err(nom::Err::Error(ParseError::new(
+                input,
+                format!("`{text}` is not a unit"),
+            ))),
+        }
+    }
+
+    fn lex_fp(input: Span, number_mode: NumberMode) -> IResult<Token> {
         let (rest, integral) = digit1.parse(input)?;
         let (rest, fractional) =
             opt(preceded(ws_tag("."), digit1::<Span, ParseError>)).parse(rest)?;
-        let fp = match fractional {
-            Some(fractional) => [integral.fragment(), *fractional].join("."),
-            None => integral.fragment().to_string(),
+        // `4i` is an imaginary literal, but `4in` is not.
+        let (rest, imaginary) =
+            opt(terminated(tag("i"), not(alphanumeric1::<Span, ParseError>))).parse(rest)?;
+        let location = Location::new(input.location_offset(), rest.location_offset());
+        let number = match number_mode {
+            NumberMode::Float => {
+                let fp = match fractional {
+                    Some(fractional) => [integral.fragment(), *fractional].join("."),
+                    None => integral.fragment().to_string(),
+                };
+                let fp = fp.parse::<f32>().unwrap();
+                match imaginary {
+                    Some(_) => Number::Complex(Complex::new(0., fp)),
+                    None => Number::Float(fp),
+                }
+            }
+            NumberMode::Decimal(_) if imaginary.is_some() => {
+                return Err(nom::Err::Failure(ParseError::at(
+                    location,
+                    "Complex numbers are not supported in decimal mode".to_owned(),
+                    "this is imaginary",
+                )));
+            }
+            // Parsed from the digits directly so literals like `0.1` stay exact.
+            NumberMode::Decimal(config) => {
+                let decimal = Decimal::from_literal(
+                    integral.fragment(),
+                    fractional.map(|f| *f.fragment()),
+                    config,
+                )
+                .map_err(|e| {
+                    nom::Err::Failure(ParseError::at(location, e.to_string(), "this literal"))
+                })?;
+                Number::Decimal(decimal)
+            }
         };
-        let fp = fp.parse::<f32>().unwrap();
 
         Ok((
             rest,
             Token {
-                token_type: TokenType::Fp(fp),
-                location_col: Some(rest.location_offset() - fp.to_string().len()),
+                token_type: TokenType::Num(number),
+                location: Some(location),
             },
         ))
     }
+
+    /// The value of the constant `id`, or an error at `location` if it has none in this number mode.
+    fn constant(
+        id: &str,
+        location: Location,
+        number_mode: NumberMode,
+    ) -> Result<Option<Number>, nom::Err<ParseError<'static>>> {
+        lookup_constant(id, number_mode).transpose().map_err(|e| {
+            nom::Err::Failure(ParseError::at(location, e.to_string(), "this constant"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+
+    /// The tokens of the expression `src` in float mode, each with the bytes it was lexed from.
+    fn lex(src: &str) -> Vec<(TokenType, Range<usize>)> {
+        let (_, tokens) = MathLexer::new(Expr::BinOp(src.to_owned()), NumberMode::Float)
+            .lex()
+            .unwrap();
+        tokens
+            .into_iter()
+            .map(|token| {
+                let location = token.location.unwrap();
+                (token.token_type, location.start..location.end)
+            })
+            .collect()
+    }
+
+    fn num(n: f32) -> TokenType {
+        TokenType::Num(Number::Float(n))
+    }
+
+    fn id(name: &str) -> TokenType {
+        TokenType::Id(name.to_owned())
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(lex("12.5"), [(num(12.5), 0..4)]);
+        assert_eq!(
+            lex("4i"),
+            [(TokenType::Num(Number::Complex(Complex::new(0., 4.))), 0..2)]
+        );
+    }
+
+    #[test]
+    fn number_with_unit() {
+        let km = Unit::parse("km").unwrap();
+        let quantity = Quantity::new(3. * km.factor, km.dimension);
+        assert_eq!(
+            lex("3 km + 1"),
+            [
+                (TokenType::Num(Number::Quantity(quantity)), 0..4),
+                (TokenType::BinOp(BinOp::Plus), 5..6),
+                (num(1.), 7..8),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversion() {
+        let mi = Unit::parse("mi").unwrap();
+        let km = Unit::parse("km").unwrap();
+        let quantity = Quantity::new(5. * km.factor, km.dimension);
+        assert_eq!(
+            lex("5 km in mi"),
+            [
+                (TokenType::Num(Number::Quantity(quantity.clone())), 0..4),
+                (TokenType::Convert(mi.clone()), 5..10),
+            ]
+        );
+        // The unit ends where the expression around the conversion goes on.
+        assert_eq!(
+            lex("(5 km in mi) * 2"),
+            [
+                (TokenType::LeftParen, 0..1),
+                (TokenType::Num(Number::Quantity(quantity)), 1..5),
+                (TokenType::Convert(mi), 6..11),
+                (TokenType::RightParen, 11..12),
+                (TokenType::BinOp(BinOp::Times), 13..14),
+                (num(2.), 15..16),
+            ]
+        );
+        let m_per_s = Unit::parse("m/s").unwrap();
+        assert_eq!(lex("x to m/s, 1")[1], (TokenType::Convert(m_per_s), 2..8));
+    }
+
+    #[test]
+    fn identifiers_and_constants() {
+        assert_eq!(
+            lex("rate_2 * pi"),
+            [
+                (id("rate_2"), 0..6),
+                (TokenType::BinOp(BinOp::Times), 7..8),
+                (num(std::f32::consts::PI), 9..11),
+            ]
+        );
+        // `Δ` is two bytes.
+        assert_eq!(
+            lex("Δt + 1"),
+            [
+                (id("Δt"), 0..3),
+                (TokenType::BinOp(BinOp::Plus), 4..5),
+                (num(1.), 6..7),
+            ]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let ops = [
+            BinOp::Plus,
+            BinOp::Minus,
+            BinOp::Times,
+            BinOp::Divide,
+            BinOp::Power,
+            BinOp::Equal,
+            BinOp::MatMul,
+        ];
+        let tokens = lex("0 + 1 - 2 * 3 / 4 ^ 5 = 6 @ 7");
+        let mut expected = vec![(num(0.), 0..1)];
+        for (n, op) in ops.into_iter().enumerate() {
+            let at = 4 * n + 2;
+            expected.push((TokenType::BinOp(op), at..at + 1));
+            expected.push((num(n as f32 + 1.), at + 2..at + 3));
+        }
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(
+            lex("-1 - −2"),
+            [
+                (TokenType::Negate, 0..1),
+                (num(1.), 1..2),
+                (TokenType::BinOp(BinOp::Minus), 3..4),
+                (TokenType::Negate, 5..8),
+                (num(2.), 8..9),
+            ]
+        );
+        assert_eq!(
+            lex("(-x)"),
+            [
+                (TokenType::LeftParen, 0..1),
+                (TokenType::Negate, 1..2),
+                (id("x"), 2..3),
+                (TokenType::RightParen, 3..4),
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode_operators() {
+        assert_eq!(
+            lex("1 ×"),
+            [(num(1.), 0..1), (TokenType::BinOp(BinOp::Times), 2..4)]
+        );
+        assert_eq!(
+            lex("1 ÷"),
+            [(num(1.), 0..1), (TokenType::BinOp(BinOp::Divide), 2..4)]
+        );
+        assert_eq!(
+            lex("1 −"),
+            [(num(1.), 0..1), (TokenType::BinOp(BinOp::Minus), 2..5)]
+        );
+    }
+
+    #[test]
+    fn square_root() {
+        assert_eq!(
+            lex("√x"),
+            [
+                (TokenType::Builtin(Builtin::Sqrt), 0..3),
+                (TokenType::LeftParen, 0..3),
+                (id("x"), 3..4),
+                (TokenType::RightParen, 4..4),
+            ]
+        );
+    }
+
+    #[test]
+    fn superscript_powers() {
+        assert_eq!(
+            lex("x²"),
+            [
+                (id("x"), 0..1),
+                (TokenType::BinOp(BinOp::Power), 1..3),
+                (num(2.), 1..3),
+            ]
+        );
+        assert_eq!(
+            lex("x⁻¹"),
+            [
+                (id("x"), 0..1),
+                (TokenType::BinOp(BinOp::Power), 1..6),
+                (num(-1.), 1..6),
+            ]
+        );
+    }
+
+    #[test]
+    fn parentheses_brackets_and_commas() {
+        assert_eq!(
+            lex("([1, 2])"),
+            [
+                (TokenType::LeftParen, 0..1),
+                (TokenType::LeftBracket, 1..2),
+                (num(1.), 2..3),
+                (TokenType::Comma, 3..4),
+                (num(2.), 5..6),
+                (TokenType::RightBracket, 6..7),
+                (TokenType::RightParen, 7..8),
+            ]
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let calls = [
+            ("sqrt", TokenType::Builtin(Builtin::Sqrt)),
+            ("det", TokenType::LinAlg(LinAlg::Det)),
+            ("mean", TokenType::Statistic(Statistic::Mean, 0)),
+            ("prod", TokenType::Reduction(Reduction::Prod)),
+            ("deriv", TokenType::Derivative(None)),
+            ("integrate", TokenType::Integrate),
+            ("simplify", TokenType::Simplify),
+            ("solve", TokenType::Solve),
+            ("grad", TokenType::Grad(String::new(), 0)),
+            ("f", TokenType::Call("f".to_owned(), 0)),
+        ];
+        for (name, token_type) in calls {
+            let len = name.len();
+            assert_eq!(
+                lex(&format!("{name}(x)")),
+                [
+                    (token_type, 0..len),
+                    (TokenType::LeftParen, len..len + 1),
+                    (id("x"), len + 1..len + 2),
+                    (TokenType::RightParen, len + 2..len + 3),
+                ],
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn derivative() {
+        assert_eq!(
+            lex("d/dx(x)"),
+            [
+                (TokenType::Derivative(Some("x".to_owned())), 0..4),
+                (TokenType::LeftParen, 4..5),
+                (id("x"), 5..6),
+                (TokenType::RightParen, 6..7),
+            ]
+        );
+    }
 }
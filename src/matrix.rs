@@ -0,0 +1,333 @@
+use crate::number::{Number, Shown};
+
+/// Whether a value is a vector or a matrix, and its size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Vector(usize),
+    /// Rows, then columns.
+    Matrix(usize, usize),
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Vector(n) => write!(f, "a vector of length {n}"),
+            Shape::Matrix(rows, cols) => write!(f, "a {rows}x{cols} matrix"),
+        }
+    }
+}
+
+/// Why a vector or matrix operation could not be carried out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatrixError {
+    /// The operands do not have the shapes the operation needs.
+    Shape,
+    /// The matrix has no inverse.
+    Singular,
+}
+
+/// A vector or matrix of real numbers, stored row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    pub shape: Shape,
+    pub data: Vec<f32>,
+}
+
+impl Matrix {
+    pub fn vector(data: Vec<f32>) -> Self {
+        Self {
+            shape: Shape::Vector(data.len()),
+            data,
+        }
+    }
+
+    pub fn new(rows: usize, cols: usize, data: Vec<f32>) -> Self {
+        assert_eq!(data.len(), rows * cols, "Ill-formed matrix.");
+        Self {
+            shape: Shape::Matrix(rows, cols),
+            data,
+        }
+    }
+
+    /// A vector counts as a single row.
+    pub fn rows(&self) -> usize {
+        match self.shape {
+            Shape::Vector(_) => 1,
+            Shape::Matrix(rows, _) => rows,
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        match self.shape {
+            Shape::Vector(n) => n,
+            Shape::Matrix(_, cols) => cols,
+        }
+    }
+
+    fn row(&self, row: usize) -> &[f32] {
+        &self.data[row * self.cols()..(row + 1) * self.cols()]
+    }
+
+    /// Pairs up the entries of two operands of an element-wise operation, in the shape of the result.
+    /// A vector meeting a matrix is repeated across its rows, so it needs one entry per column.
+    pub fn broadcast(&self, other: &Self) -> Result<(Shape, Vec<(f32, f32)>), MatrixError> {
+        match (self.shape, other.shape) {
+            (x, y) if x == y => Ok((
+                x,
+                self.data
+                    .iter()
+                    .copied()
+                    .zip(other.data.iter().copied())
+                    .collect(),
+            )),
+            (Shape::Matrix(_, cols), Shape::Vector(n)) if cols == n => Ok((
+                self.shape,
+                self.data
+                    .iter()
+                    .copied()
+                    .zip(other.data.iter().copied().cycle())
+                    .collect(),
+            )),
+            (Shape::Vector(n), Shape::Matrix(_, cols)) if cols == n => Ok((
+                other.shape,
+                self.data
+                    .iter()
+                    .copied()
+                    .cycle()
+                    .zip(other.data.iter().copied())
+                    .collect(),
+            )),
+            _ => Err(MatrixError::Shape),
+        }
+    }
+
+    /// `a @ b`. A vector on the left is a row and one on the right is a column, so two vectors give their dot product.
+    pub fn matmul(&self, other: &Self) -> Result<Number, MatrixError> {
+        if let (Shape::Vector(_), Shape::Vector(_)) = (self.shape, other.shape) {
+            return self.dot(other).map(Number::Float);
+        }
+        let inner = self.cols();
+        let other_rows = match other.shape {
+            Shape::Vector(n) => n,
+            Shape::Matrix(rows, _) => rows,
+        };
+        if inner != other_rows {
+            return Err(MatrixError::Shape);
+        }
+        let other_cols = match other.shape {
+            Shape::Vector(_) => 1,
+            Shape::Matrix(_, cols) => cols,
+        };
+        let data: Vec<f32> = (0..self.rows())
+            .flat_map(|row| {
+                (0..other_cols).map(move |col| {
+                    (0..inner)
+                        .map(|k| self.row(row)[k] as f64 * other.data[k * other_cols + col] as f64)
+                        .sum::<f64>() as f32
+                })
+            })
+            .collect();
+        Ok(Number::Matrix(match (self.shape, other.shape) {
+            (Shape::Matrix(rows, _), Shape::Matrix(..)) => Matrix::new(rows, other_cols, data),
+            _ => Matrix::vector(data),
+        }))
+    }
+
+    pub fn dot(&self, other: &Self) -> Result<f32, MatrixError> {
+        match (self.shape, other.shape) {
+            (Shape::Vector(n), Shape::Vector(m)) if n == m => Ok(self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| *a as f64 * *b as f64)
+                .sum::<f64>()
+                as f32),
+            _ => Err(MatrixError::Shape),
+        }
+    }
+
+    pub fn cross(&self, other: &Self) -> Result<Self, MatrixError> {
+        match (self.data.as_slice(), other.data.as_slice()) {
+            ([a1, a2, a3], [b1, b2, b3])
+                if self.shape == Shape::Vector(3) && other.shape == Shape::Vector(3) =>
+            {
+                Ok(Matrix::vector(vec![
+                    a2 * b3 - a3 * b2,
+                    a3 * b1 - a1 * b3,
+                    a1 * b2 - a2 * b1,
+                ]))
+            }
+            _ => Err(MatrixError::Shape),
+        }
+    }
+
+    /// A vector becomes a single column.
+    pub fn transpose(&self) -> Self {
+        let (rows, cols) = (self.rows(), self.cols());
+        let data = (0..cols)
+            .flat_map(|col| (0..rows).map(move |row| self.data[row * cols + col]))
+            .collect();
+        Matrix::new(cols, rows, data)
+    }
+
+    pub fn det(&self) -> Result<f32, MatrixError> {
+        let (det, _) = self.eliminate()?;
+        Ok(det as f32)
+    }
+
+    pub fn inv(&self) -> Result<Self, MatrixError> {
+        match self.eliminate()? {
+            (_, Some(inverse)) => Ok(inverse),
+            (_, None) => Err(MatrixError::Singular),
+        }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, in `f64`. Returns the determinant and,
+    /// unless it is zero, the inverse.
+    fn eliminate(&self) -> Result<(f64, Option<Self>), MatrixError> {
+        let n = match self.shape {
+            Shape::Matrix(rows, cols) if rows == cols => rows,
+            _ => return Err(MatrixError::Shape),
+        };
+        let mut a: Vec<Vec<f64>> = (0..n)
+            .map(|row| self.row(row).iter().map(|x| *x as f64).collect())
+            .collect();
+        let mut inverse: Vec<Vec<f64>> = (0..n)
+            .map(|row| (0..n).map(|col| (row == col) as u8 as f64).collect())
+            .collect();
+        // The entries are `f32`, so a pivot this small relative to them is rounding noise.
+        let scale = self.data.iter().fold(0f32, |max, x| max.max(x.abs())) as f64;
+        let threshold = n as f64 * f32::EPSILON as f64 * scale;
+
+        let mut det = 1.;
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() <= threshold {
+                return Ok((0., None));
+            }
+            if pivot != col {
+                a.swap(pivot, col);
+                inverse.swap(pivot, col);
+                det = -det;
+            }
+            let p = a[col][col];
+            det *= p;
+            for k in 0..n {
+                a[col][k] /= p;
+                inverse[col][k] /= p;
+            }
+            for row in (0..n).filter(|row| *row != col) {
+                let factor = a[row][col];
+                for k in 0..n {
+                    a[row][k] -= factor * a[col][k];
+                    inverse[row][k] -= factor * inverse[col][k];
+                }
+            }
+        }
+        let data = inverse.into_iter().flatten().map(|x| x as f32).collect();
+        Ok((det, Some(Matrix::new(n, n, data))))
+    }
+}
+
+impl std::fmt::Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Adding zero turns `-0` into `0`.
+        let row = |entries: &[f32]| {
+            let entries: Vec<String> = entries.iter().map(|x| Shown(x + 0.).to_string()).collect();
+            format!("[{}]", entries.join(", "))
+        };
+        match self.shape {
+            Shape::Vector(_) => f.write_str(&row(&self.data)),
+            Shape::Matrix(..) => {
+                let rows: Vec<String> = (0..self.rows()).map(|n| row(self.row(n))).collect();
+                write!(f, "[{}]", rows.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(x: &[f32], y: &[f32]) -> Vec<(f32, f32)> {
+        x.iter().copied().zip(y.iter().copied()).collect()
+    }
+
+    #[test]
+    fn broadcasting() {
+        let v = Matrix::vector(vec![1., 2.]);
+        let m = Matrix::new(2, 2, vec![3., 4., 5., 6.]);
+        assert_eq!(
+            v.broadcast(&Matrix::vector(vec![7., 8.])),
+            Ok((Shape::Vector(2), pairs(&[1., 2.], &[7., 8.])))
+        );
+        // A vector is repeated across the rows, on either side.
+        assert_eq!(
+            m.broadcast(&v),
+            Ok((
+                Shape::Matrix(2, 2),
+                pairs(&[3., 4., 5., 6.], &[1., 2., 1., 2.])
+            ))
+        );
+        assert_eq!(
+            v.broadcast(&m),
+            Ok((
+                Shape::Matrix(2, 2),
+                pairs(&[1., 2., 1., 2.], &[3., 4., 5., 6.])
+            ))
+        );
+        let wide = Matrix::new(1, 3, vec![1., 2., 3.]);
+        assert_eq!(
+            v.broadcast(&Matrix::vector(vec![1.])),
+            Err(MatrixError::Shape)
+        );
+        assert_eq!(wide.broadcast(&v), Err(MatrixError::Shape));
+        assert_eq!(m.broadcast(&wide), Err(MatrixError::Shape));
+    }
+
+    #[test]
+    fn products() {
+        let v = Matrix::vector(vec![1., 2.]);
+        let m = Matrix::new(2, 2, vec![1., 2., 3., 4.]);
+        assert_eq!(v.matmul(&v), Ok(Number::Float(5.)));
+        assert_eq!(
+            m.matmul(&v),
+            Ok(Number::Matrix(Matrix::vector(vec![5., 11.])))
+        );
+        assert_eq!(
+            v.matmul(&m),
+            Ok(Number::Matrix(Matrix::vector(vec![7., 10.])))
+        );
+        assert_eq!(
+            m.matmul(&m),
+            Ok(Number::Matrix(Matrix::new(2, 2, vec![7., 10., 15., 22.])))
+        );
+        let tall = Matrix::new(3, 1, vec![1., 2., 3.]);
+        assert_eq!(m.matmul(&tall), Err(MatrixError::Shape));
+        assert_eq!(v.dot(&Matrix::vector(vec![1.])), Err(MatrixError::Shape));
+        assert_eq!(
+            Matrix::vector(vec![1., 0., 0.]).cross(&Matrix::vector(vec![0., 1., 0.])),
+            Ok(Matrix::vector(vec![0., 0., 1.]))
+        );
+        assert_eq!(v.cross(&v), Err(MatrixError::Shape));
+        assert_eq!(v.transpose(), Matrix::new(2, 1, vec![1., 2.]));
+    }
+
+    #[test]
+    fn inverses() {
+        let m = Matrix::new(2, 2, vec![4., 7., 2., 6.]);
+        assert_eq!(m.det(), Ok(10.));
+        assert_eq!(m.inv(), Ok(Matrix::new(2, 2, vec![0.6, -0.7, -0.2, 0.4])));
+        let singular = Matrix::new(2, 2, vec![1., 2., 2., 4.]);
+        assert_eq!(singular.det(), Ok(0.));
+        assert_eq!(singular.inv(), Err(MatrixError::Singular));
+        assert_eq!(Matrix::vector(vec![1., 2.]).det(), Err(MatrixError::Shape));
+        assert_eq!(
+            Matrix::new(1, 2, vec![1., 2.]).inv(),
+            Err(MatrixError::Shape)
+        );
+    }
+}
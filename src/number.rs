@@ -0,0 +1,754 @@
+use std::str::FromStr;
+
+use crate::{
+    ast::Node,
+    lexer::Builtin,
+    matrix::Matrix,
+    stats::Histogram,
+    units::{Dimension, Quantity},
+};
+
+/// How a fractional result is brought back to the configured decimal scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    HalfEven,
+    HalfUp,
+    Truncate,
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "truncate" => Ok(RoundingMode::Truncate),
+            _ => Err(format!(
+                "unknown rounding mode `{s}` (expected half-even, half-up or truncate)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalConfig {
+    pub scale: u32,
+    pub rounding: RoundingMode,
+}
+
+impl Default for DecimalConfig {
+    fn default() -> Self {
+        Self {
+            scale: 2,
+            rounding: RoundingMode::HalfEven,
+        }
+    }
+}
+
+/// The number type every literal is lexed into and every operation is evaluated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    #[default]
+    Float,
+    Decimal(DecimalConfig),
+}
+
+/// Fixed-point base-10 number: `units / 10^scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+    units: i64,
+    config: DecimalConfig,
+}
+
+/// Why a decimal operation has no result. Decimals never round a result that does not fit, since a
+/// wrong amount of money is worse than none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecimalError {
+    Overflow,
+    DivisionByZero,
+    FractionalExponent,
+    NotFinite,
+    Unsupported(Builtin),
+}
+
+impl std::fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecimalError::Overflow => f.write_str("The result does not fit in a decimal"),
+            DecimalError::DivisionByZero => f.write_str("Division by zero"),
+            DecimalError::FractionalExponent => {
+                f.write_str("Decimal mode only supports whole-number exponents")
+            }
+            DecimalError::NotFinite => f.write_str("Decimal mode has no infinities or `nan`"),
+            DecimalError::Unsupported(builtin) => {
+                write!(f, "`{builtin}` is not supported in decimal mode")
+            }
+        }
+    }
+}
+
+impl Decimal {
+    pub fn from_units(units: i64, config: DecimalConfig) -> Self {
+        Self { units, config }
+    }
+
+    /// Builds a decimal from the digits of a literal, rounding away any digits past the scale.
+    pub fn from_literal(
+        integral: &str,
+        fractional: Option<&str>,
+        config: DecimalConfig,
+    ) -> Result<Self, DecimalError> {
+        let fractional = fractional.unwrap_or("");
+        let digits = [integral, fractional].concat();
+        let n = digits.parse::<i128>().map_err(|_| DecimalError::Overflow)?;
+        let literal_scale = fractional.len() as u32;
+        let units = if literal_scale <= config.scale {
+            n.checked_mul(pow10(config.scale - literal_scale))
+                .ok_or(DecimalError::Overflow)?
+        } else {
+            round_div(n, pow10(literal_scale - config.scale), config.rounding)
+        };
+
+        Ok(Self::from_units(narrow(units)?, config))
+    }
+
+    pub fn units(&self) -> i64 {
+        self.units
+    }
+
+    pub fn config(&self) -> DecimalConfig {
+        self.config
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    pub fn checked_abs(self) -> Result<Self, DecimalError> {
+        let units = self.units.checked_abs().ok_or(DecimalError::Overflow)?;
+        Ok(Self::from_units(units, self.config))
+    }
+
+    pub fn checked_add(self, other: Self) -> Result<Self, DecimalError> {
+        let units = self
+            .units
+            .checked_add(other.units)
+            .ok_or(DecimalError::Overflow)?;
+        Ok(Self::from_units(units, self.config))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Result<Self, DecimalError> {
+        let units = self
+            .units
+            .checked_sub(other.units)
+            .ok_or(DecimalError::Overflow)?;
+        Ok(Self::from_units(units, self.config))
+    }
+
+    pub fn checked_mul(self, other: Self) -> Result<Self, DecimalError> {
+        let product = self.units as i128 * other.units as i128;
+        let units = round_div(product, pow10(self.config.scale), self.config.rounding);
+        Ok(Self::from_units(narrow(units)?, self.config))
+    }
+
+    pub fn checked_div(self, other: Self) -> Result<Self, DecimalError> {
+        if other.is_zero() {
+            return Err(DecimalError::DivisionByZero);
+        }
+        let dividend = self.units as i128 * pow10(self.config.scale);
+        let units = round_div(dividend, other.units as i128, self.config.rounding);
+        Ok(Self::from_units(narrow(units)?, self.config))
+    }
+
+    /// Raises to a whole-number power by repeated multiplication, rounding after every step.
+    pub fn checked_powi(self, exp: Self) -> Result<Self, DecimalError> {
+        let one = Self::from_units(pow10(self.config.scale) as i64, self.config);
+        if exp.units % one.units != 0 {
+            return Err(DecimalError::FractionalExponent);
+        }
+        let n = exp.units / one.units;
+        let mut result = one;
+        for step in 0..n.unsigned_abs() {
+            let next = result.checked_mul(self)?;
+            // Once rounding stops changing the result, as for `0`, `1` or `0.5` at a scale of 2, the
+            // remaining steps would only repeat it, or flip its sign every step.
+            if next.units.unsigned_abs() == result.units.unsigned_abs() {
+                let flips = next.units != result.units;
+                let even = (n.unsigned_abs() - step).is_multiple_of(2);
+                result = if flips && even { result } else { next };
+                break;
+            }
+            result = next;
+        }
+        if n < 0 {
+            one.checked_div(result)
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// The built-ins that keep a decimal exact. The others would leave it rounded to the scale
+    /// while looking exact.
+    pub fn call(self, builtin: Builtin) -> Result<Self, DecimalError> {
+        match builtin {
+            Builtin::Abs => self.checked_abs(),
+            _ => Err(DecimalError::Unsupported(builtin)),
+        }
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scale = self.config.scale as usize;
+        let sign = if self.units < 0 { "-" } else { "" };
+        let digits = format!("{:0>width$}", self.units.unsigned_abs(), width = scale + 1);
+        let (integral, fractional) = digits.split_at(digits.len() - scale);
+        if fractional.is_empty() {
+            write!(f, "{sign}{integral}")
+        } else {
+            write!(f, "{sign}{integral}.{fractional}")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub const I: Complex = Complex { re: 0., im: 1. };
+
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn abs(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f32 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    /// Principal square root, with the branch cut along the negative real axis.
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.).sqrt();
+        let im = ((r - self.re) / 2.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+
+    pub fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Self::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+
+    pub fn ln(self) -> Self {
+        Self::new(self.abs().ln(), self.arg())
+    }
+
+    pub fn pow(self, exp: Self) -> Self {
+        // Zero to a positive power is zero, to the power of zero is one, and to a negative power is
+        // infinite. Any other exponent has no defined value.
+        if self == Complex::default() {
+            return match exp.re {
+                re if re > 0. => Complex::default(),
+                re if re == 0. && exp.im == 0. => Complex::new(1., 0.),
+                re if re < 0. && exp.im == 0. => Complex::new(f32::INFINITY, 0.),
+                _ => Complex::new(f32::NAN, f32::NAN),
+            };
+        }
+        // Whole-number powers are multiplied out so that `i^2` is exactly `-1`.
+        if exp.im == 0. && exp.re.fract() == 0. && exp.re.abs() <= 64. {
+            let mut result = Complex::new(1., 0.);
+            for _ in 0..exp.re.abs() as u32 {
+                result = result * self;
+            }
+            return if exp.re < 0. {
+                Complex::new(1., 0.) / result
+            } else {
+                result
+            };
+        }
+        (exp * self.ln()).exp()
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let denominator = other.re * other.re + other.im * other.im;
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+impl std::ops::Neg for Complex {
+    type Output = Self;
+
+    /// Subtracts from zero, so that `-3i` has no `-0` real part to print.
+    fn neg(self) -> Self {
+        Self::default() - self
+    }
+}
+
+/// An `f32` as results show it. `{x}` writes the fewest digits that tell a value apart from its
+/// neighbours and pads the rest of a whole number with zeros, so 2^28 would show as `268435460`.
+/// Whole numbers are written out in full instead, as long as `f64` holds every digit.
+#[derive(Debug, Clone, Copy)]
+pub struct Shown(pub f32);
+
+impl std::fmt::Display for Shown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Shown(x) = *self;
+        if x.fract() == 0. && x.abs() < 2f32.powi(53) {
+            write!(f, "{}", x as f64)
+        } else {
+            write!(f, "{x}")
+        }
+    }
+}
+
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.im.is_sign_negative() {
+            write!(f, "{} - {}i", Shown(self.re), Shown(-self.im))
+        } else {
+            write!(f, "{} + {}i", Shown(self.re), Shown(self.im))
+        }
+    }
+}
+
+/// The result of `grad`: a function's value at a point and its partial derivatives there, in parameter order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub value: f32,
+    pub gradient: Vec<f32>,
+}
+
+impl std::fmt::Display for Gradient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let partials: Vec<String> = self
+            .gradient
+            .iter()
+            .map(|d| Shown(*d).to_string())
+            .collect();
+        write!(
+            f,
+            "{} with gradient [{}]",
+            Shown(self.value),
+            partials.join(", ")
+        )
+    }
+}
+
+/// The result of `integrate`: its value and an estimate of the absolute error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub value: f32,
+    pub error: f32,
+}
+
+impl std::fmt::Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.error == 0. {
+            write!(f, "{} ± 0", Shown(self.value))
+        } else {
+            write!(f, "{} ± {:.1e}", Shown(self.value), self.error)
+        }
+    }
+}
+
+/// The result of `solve`: every root found, in ascending order of their real parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Roots {
+    pub variable: String,
+    pub roots: Vec<Number>,
+}
+
+impl std::fmt::Display for Roots {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let roots: Vec<String> = self
+            .roots
+            .iter()
+            .map(|root| format!("{} = {root}", self.variable))
+            .collect();
+        f.write_str(&roots.join(" or "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Float(f32),
+    Decimal(Decimal),
+    Complex(Complex),
+    Quantity(Quantity),
+    Gradient(Gradient),
+    Estimate(Estimate),
+    Roots(Roots),
+    /// The result of `simplify`, printed as an expression.
+    Symbolic(Node),
+    Matrix(Matrix),
+    Histogram(Histogram),
+}
+
+impl Number {
+    /// Widens a float so it can meet a complex operand. Anything else, such as a decimal or a
+    /// quantity, cannot be complex.
+    pub fn to_complex(self) -> Option<Complex> {
+        match self {
+            Number::Float(n) => Some(Complex::new(n, 0.)),
+            Number::Complex(c) => Some(c),
+            Number::Estimate(e) => Some(Complex::new(e.value, 0.)),
+            _ => None,
+        }
+    }
+
+    /// Treats a float as a dimensionless quantity so it can meet one with units. Nothing else can carry units.
+    pub fn to_quantity(self) -> Option<Quantity> {
+        match self {
+            Number::Float(n) => Some(Quantity::new(n, Dimension::NONE)),
+            Number::Quantity(q) => Some(q),
+            Number::Estimate(e) => Some(Quantity::new(e.value, Dimension::NONE)),
+            _ => None,
+        }
+    }
+
+    /// What kind of value this is, for errors about operands that do not fit together.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Number::Float(_) => "a real number",
+            Number::Decimal(_) => "a decimal",
+            Number::Complex(_) => "a complex number",
+            Number::Quantity(_) => "a quantity",
+            Number::Gradient(_) => "a gradient",
+            Number::Estimate(_) => "an integral",
+            Number::Roots(_) => "several roots",
+            Number::Symbolic(_) => "a simplified expression",
+            Number::Matrix(_) => "a vector or matrix",
+            Number::Histogram(_) => "a histogram",
+        }
+    }
+
+    /// Drops the error estimate of an integral, the variable of a single root, or the bins of a histogram, which
+    /// only the printed result keeps.
+    pub fn into_value(self) -> Self {
+        match self {
+            Number::Estimate(e) => Number::Float(e.value),
+            Number::Roots(mut r) if r.roots.len() == 1 => r.roots.remove(0),
+            Number::Histogram(h) => {
+                Number::Matrix(Matrix::vector(h.counts.iter().map(|c| *c as f32).collect()))
+            }
+            n => n,
+        }
+    }
+
+    /// `builtin` applied to a float, complex number or quantity, and `None` for anything it does not apply to.
+    /// Decimal built-ins can fail, so they go through `Decimal::call` instead.
+    pub fn call(self, builtin: Builtin) -> Option<Self> {
+        Some(match self {
+            Number::Float(x) => match builtin {
+                Builtin::Re | Builtin::Conj => Number::Float(x),
+                Builtin::Im => Number::Float(0.),
+                Builtin::Abs => Number::Float(x.abs()),
+                Builtin::Arg => Number::Float(0f32.atan2(x)),
+                Builtin::Sqrt if x < 0. => Number::Complex(Complex::new(0., (-x).sqrt())),
+                Builtin::Sqrt => Number::Float(x.sqrt()),
+                Builtin::Exp => Number::Float(x.exp()),
+                Builtin::Ln if x < 0. => Number::Complex(Complex::new(x, 0.).ln()),
+                Builtin::Ln => Number::Float(x.ln()),
+            },
+            Number::Complex(c) => match builtin {
+                Builtin::Re => Number::Float(c.re),
+                Builtin::Im => Number::Float(c.im),
+                Builtin::Abs => Number::Float(c.abs()),
+                Builtin::Arg => Number::Float(c.arg()),
+                Builtin::Conj => Number::Complex(c.conj()),
+                Builtin::Sqrt => Number::Complex(c.sqrt()),
+                Builtin::Exp => Number::Complex(c.exp()),
+                Builtin::Ln => Number::Complex(c.ln()),
+            },
+            Number::Quantity(q) => match builtin {
+                Builtin::Re | Builtin::Conj => Number::Quantity(q),
+                Builtin::Im => Quantity::new(0., q.dimension).into_number(),
+                Builtin::Abs => Quantity::new(q.value.abs(), q.dimension).into_number(),
+                Builtin::Sqrt => {
+                    Quantity::new(q.value.sqrt(), q.dimension.powf(0.5)?).into_number()
+                }
+                _ => return None,
+            },
+            Number::Estimate(e) => return Number::Float(e.value).call(builtin),
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Float(n) => write!(f, "{}", Shown(*n)),
+            Number::Decimal(d) => write!(f, "{d}"),
+            Number::Complex(c) => write!(f, "{c}"),
+            Number::Quantity(q) => write!(f, "{q}"),
+            Number::Gradient(g) => write!(f, "{g}"),
+            Number::Estimate(e) => write!(f, "{e}"),
+            Number::Roots(r) => write!(f, "{r}"),
+            Number::Symbolic(node) => write!(f, "{node}"),
+            Number::Matrix(m) => write!(f, "{m}"),
+            Number::Histogram(h) => write!(f, "{h}"),
+        }
+    }
+}
+
+pub fn pow10(exp: u32) -> i128 {
+    10i128.pow(exp)
+}
+
+/// Integer division that rounds according to `rounding`. `CodeGen::build_round_div` emits the same logic in IR.
+pub fn round_div(n: i128, d: i128, rounding: RoundingMode) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    let twice_r = 2 * r.abs();
+    let bump = match rounding {
+        RoundingMode::Truncate => false,
+        RoundingMode::HalfUp => twice_r >= d.abs(),
+        RoundingMode::HalfEven => twice_r > d.abs() || (twice_r == d.abs() && q % 2 != 0),
+    };
+    if !bump {
+        q
+    } else if (n < 0) != (d < 0) {
+        q - 1
+    } else {
+        q + 1
+    }
+}
+
+fn narrow(units: i128) -> Result<i64, DecimalError> {
+    i64::try_from(units).map_err(|_| DecimalError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(scale: u32, rounding: RoundingMode) -> DecimalConfig {
+        DecimalConfig { scale, rounding }
+    }
+
+    fn decimal(literal: &str, config: DecimalConfig) -> Decimal {
+        let (integral, fractional) = match literal.split_once('.') {
+            Some((integral, fractional)) => (integral, Some(fractional)),
+            None => (literal, None),
+        };
+        let d =
+            Decimal::from_literal(integral.trim_start_matches('-'), fractional, config).unwrap();
+        if literal.starts_with('-') {
+            Decimal::from_units(-d.units(), config)
+        } else {
+            d
+        }
+    }
+
+    #[test]
+    fn rounding_modes() {
+        use RoundingMode::*;
+        // Halfway cases, either side of zero.
+        assert_eq!(round_div(25, 10, HalfEven), 2);
+        assert_eq!(round_div(35, 10, HalfEven), 4);
+        assert_eq!(round_div(-25, 10, HalfEven), -2);
+        assert_eq!(round_div(-35, 10, HalfEven), -4);
+        assert_eq!(round_div(25, 10, HalfUp), 3);
+        assert_eq!(round_div(-25, 10, HalfUp), -3);
+        assert_eq!(round_div(25, -10, HalfUp), -3);
+        assert_eq!(round_div(29, 10, Truncate), 2);
+        assert_eq!(round_div(-29, 10, Truncate), -2);
+        // Off the halfway point, the modes that round agree.
+        assert_eq!(round_div(26, 10, HalfEven), 3);
+        assert_eq!(round_div(-24, 10, HalfUp), -2);
+    }
+
+    #[test]
+    fn literals_round_to_the_scale() {
+        let even = config(2, RoundingMode::HalfEven);
+        assert_eq!(decimal("1.005", even).to_string(), "1.00");
+        assert_eq!(decimal("1.015", even).to_string(), "1.02");
+        assert_eq!(
+            decimal("1.005", config(2, RoundingMode::HalfUp)).to_string(),
+            "1.01"
+        );
+        assert_eq!(
+            decimal("1.009", config(2, RoundingMode::Truncate)).to_string(),
+            "1.00"
+        );
+        assert_eq!(decimal("7", even).to_string(), "7.00");
+        assert_eq!(
+            decimal("-0.5", config(0, RoundingMode::HalfEven)).to_string(),
+            "0"
+        );
+        assert_eq!(decimal("-0.05", even).to_string(), "-0.05");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let even = config(2, RoundingMode::HalfEven);
+        let (a, b) = (decimal("1.25", even), decimal("-0.50", even));
+        assert_eq!(a.checked_add(b).unwrap().to_string(), "0.75");
+        assert_eq!(a.checked_sub(b).unwrap().to_string(), "1.75");
+        // 1.25 * -0.5 is -0.625, halfway, and 2 is even.
+        assert_eq!(a.checked_mul(b).unwrap().to_string(), "-0.62");
+        let up = config(2, RoundingMode::HalfUp);
+        assert_eq!(
+            decimal("1.25", up)
+                .checked_mul(decimal("-0.50", up))
+                .unwrap()
+                .to_string(),
+            "-0.63"
+        );
+        assert_eq!(
+            decimal("1", even)
+                .checked_div(decimal("3", even))
+                .unwrap()
+                .to_string(),
+            "0.33"
+        );
+        assert_eq!(
+            decimal("-2", even)
+                .checked_div(decimal("3", even))
+                .unwrap()
+                .to_string(),
+            "-0.67"
+        );
+        let truncate = config(2, RoundingMode::Truncate);
+        assert_eq!(
+            decimal("-2", truncate)
+                .checked_div(decimal("3", truncate))
+                .unwrap()
+                .to_string(),
+            "-0.66"
+        );
+        assert_eq!(b.checked_abs().unwrap().to_string(), "0.50");
+    }
+
+    #[test]
+    fn errors() {
+        let even = config(2, RoundingMode::HalfEven);
+        let max = Decimal::from_units(i64::MAX, even);
+        let min = Decimal::from_units(i64::MIN, even);
+        let one = decimal("1", even);
+        assert_eq!(
+            max.checked_add(Decimal::from_units(1, even)),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(
+            min.checked_sub(Decimal::from_units(1, even)),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(
+            max.checked_mul(decimal("2", even)),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(
+            max.checked_div(decimal("0.5", even)),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(min.checked_abs(), Err(DecimalError::Overflow));
+        assert_eq!(
+            one.checked_div(decimal("0", even)),
+            Err(DecimalError::DivisionByZero)
+        );
+        // 0.001 rounds to zero at a scale of 2, which is still a division by zero.
+        assert_eq!(
+            one.checked_div(decimal("0.001", even)),
+            Err(DecimalError::DivisionByZero)
+        );
+        assert_eq!(
+            Decimal::from_literal("99999999999999999999", None, even),
+            Err(DecimalError::Overflow)
+        );
+        assert_eq!(
+            one.call(Builtin::Sqrt),
+            Err(DecimalError::Unsupported(Builtin::Sqrt))
+        );
+    }
+
+    #[test]
+    fn whole_powers() {
+        let even = config(2, RoundingMode::HalfEven);
+        let pow = |base: &str, exp: &str| {
+            decimal(base, even)
+                .checked_powi(decimal(exp, even))
+                .map(|d| d.to_string())
+        };
+        assert_eq!(pow("1.5", "3"), Ok("3.38".to_owned()));
+        assert_eq!(pow("-2", "3"), Ok("-8.00".to_owned()));
+        assert_eq!(pow("2", "-2"), Ok("0.25".to_owned()));
+        assert_eq!(pow("5", "0"), Ok("1.00".to_owned()));
+        // Repeated rounding stops changing the result, so huge exponents end early.
+        assert_eq!(pow("1", "1000000000"), Ok("1.00".to_owned()));
+        assert_eq!(pow("0.5", "1000000"), Ok("0.00".to_owned()));
+        assert_eq!(pow("-1", "1000001"), Ok("-1.00".to_owned()));
+        assert_eq!(pow("-1", "1000000"), Ok("1.00".to_owned()));
+        assert_eq!(pow("2", "0.5"), Err(DecimalError::FractionalExponent));
+        assert_eq!(pow("10", "100"), Err(DecimalError::Overflow));
+        assert_eq!(pow("0", "-1"), Err(DecimalError::DivisionByZero));
+    }
+
+    #[test]
+    fn complex_powers() {
+        let zero = Complex::default();
+        let real = |re: f32| Complex::new(re, 0.);
+        // Zero follows the sign of the exponent's real part.
+        assert_eq!(zero.pow(real(2.)), zero);
+        assert_eq!(zero.pow(Complex::new(0.5, 3.)), zero);
+        assert_eq!(zero.pow(zero), real(1.));
+        assert_eq!(zero.pow(real(-1.)), real(f32::INFINITY));
+        let undefined = zero.pow(Complex::I);
+        assert!(undefined.re.is_nan() && undefined.im.is_nan());
+        // Whole powers are multiplied out exactly.
+        assert_eq!(Complex::I.pow(real(2.)), real(-1.));
+        assert_eq!(Complex::I.pow(real(-1.)), Complex::new(0., -1.));
+        assert_eq!(Complex::new(1., 1.).pow(real(4.)), real(-4.));
+        // i^i is e^(-pi/2).
+        let principal = Complex::I.pow(Complex::I);
+        assert!((principal.re - (-std::f32::consts::FRAC_PI_2).exp()).abs() < 1e-6);
+        assert!(principal.im.abs() < 1e-6);
+    }
+}
@@ -1,61 +1,733 @@
 use crate::{
-    IResult, Span, Token, TokenType, error::ParseError, lexer::Statement, math_lexing::MathLexer,
+    IResult, Location, Span, Token, TokenType,
+    ast::{self, Node},
+    covering,
+    error::ParseError,
+    lexer::{BinOp, Format, Reduction, Statement, Statistic},
+    number::Number,
+    session::LAST_RESULT,
+    start,
     symbols::SymbolTable,
 };
 
+/// An error about the tokens at `location`, which is reported with `label` under it, or about the
+/// whole program when they have no location.
+fn error<'a>(location: Option<Location>, message: String, label: &str) -> nom::Err<ParseError<'a>> {
+    nom::Err::Error(match location {
+        Some(location) => ParseError::at(location, message, label),
+        None => ParseError::new(Span::new(""), message),
+    })
+}
+
+/// A bounded `sum` or `prod`. Its bounds and body stay separate so the body can be compiled into a loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub reduction: Reduction,
+    /// Variable bound by the loop, visible only inside the body.
+    pub index: String,
+    pub from: Vec<Token>,
+    pub to: Vec<Token>,
+    pub body: Vec<Token>,
+    /// Source offset of the `sum`/`prod` keyword, which also names the compiled function.
+    pub location: usize,
+    pub from_location: usize,
+    pub to_location: usize,
+}
+
+impl Loop {
+    /// Variables the compiled loop reads from outside, in first-use order. The bounds are
+    /// evaluated before the loop runs, but those of nested loops are computed inside it.
+    pub fn captures(&self) -> Vec<String> {
+        let mut captures = vec![];
+        free_variables(&self.body, &mut vec![self.index.clone()], &mut captures);
+        captures
+    }
+}
+
+/// `integrate(body, variable, from, to)`, with an optional tolerance as a fifth argument. Like a
+/// loop, the body is kept apart from the bounds so it can be compiled into the integrand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Integral {
+    /// Variable of integration, visible only inside the body.
+    pub variable: String,
+    pub body: Vec<Token>,
+    pub from: Vec<Token>,
+    pub to: Vec<Token>,
+    pub tolerance: Option<Vec<Token>>,
+    /// Source offset of the `integrate` keyword, which also names the compiled integrand.
+    pub location: usize,
+    pub from_location: usize,
+    pub to_location: usize,
+    pub tolerance_location: usize,
+}
+
+impl Integral {
+    /// Variables the compiled integrand reads from outside, in first-use order.
+    pub fn captures(&self) -> Vec<String> {
+        let mut captures = vec![];
+        free_variables(&self.body, &mut vec![self.variable.clone()], &mut captures);
+        captures
+    }
+}
+
+/// `solve(lhs = rhs, variable)`, optionally with a starting guess, as the expression whose roots are wanted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equation {
+    /// Variable solved for, visible only inside the body.
+    pub variable: String,
+    /// `lhs - rhs`, or the expression itself when there is no `=`.
+    pub body: Vec<Token>,
+    /// First and second derivatives of the body, when it can be differentiated symbolically.
+    pub derivatives: Option<[Vec<Token>; 2]>,
+    /// Set when the body is a polynomial of degree at most two, which is solved in closed form.
+    pub quadratic: bool,
+    pub guess: Option<Vec<Token>>,
+    /// Source offset of the `solve` keyword, which also names the compiled functions.
+    pub location: usize,
+    pub guess_location: usize,
+}
+
+impl Equation {
+    /// Variables the compiled body reads from outside, in first-use order. Its derivatives use no others.
+    pub fn captures(&self) -> Vec<String> {
+        let mut captures = vec![];
+        free_variables(&self.body, &mut vec![self.variable.clone()], &mut captures);
+        captures
+    }
+}
+
+/// A user function `name(params) = body`, compiled once and then called by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Token>,
+    pub location: usize,
+}
+
+/// Collects the identifiers in `tokens` that are not `bound`, in first-use order.
+pub fn free_variables(tokens: &[Token], bound: &mut Vec<String>, captures: &mut Vec<String>) {
+    for token in tokens {
+        match &token.token_type {
+            TokenType::Id(id) if !bound.contains(id) && !captures.contains(id) => {
+                captures.push(id.clone())
+            }
+            TokenType::Loop(inner) => {
+                free_variables(&inner.from, bound, captures);
+                free_variables(&inner.to, bound, captures);
+                bound.push(inner.index.clone());
+                free_variables(&inner.body, bound, captures);
+                bound.pop();
+            }
+            TokenType::Integral(integral) => {
+                free_variables(&integral.from, bound, captures);
+                free_variables(&integral.to, bound, captures);
+                if let Some(tolerance) = &integral.tolerance {
+                    free_variables(tolerance, bound, captures);
+                }
+                bound.push(integral.variable.clone());
+                free_variables(&integral.body, bound, captures);
+                bound.pop();
+            }
+            TokenType::Simplified(node) => free_variables(&node.to_rpn(None), bound, captures),
+            TokenType::Equation(equation) => {
+                if let Some(guess) = &equation.guess {
+                    free_variables(guess, bound, captures);
+                }
+                bound.push(equation.variable.clone());
+                free_variables(&equation.body, bound, captures);
+                bound.pop();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// `tokens` with each read of `name` that nothing inside them binds replaced with the tokens of `with`.
+fn substitute(tokens: Vec<Token>, name: &str, with: &[Token]) -> Vec<Token> {
+    let within = |tokens: Vec<Token>, bound: &str| {
+        if bound == name {
+            tokens
+        } else {
+            substitute(tokens, name, with)
+        }
+    };
+    let mut substituted = vec![];
+    for mut token in tokens {
+        match &mut token.token_type {
+            TokenType::Id(id) if id == name => {
+                substituted.extend_from_slice(with);
+                continue;
+            }
+            TokenType::Loop(inner) => {
+                inner.from = substitute(std::mem::take(&mut inner.from), name, with);
+                inner.to = substitute(std::mem::take(&mut inner.to), name, with);
+                inner.body = within(std::mem::take(&mut inner.body), &inner.index);
+            }
+            TokenType::Integral(integral) => {
+                integral.from = substitute(std::mem::take(&mut integral.from), name, with);
+                integral.to = substitute(std::mem::take(&mut integral.to), name, with);
+                integral.tolerance =
+                    (integral.tolerance.take()).map(|tolerance| substitute(tolerance, name, with));
+                integral.body = within(std::mem::take(&mut integral.body), &integral.variable);
+            }
+            TokenType::Equation(equation) => {
+                equation.guess = (equation.guess.take()).map(|guess| substitute(guess, name, with));
+                equation.body = within(std::mem::take(&mut equation.body), &equation.variable);
+                if let Some(derivatives) = &mut equation.derivatives {
+                    for derivative in derivatives {
+                        *derivative = within(std::mem::take(derivative), &equation.variable);
+                    }
+                }
+            }
+            // A simplified tree that reads `name` is evaluated like any other expression.
+            TokenType::Simplified(node) => {
+                let rpn = node.to_rpn(None);
+                let mut free = vec![];
+                free_variables(&rpn, &mut vec![], &mut free);
+                if free.iter().any(|id| id == name) {
+                    substituted.extend(substitute(rpn, name, with));
+                    continue;
+                }
+            }
+            _ => (),
+        }
+        substituted.push(token);
+    }
+    substituted
+}
+
+/// Collects every call of a user function in `tokens`, including inside sums, integrals and equations,
+/// as its name, its number of arguments and where it is.
+fn calls(tokens: &[Token], found: &mut Vec<(String, usize, Option<Location>)>) {
+    for token in tokens {
+        match &token.token_type {
+            TokenType::Call(name, arity) | TokenType::Grad(name, arity) => {
+                found.push((name.clone(), *arity, token.location))
+            }
+            TokenType::Loop(inner) => {
+                for tokens in [&inner.from, &inner.to, &inner.body] {
+                    calls(tokens, found);
+                }
+            }
+            TokenType::Integral(integral) => {
+                for tokens in [&integral.from, &integral.to, &integral.body] {
+                    calls(tokens, found);
+                }
+                if let Some(tolerance) = &integral.tolerance {
+                    calls(tolerance, found);
+                }
+            }
+            TokenType::Equation(equation) => {
+                if let Some(guess) = &equation.guess {
+                    calls(guess, found);
+                }
+                calls(&equation.body, found);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// How many values from before it a token of RPN takes.
+fn operands(token_type: &TokenType) -> usize {
+    match token_type {
+        TokenType::BinOp(_) => 2,
+        TokenType::Negate | TokenType::Builtin(_) | TokenType::Convert(_) => 1,
+        TokenType::Call(_, arity)
+        | TokenType::Grad(_, arity)
+        | TokenType::Statistic(_, arity)
+        | TokenType::Array(arity) => *arity,
+        TokenType::LinAlg(linalg) => linalg.arity(),
+        _ => 0,
+    }
+}
+
+/// A shorter form of `x op y` with the same value for every `x` and `y`, `nan` and infinities
+/// included, except that collected terms are rounded once rather than twice.
+fn rewrite(op: &BinOp, x: &[Token], y: &[Token]) -> Option<Vec<Token>> {
+    let location = x
+        .iter()
+        .chain(y)
+        .fold(None, |location, token| covering(location, token.location));
+    let token = |token_type| Token {
+        token_type,
+        location,
+    };
+    match (op, constant(x), constant(y)) {
+        (BinOp::Equal | BinOp::MatMul, _, _) => None,
+        (_, Some(a), Some(b)) => Some(vec![token(TokenType::Num(Number::Float(ast::fold(
+            op, a, b,
+        )?)))]),
+        (BinOp::Times, Some(1.), _) => Some(y.to_vec()),
+        (BinOp::Times | BinOp::Divide | BinOp::Power, _, Some(1.)) => Some(x.to_vec()),
+        // `x + 0` is kept, since it turns `-0` into `0`.
+        (BinOp::Minus, _, Some(zero)) if zero == 0. && zero.is_sign_positive() => Some(x.to_vec()),
+        (BinOp::Plus | BinOp::Minus, _, _) => {
+            let ((a, u), (b, v)) = (term(x), term(y));
+            let same =
+                u.len() == v.len() && u.iter().zip(v).all(|(u, v)| u.token_type == v.token_type);
+            if !same {
+                return None;
+            }
+            // Terms that cancel are kept, since `2*x - 2*x` is `nan` rather than `0` at infinity,
+            // and so is a difference whose terms overflow.
+            let b = if *op == BinOp::Minus { -b } else { b };
+            if a * b <= 0. {
+                return None;
+            }
+            let coefficient = ast::fold(&BinOp::Plus, a, b)?;
+            let mut tokens = vec![token(TokenType::Num(Number::Float(coefficient)))];
+            tokens.extend_from_slice(u);
+            tokens.push(token(TokenType::BinOp(BinOp::Times)));
+            Some(tokens)
+        }
+        _ => None,
+    }
+}
+
+/// The value of an operand that is a single float.
+fn constant(operand: &[Token]) -> Option<f32> {
+    match operand {
+        [
+            Token {
+                token_type: TokenType::Num(Number::Float(x)),
+                ..
+            },
+        ] => Some(*x),
+        _ => None,
+    }
+}
+
+/// Splits a term of a sum into its constant coefficient and the rest, as in `2 * x`.
+fn term(operand: &[Token]) -> (f32, &[Token]) {
+    if let [factors @ .., last] = operand
+        && last.token_type == TokenType::BinOp(BinOp::Times)
+        && let Some(split) = operand_start(factors)
+    {
+        let (u, v) = factors.split_at(split);
+        match (constant(u), constant(v)) {
+            (Some(a), None) => return (a, v),
+            (None, Some(b)) => return (b, u),
+            _ => (),
+        }
+    }
+    (1., operand)
+}
+
+/// Where the last operand of `rpn` starts, found by walking back until its tokens add up to one
+/// value, or `None` if they never do.
+pub fn operand_start(rpn: &[Token]) -> Option<usize> {
+    let mut needed = 1;
+    for (n, token) in rpn.iter().enumerate().rev() {
+        needed = needed + operands(&token.token_type) - 1;
+        if needed == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// An expression the program prints.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub rpn: Vec<Token>,
+    pub format: Format,
+    /// The expression as it was written, which labels its value.
+    pub text: String,
+}
+
+/// The printed expressions in the order they were written, the variables and the functions.
+pub type Program = (Vec<Output>, SymbolTable<Vec<Token>>, Vec<Function>);
+
 pub struct Parser {
     statements: Vec<Statement>,
+    /// Functions defined so far, which later statements can call.
+    functions: Vec<Function>,
+    /// How many results earlier programs printed, which the results of this one are numbered after.
+    results: usize,
+    /// Values defined before the program runs, which functions read when the program does not assign them.
+    inputs: Vec<(String, Number)>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Statement>) -> Self {
-        Self { statements: tokens }
-    }
-
-    pub fn parse<'a>(mut self) -> IResult<'a, (Vec<Token>, SymbolTable<Vec<Token>>)> {
-        // Checks for at most one print.
-        assert!(
-            self.statements
-                .iter()
-                .filter(|s| if let Statement::Print(_) = s {
-                    return true;
-                } else {
-                    return false;
-                })
-                .count()
-                <= 1
-        );
+        Self {
+            statements: tokens,
+            functions: vec![],
+            results: 0,
+            inputs: vec![],
+        }
+    }
+
+    /// Numbers the results of the program after `results` printed before it, as `Compiler::with_results` does.
+    pub fn with_results(mut self, results: usize) -> Self {
+        self.results = results;
+        self
+    }
+
+    /// Defines the values `Compiler::with_input` was given, for the functions that read them.
+    pub fn with_inputs(mut self, inputs: &[(String, Number)]) -> Self {
+        self.inputs = inputs.to_vec();
+        self
+    }
 
+    pub fn parse<'a>(mut self) -> IResult<'a, Program> {
         let mut symbol_table = SymbolTable::new();
-        for statement in &self.statements {
-            if let Statement::Assign(id, expr) = statement {
-                let (_, rpn) = Parser::parse_into_rpn(expr.to_vec())?;
+        let mut outputs = vec![];
+        // Names defined before the latest output.
+        let mut printed = vec![];
+        let statements = std::mem::take(&mut self.statements);
+        let assigns_last = statements.iter().any(|statement| {
+            matches!(statement, Statement::Assign(Token { token_type: TokenType::Id(id), .. }, _) if id == LAST_RESULT)
+        });
+        // The outputs that are a derivative alone, which are printed as one when their variable has
+        // no value, like a `simplify`.
+        let mut derivatives = vec![];
+        for statement in &statements {
+            if let Statement::Assign(
+                Token {
+                    token_type: TokenType::Id(id),
+                    location,
+                },
+                expr,
+            ) = statement
+            {
+                if printed.contains(id) {
+                    return Err(Parser::redefined(id, *location));
+                }
+                let mut expr = expr.to_vec();
+                // `ans` is the result printed last before the definition, not after the whole program.
+                if !assigns_last && !outputs.is_empty() {
+                    let numbered = format!("_{}", self.results + outputs.len());
+                    for token in &mut expr {
+                        if token.token_type == TokenType::Id(LAST_RESULT.to_owned()) {
+                            token.token_type = TokenType::Id(numbered.clone());
+                        }
+                    }
+                }
+                let (_, rpn) = self.parse_into_rpn(expr)?;
                 symbol_table.add(id, rpn);
-            } else if let Statement::Print(print) = statement {
-                // Should only run once.
-                let (_, rpn) = Parser::parse_into_rpn(print.to_vec())?;
-                return Ok((Span::new(""), (rpn, symbol_table)));
+            } else if let Statement::Function(
+                Token {
+                    token_type: TokenType::Id(name),
+                    location,
+                },
+                params,
+                body,
+            ) = statement
+            {
+                if printed.contains(name) {
+                    return Err(Parser::redefined(name, *location));
+                }
+                let params: Vec<String> = params
+                    .iter()
+                    .filter_map(|param| match &param.token_type {
+                        TokenType::Id(id) => Some(id.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let (_, mut body) = self.parse_into_rpn(body.to_vec())?;
+                // The variables a function reads other than its parameters are the values they
+                // have where it is defined.
+                let mut resolved = vec![];
+                loop {
+                    let mut free = vec![];
+                    free_variables(&body, &mut params.clone(), &mut free);
+                    let Some(id) = free.into_iter().next() else {
+                        break;
+                    };
+                    let value = match resolved.contains(&id) {
+                        false => self.value_of(&id, &symbol_table, &outputs, assigns_last),
+                        // A variable defined in terms of itself.
+                        true => None,
+                    };
+                    let Some(value) = value else {
+                        let used = body
+                            .iter()
+                            .find(|token| token.token_type == TokenType::Id(id.clone()))
+                            .and_then(|token| token.location);
+                        return Err(error(
+                            used,
+                            format!(
+                                "`{name}` uses `{id}`, which is neither one of its parameters nor defined before it."
+                            ),
+                            "not defined",
+                        ));
+                    };
+                    body = substitute(body, &id, &value);
+                    resolved.push(id);
+                }
+                let function = Function {
+                    name: name.clone(),
+                    params,
+                    body: self.optimize(body),
+                    location: start(*location),
+                };
+                // A new definition takes the place of the old one, so what called the old one calls it.
+                match self.functions.iter().position(|f| &f.name == name) {
+                    Some(n) => {
+                        self.functions[n] = function;
+                        self.check_calls(name, &symbol_table)?;
+                        self.order_functions(name, *location)?;
+                    }
+                    None => self.functions.push(function),
+                }
+            } else if let Statement::Print(print, format, text) = statement {
+                let (_, rpn) = self.parse_into_rpn(print.to_vec())?;
+                if let Some((
+                    Token {
+                        token_type: TokenType::Derivative(variable),
+                        location,
+                    },
+                    rest,
+                )) = print.split_first()
+                {
+                    let mut rest = rest.iter().cloned();
+                    if let Ok((_, derivative)) =
+                        self.derivative(variable.clone(), *location, &mut rest)
+                        && rest.next().is_none()
+                    {
+                        derivatives.push((outputs.len(), derivative));
+                    }
+                }
+                outputs.push(Output {
+                    rpn,
+                    format: *format,
+                    text: text.clone(),
+                });
+                printed = symbol_table.variables.keys().cloned().collect();
+                printed.extend(self.functions.iter().map(|f| f.name.clone()));
             }
         }
 
-        unreachable!()
+        for (n, (node, x, location)) in derivatives {
+            if symbol_table.lookup(&x).is_none() && !self.inputs.iter().any(|(id, _)| *id == x) {
+                outputs[n].rpn = vec![Token {
+                    token_type: TokenType::Simplified(Box::new(node)),
+                    location,
+                }];
+            }
+        }
+        Ok((Span::new(""), (outputs, symbol_table, self.functions)))
+    }
+
+    /// What `id` stands for at this point of the program: the expression assigned to it, the expression
+    /// printed as that result, or the value it was given before the program ran.
+    fn value_of(
+        &self,
+        id: &str,
+        symbol_table: &SymbolTable<Vec<Token>>,
+        outputs: &[Output],
+        assigns_last: bool,
+    ) -> Option<Vec<Token>> {
+        if let Some(rpn) = symbol_table.lookup(id) {
+            return Some(rpn.clone());
+        }
+        let printed = |n: usize| Some(outputs.get(n.checked_sub(self.results + 1)?)?.rpn.clone());
+        let result = match id {
+            LAST_RESULT if !assigns_last && !outputs.is_empty() => {
+                printed(self.results + outputs.len())
+            }
+            _ => id
+                .strip_prefix('_')
+                .and_then(|n| n.parse().ok())
+                .and_then(printed),
+        };
+        result.or_else(|| {
+            let (_, value) = self.inputs.iter().find(|(input, _)| input == id)?;
+            Some(vec![Token {
+                token_type: TokenType::Num(value.clone()),
+                location: None,
+            }])
+        })
+    }
+
+    /// Checks that the functions and variables that call `name` pass it as many arguments as its
+    /// latest definition takes.
+    fn check_calls<'a>(
+        &self,
+        name: &str,
+        symbol_table: &SymbolTable<Vec<Token>>,
+    ) -> Result<(), nom::Err<ParseError<'a>>> {
+        let Some(function) = self.functions.iter().find(|f| f.name == name) else {
+            return Ok(());
+        };
+        let mut found = vec![];
+        for tokens in self
+            .functions
+            .iter()
+            .map(|f| &f.body)
+            .chain(symbol_table.variables.values())
+        {
+            calls(tokens, &mut found);
+        }
+        match found
+            .into_iter()
+            .find(|(callee, arity, _)| callee == name && *arity != function.params.len())
+        {
+            Some((_, arity, location)) => Err(error(
+                location,
+                format!(
+                    "`{name}` is called with {arity} argument(s), but its new definition takes {}.",
+                    function.params.len()
+                ),
+                "calls the earlier definition",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Puts every function after the ones it calls, keeping definition order otherwise, since each is
+    /// compiled after its callees. Only redefining `name`, at `location`, can make it call itself.
+    fn order_functions<'a>(
+        &mut self,
+        name: &str,
+        location: Option<Location>,
+    ) -> Result<(), nom::Err<ParseError<'a>>> {
+        let mut pending = std::mem::take(&mut self.functions);
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|function| {
+                let mut found = vec![];
+                calls(&function.body, &mut found);
+                found
+                    .iter()
+                    .all(|(callee, ..)| self.functions.iter().any(|f| &f.name == callee))
+            });
+            let Some(ready) = ready else {
+                return Err(error(
+                    location,
+                    format!("`{name}` would call itself forever."),
+                    "redefined here",
+                ));
+            };
+            self.functions.push(pending.remove(ready));
+        }
+        Ok(())
+    }
+
+    /// Definitions are not ordered the way outputs are, so an output before a second definition of the
+    /// same name would print what the second one defines.
+    fn redefined<'a>(name: &str, location: Option<Location>) -> nom::Err<ParseError<'a>> {
+        error(
+            location,
+            format!(
+                "`{name}` is defined again after it is printed; give the new definition another name."
+            ),
+            "defined again",
+        )
     }
 
     /// Implements shunting yard: https://en.wikipedia.org/wiki/Shunting_yard_algorithm#The_algorithm_in_detail
-    pub fn parse_into_rpn<'a>(tokens: Vec<Token>) -> IResult<'a, Vec<Token>> {
+    pub fn parse_into_rpn<'a>(&self, tokens: Vec<Token>) -> IResult<'a, Vec<Token>> {
         let mut output_queue: Vec<Token> = vec![];
         let mut operator_stack: Vec<Token> = vec![];
+        // Number of arguments seen so far, one entry per open parenthesis.
+        let mut arg_counts: Vec<usize> = vec![];
 
-        for token in tokens {
+        // Set by `[` for the next token only, so `[]` can be rejected.
+        let mut opened_bracket = false;
+
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            let after_bracket = std::mem::replace(&mut opened_bracket, false);
             match token.token_type {
-                TokenType::Fp(_) | TokenType::Id(_) => output_queue.push(token),
+                TokenType::Num(_)
+                | TokenType::Id(_)
+                | TokenType::Loop(_)
+                | TokenType::Integral(_)
+                | TokenType::Equation(_)
+                | TokenType::Simplified(_)
+                | TokenType::Array(_) => output_queue.push(token),
+                TokenType::Reduction(reduction) => {
+                    let args = Parser::gather_args(&mut tokens);
+                    // `sum(list)` adds up the entries of a vector instead of looping.
+                    if let (Reduction::Sum, Some([list])) = (reduction, args.as_deref()) {
+                        let (_, list) = self.parse_into_rpn(list.clone())?;
+                        output_queue.extend(list);
+                        output_queue.push(Token {
+                            token_type: TokenType::Statistic(Statistic::Sum, 1),
+                            location: token.location,
+                        });
+                        continue;
+                    }
+                    let (_, reduction) = self.parse_loop(reduction, token.location, args)?;
+                    output_queue.push(Token {
+                        token_type: TokenType::Loop(Box::new(reduction)),
+                        location: token.location,
+                    });
+                }
+                TokenType::Integrate => {
+                    let (_, integral) = self.parse_integral(token.location, &mut tokens)?;
+                    output_queue.push(Token {
+                        token_type: TokenType::Integral(Box::new(integral)),
+                        location: token.location,
+                    });
+                }
+                TokenType::Solve => {
+                    let (_, equation) = self.parse_solve(token.location, &mut tokens)?;
+                    output_queue.push(Token {
+                        token_type: TokenType::Equation(Box::new(equation)),
+                        location: token.location,
+                    });
+                }
+                TokenType::Simplify => {
+                    let (_, node) = self.parse_simplify(token.location, &mut tokens)?;
+                    output_queue.push(Token {
+                        token_type: TokenType::Simplified(Box::new(node)),
+                        location: token.location,
+                    });
+                }
+                TokenType::BinOp(BinOp::Equal) => {
+                    return Err(error(
+                        token.location,
+                        String::from("`=` can only be used in an equation passed to `solve`."),
+                        "not in an equation",
+                    ));
+                }
+                TokenType::Derivative(ref variable) => {
+                    let (_, derivative) =
+                        self.parse_derivative(variable.clone(), token.location, &mut tokens)?;
+                    output_queue.extend(derivative);
+                }
+                TokenType::Grad(..) => {
+                    let (_, grad) = self.parse_grad(token.location, &mut tokens)?;
+                    output_queue.extend(grad);
+                }
+                TokenType::Comma => {
+                    while operator_stack.last().is_some_and(|t| {
+                        !matches!(t.token_type, TokenType::LeftParen | TokenType::LeftBracket)
+                    }) {
+                        output_queue.push(operator_stack.pop().unwrap());
+                    }
+                    let in_array = operator_stack
+                        .last()
+                        .is_some_and(|t| t.token_type == TokenType::LeftBracket);
+                    let in_call = operator_stack.len() >= 2
+                        && matches!(
+                            operator_stack[operator_stack.len() - 2].token_type,
+                            TokenType::Call(..) | TokenType::LinAlg(_) | TokenType::Statistic(..)
+                        );
+                    if !in_array && !in_call {
+                        return Err(error(
+                            token.location,
+                            String::from(
+                                "`,` can only separate the arguments of a function or the entries of a vector.",
+                            ),
+                            "not between arguments",
+                        ));
+                    }
+                    *arg_counts.last_mut().unwrap() += 1;
+                }
                 TokenType::BinOp(ref o_1) => {
                     while operator_stack
                         .last()
                         .is_some_and(|o_2| match &o_2.token_type {
                             TokenType::BinOp(o_2) => o_2.has_greater_precedence_than(o_1),
-                            TokenType::LeftParen => false,
-                            _ => panic!(""),
+                            // `-` binds tighter than anything but `^`, so `-2^2` is `-4`.
+                            TokenType::Negate => *o_1 != BinOp::Power,
+                            // Functions wait under their arguments until those are closed.
+                            _ => false,
                         })
                     {
                         let o_2 = operator_stack.pop().unwrap();
@@ -64,38 +736,183 @@ impl Parser {
 
                     operator_stack.push(token);
                 }
-                TokenType::LeftParen => operator_stack.push(token),
+                // Nothing before a prefix operator is its operand, so nothing is applied yet.
+                TokenType::Negate
+                | TokenType::Builtin(_)
+                | TokenType::LinAlg(_)
+                | TokenType::Statistic(..)
+                | TokenType::Call(..) => operator_stack.push(token),
+                TokenType::LeftParen => {
+                    arg_counts.push(1);
+                    operator_stack.push(token);
+                }
+                TokenType::LeftBracket => {
+                    arg_counts.push(1);
+                    operator_stack.push(token);
+                    opened_bracket = true;
+                }
+                TokenType::RightBracket => {
+                    if after_bracket {
+                        return Err(error(
+                            token.location,
+                            String::from("Vectors and matrices need at least one entry."),
+                            "empty",
+                        ));
+                    }
+                    while operator_stack.last().is_some_and(|t| {
+                        !matches!(t.token_type, TokenType::LeftParen | TokenType::LeftBracket)
+                    }) {
+                        output_queue.push(operator_stack.pop().unwrap());
+                    }
+                    let Some(Token {
+                        token_type: TokenType::LeftBracket,
+                        location,
+                    }) = operator_stack.pop()
+                    else {
+                        return Err(error(
+                            token.location,
+                            String::from("Unmatched `]`."),
+                            "unmatched",
+                        ));
+                    };
+                    output_queue.push(Token {
+                        token_type: TokenType::Array(arg_counts.pop().unwrap()),
+                        location: covering(location, token.location),
+                    });
+                }
+                TokenType::Convert(_) => {
+                    // Converting applies to everything before it.
+                    while operator_stack.last().is_some_and(|t| {
+                        !matches!(t.token_type, TokenType::LeftParen | TokenType::LeftBracket)
+                    }) {
+                        output_queue.push(operator_stack.pop().unwrap());
+                    }
+                    output_queue.push(token);
+                }
                 TokenType::RightParen => {
-                    while operator_stack
-                        .last()
-                        .is_some_and(|t| t.token_type != TokenType::LeftParen)
-                    {
+                    while operator_stack.last().is_some_and(|t| {
+                        !matches!(t.token_type, TokenType::LeftParen | TokenType::LeftBracket)
+                    }) {
                         let last_op = operator_stack.pop().unwrap();
                         output_queue.push(last_op);
                     }
+                    if let Some(bracket) = operator_stack
+                        .last()
+                        .filter(|t| t.token_type == TokenType::LeftBracket)
+                    {
+                        return Err(error(
+                            bracket.location,
+                            String::from("Unclosed `[` before `)`."),
+                            "not closed",
+                        ));
+                    }
                     if operator_stack.is_empty() {
-                        let op_msg = format!("{:?}", operator_stack);
-                        return Err(nom::Err::Error(ParseError::new(
-                            // Span::new(&op_msg),
-                            Span::new(""),
-                            String::from("Operator stack not empty."),
-                        )));
+                        return Err(error(
+                            token.location,
+                            String::from("Unmatched `)`."),
+                            "unmatched",
+                        ));
                     }
 
                     operator_stack.pop();
+                    let arg_count = arg_counts.pop().unwrap();
+                    let queued = output_queue.len();
+                    // What an error about the call the parenthesis closes points at.
+                    let call = covering(
+                        operator_stack.last().and_then(|t| t.location),
+                        token.location,
+                    );
+                    let called_with = format!("called with {arg_count}");
+                    // Closing a call's argument list applies the function.
+                    match operator_stack.last().map(|t| &t.token_type) {
+                        Some(TokenType::Builtin(_)) => {
+                            output_queue.push(operator_stack.pop().unwrap())
+                        }
+                        Some(TokenType::LinAlg(linalg)) => {
+                            if linalg.arity() != arg_count {
+                                return Err(error(
+                                    call,
+                                    format!(
+                                        "`{linalg}` takes {} argument(s), but was given {arg_count}.",
+                                        linalg.arity()
+                                    ),
+                                    &called_with,
+                                ));
+                            }
+                            output_queue.push(operator_stack.pop().unwrap());
+                        }
+                        Some(TokenType::Statistic(statistic, _)) => {
+                            let statistic = *statistic;
+                            let (fewest, most) = statistic.arity();
+                            if !(fewest..=most).contains(&arg_count) {
+                                let expected = if fewest == most {
+                                    fewest.to_string()
+                                } else {
+                                    format!("{fewest} or {most}")
+                                };
+                                return Err(error(
+                                    call,
+                                    format!(
+                                        "`{statistic}` takes {expected} argument(s), but was given {arg_count}."
+                                    ),
+                                    &called_with,
+                                ));
+                            }
+                            let mut call = operator_stack.pop().unwrap();
+                            call.token_type = TokenType::Statistic(statistic, arg_count);
+                            output_queue.push(call);
+                        }
+                        Some(TokenType::Call(name, _)) => {
+                            let name = name.clone();
+                            let Some(function) = self.functions.iter().find(|f| f.name == name)
+                            else {
+                                let name_location = operator_stack.last().and_then(|t| t.location);
+                                return Err(error(
+                                    name_location,
+                                    format!("Unknown function `{name}`."),
+                                    "not defined",
+                                ));
+                            };
+                            if function.params.len() != arg_count {
+                                return Err(error(
+                                    call,
+                                    format!(
+                                        "`{name}` takes {} argument(s), but was given {arg_count}.",
+                                        function.params.len()
+                                    ),
+                                    &called_with,
+                                ));
+                            }
+                            let mut call = operator_stack.pop().unwrap();
+                            call.token_type = TokenType::Call(name, arg_count);
+                            output_queue.push(call);
+                        }
+                        _ => (),
+                    }
+                    // A call covers its arguments, up to the closing parenthesis.
+                    if output_queue.len() > queued {
+                        let call = output_queue.last_mut().unwrap();
+                        call.location = covering(call.location, token.location);
+                    }
                 }
             }
         }
 
-        while !operator_stack.is_empty() {
-            if operator_stack.first().is_some_and(|t| {
-                t.token_type == TokenType::LeftParen || t.token_type == TokenType::RightParen
-            }) {
-                return Err(nom::Err::Error(ParseError::new(
-                    // Span::new(&format!("{:?}", operator_stack)),
-                    "".into(),
+        while let Some(first) = operator_stack.first() {
+            if first.token_type == TokenType::LeftBracket {
+                return Err(error(
+                    first.location,
+                    String::from("Unclosed `[`."),
+                    "not closed",
+                ));
+            }
+            if first.token_type == TokenType::LeftParen || first.token_type == TokenType::RightParen
+            {
+                return Err(error(
+                    first.location,
                     String::from("Unclosed parenthesis."),
-                )));
+                    "not closed",
+                ));
             }
             let last_op = operator_stack.pop().unwrap();
             output_queue.push(last_op);
@@ -104,14 +921,467 @@ impl Parser {
         operator_stack.reverse();
         output_queue.append(&mut operator_stack);
 
+        Parser::check_operands(&output_queue)?;
         Ok((Span::new(""), output_queue))
     }
 
-    fn eval_rpn(mut rpn: Vec<Token>) -> IResult<'static, f32> {
-        let mut result = 0.;
+    /// Checks that every operator has its operands and that they add up to a single value, so that
+    /// `2 *` is reported here rather than when it is evaluated.
+    fn check_operands<'a>(rpn: &[Token]) -> Result<(), nom::Err<ParseError<'a>>> {
+        // The location of each value computed so far.
+        let mut values: Vec<Option<Location>> = vec![];
+        for token in rpn {
+            let needed = operands(&token.token_type);
+            let Some(at) = values.len().checked_sub(needed) else {
+                return Err(error(
+                    token.location,
+                    String::from("An operator is missing an operand."),
+                    "missing an operand",
+                ));
+            };
+            let location = values.drain(at..).fold(token.location, covering);
+            values.push(location);
+        }
+        match values.as_slice() {
+            [_, second, ..] => Err(error(
+                *second,
+                String::from("Two values have no operator between them."),
+                "no operator before this",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Splits the parenthesised argument list that follows a keyword at its top-level commas.
+    /// Returns `None` when there is no opening parenthesis or it is never closed.
+    pub(crate) fn gather_args(tokens: &mut impl Iterator<Item = Token>) -> Option<Vec<Vec<Token>>> {
+        if !tokens
+            .next()
+            .is_some_and(|t| t.token_type == TokenType::LeftParen)
+        {
+            return None;
+        }
+        let mut args: Vec<Vec<Token>> = vec![vec![]];
+        let mut depth = 0;
+        loop {
+            let token = tokens.next()?;
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen if depth == 0 => return Some(args),
+                TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+                TokenType::Comma if depth == 0 => {
+                    args.push(vec![]);
+                    continue;
+                }
+                _ => (),
+            }
+            args.last_mut().unwrap().push(token);
+        }
+    }
+
+    /// Expands `d/dx(expr)` or `deriv(f, x)`, whose keyword has just been consumed, into its simplified derivative.
+    fn parse_derivative<'a>(
+        &self,
+        variable: Option<String>,
+        location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, Vec<Token>> {
+        let (_, (node, _, location)) = self.derivative(variable, location, tokens)?;
+        Ok((Span::new(""), node.to_rpn(location)))
+    }
+
+    /// The simplified derivative that `parse_derivative` compiles, the variable it is taken in, and
+    /// where it is.
+    fn derivative<'a>(
+        &self,
+        variable: Option<String>,
+        location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, (Node, String, Option<Location>)> {
+        let error = |message: String| error(location, message, "this derivative");
+        let usage = || {
+            error(String::from(
+                "Derivatives are written `d/dx(expr)` or `deriv(f, x)`.",
+            ))
+        };
+        let token = |token_type| Token {
+            token_type,
+            location,
+        };
+
+        let args = Parser::gather_args(tokens).ok_or_else(usage)?;
+        let (expr, x) = match (variable, args.as_slice()) {
+            (Some(x), [expr]) if !expr.is_empty() => (expr.clone(), x),
+            (None, [f, x]) => match (f.as_slice(), x.as_slice()) {
+                (
+                    [
+                        Token {
+                            token_type: TokenType::Id(f),
+                            ..
+                        },
+                    ],
+                    [
+                        Token {
+                            token_type: TokenType::Id(x),
+                            ..
+                        },
+                    ],
+                ) => (
+                    vec![
+                        token(TokenType::Call(f.clone(), 0)),
+                        token(TokenType::LeftParen),
+                        token(TokenType::Id(x.clone())),
+                        token(TokenType::RightParen),
+                    ],
+                    x.clone(),
+                ),
+                _ => return Err(usage()),
+            },
+            _ => return Err(usage()),
+        };
 
-        while !rpn.is_empty() {}
+        // The derivative has no source of its own, so it is placed at the whole call.
+        let location = args.iter().flatten().fold(location, |location, token| {
+            covering(location, token.location)
+        });
+        let (_, rpn) = self.parse_into_rpn(expr)?;
+        let node = Node::from_rpn(&rpn, &self.functions).map_err(error)?;
+        Ok((Span::new(""), (node.derivative(&x).simplify(), x, location)))
+    }
+
+    /// Turns `grad(f, a, b)`, whose keyword has just been consumed, into the RPN of its point followed by the `Grad` token.
+    fn parse_grad<'a>(
+        &self,
+        location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, Vec<Token>> {
+        let error = |message: String| error(location, message, "this gradient");
+        let usage = || {
+            error(String::from(
+                "`grad` takes a function and a point, as in `grad(f, 1, 2)`.",
+            ))
+        };
+
+        let mut args = Parser::gather_args(tokens).ok_or_else(usage)?.into_iter();
+        let name = match args.next().as_deref() {
+            Some(
+                [
+                    Token {
+                        token_type: TokenType::Id(name),
+                        ..
+                    },
+                ],
+            ) => name.clone(),
+            _ => return Err(usage()),
+        };
+        let Some(function) = self.functions.iter().find(|f| f.name == name) else {
+            return Err(error(format!("Unknown function `{name}`.")));
+        };
+        let point: Vec<Vec<Token>> = args.collect();
+        if point.len() != function.params.len() || point.iter().any(|arg| arg.is_empty()) {
+            return Err(error(format!(
+                "`{name}` takes {} argument(s), so `grad` needs a point with that many coordinates.",
+                function.params.len()
+            )));
+        }
+
+        let mut rpn = vec![];
+        for arg in point {
+            rpn.extend(self.parse_into_rpn(arg)?.1);
+        }
+        rpn.push(Token {
+            token_type: TokenType::Grad(name, function.params.len()),
+            location,
+        });
+        Ok((Span::new(""), rpn))
+    }
+
+    /// Builds a `sum`/`prod` loop from the arguments gathered after its keyword.
+    fn parse_loop<'a>(
+        &self,
+        reduction: Reduction,
+        token_location: Option<Location>,
+        args: Option<Vec<Vec<Token>>>,
+    ) -> IResult<'a, Loop> {
+        let label = format!("this `{reduction}`");
+        let error = |message: String| error(token_location, message, &label);
+        let location = start(token_location);
+        let usage = format!(
+            "`{reduction}` takes an index, two bounds and a body, as in `{reduction}(i, 1, 10, i^2)`."
+        );
+
+        let Some(args) = args else {
+            return Err(error(usage));
+        };
+        let Ok([index, from, to, body]) = <[Vec<Token>; 4]>::try_from(args) else {
+            return Err(error(usage));
+        };
+        let index = match index.as_slice() {
+            [
+                Token {
+                    token_type: TokenType::Id(id),
+                    ..
+                },
+            ] => id.clone(),
+            _ => {
+                return Err(error(format!(
+                    "The first argument of `{reduction}` must be a variable name."
+                )));
+            }
+        };
+        if from.is_empty() || to.is_empty() || body.is_empty() {
+            return Err(error(usage));
+        }
+        let from_location = from[0].location.map_or(location, |l| l.start);
+        let to_location = to[0].location.map_or(location, |l| l.start);
+        let (_, from) = self.parse_into_rpn(from)?;
+        let (_, to) = self.parse_into_rpn(to)?;
+        let (_, body) = self.parse_into_rpn(body)?;
+        let body = self.optimize(body);
+
+        Ok((
+            Span::new(""),
+            Loop {
+                reduction,
+                index,
+                from,
+                to,
+                body,
+                location,
+                from_location,
+                to_location,
+            },
+        ))
+    }
+
+    /// Gathers the arguments of `integrate`, whose keyword has just been consumed, up to its closing parenthesis.
+    fn parse_integral<'a>(
+        &self,
+        token_location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, Integral> {
+        let error = |message: String| error(token_location, message, "this integral");
+        let location = start(token_location);
+        let usage = || {
+            error(String::from(
+                "`integrate` takes an expression, a variable, two bounds and optionally a tolerance, as in `integrate(x^2, x, 0, 1)`.",
+            ))
+        };
+
+        let mut args = Parser::gather_args(tokens).ok_or_else(usage)?.into_iter();
+        let (Some(body), Some(variable), Some(from), Some(to)) =
+            (args.next(), args.next(), args.next(), args.next())
+        else {
+            return Err(usage());
+        };
+        let tolerance = args.next();
+        if args.next().is_some()
+            || [&body, &from, &to].iter().any(|arg| arg.is_empty())
+            || tolerance.as_ref().is_some_and(|arg| arg.is_empty())
+        {
+            return Err(usage());
+        }
+        let variable = match variable.as_slice() {
+            [
+                Token {
+                    token_type: TokenType::Id(id),
+                    ..
+                },
+            ] => id.clone(),
+            _ => {
+                return Err(error(String::from(
+                    "The second argument of `integrate` must be a variable name.",
+                )));
+            }
+        };
+        let from_location = from[0].location.map_or(location, |l| l.start);
+        let to_location = to[0].location.map_or(location, |l| l.start);
+        let tolerance_location = tolerance
+            .as_ref()
+            .and_then(|arg| arg[0].location)
+            .map_or(location, |l| l.start);
+        let body = self.optimize(self.parse_into_rpn(body)?.1);
+        let (_, from) = self.parse_into_rpn(from)?;
+        let (_, to) = self.parse_into_rpn(to)?;
+        let tolerance = match tolerance {
+            Some(tolerance) => Some(self.parse_into_rpn(tolerance)?.1),
+            None => None,
+        };
+
+        Ok((
+            Span::new(""),
+            Integral {
+                variable,
+                body,
+                from,
+                to,
+                tolerance,
+                location,
+                from_location,
+                to_location,
+                tolerance_location,
+            },
+        ))
+    }
+
+    /// Turns `simplify(expr)`, whose keyword has just been consumed, into the simplified tree of `expr`.
+    fn parse_simplify<'a>(
+        &self,
+        location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, Node> {
+        let error = |message: String| error(location, message, "this `simplify`");
+        let expr = match Parser::gather_args(tokens).as_deref() {
+            Some([expr]) if !expr.is_empty() => expr.clone(),
+            _ => {
+                return Err(error(String::from(
+                    "`simplify` takes one expression, as in `simplify(2*x + 3*x)`.",
+                )));
+            }
+        };
+        let (_, rpn) = self.parse_into_rpn(expr)?;
+        let node = Node::from_rpn(&rpn, &self.functions).map_err(error)?;
+        Ok((Span::new(""), node.simplify()))
+    }
+
+    /// Rewrites an expression that is about to be compiled into a shorter one with the same value:
+    /// constant operations are folded, as in `2 * pi`, identities such as `x * 1` and `x - 0` are
+    /// dropped, and like terms that do not cancel are collected, so `2*x + 3*x` is `5*x`. Nothing
+    /// that could change a value is rewritten, so `x / x` is still `nan` at `0`. Every token keeps
+    /// its location, and a rewritten one covers the tokens it came from.
+    fn optimize(&self, rpn: Vec<Token>) -> Vec<Token> {
+        let mut folded: Vec<Token> = vec![];
+        // Where each value on the stack starts in `folded`. Ill-formed RPN is left for codegen to report.
+        let mut starts: Vec<usize> = vec![];
+        for token in rpn {
+            let arity = operands(&token.token_type);
+            if starts.len() < arity {
+                folded.push(token);
+                starts.clear();
+                continue;
+            }
+            let args = starts.split_off(starts.len() - arity);
+            let start = args.first().copied().unwrap_or(folded.len());
+            let rewritten = match (&token.token_type, args.as_slice()) {
+                (TokenType::BinOp(op), &[x, y]) => rewrite(op, &folded[x..y], &folded[y..]),
+                (TokenType::Negate, _) => match folded[start..] {
+                    [
+                        Token {
+                            token_type: TokenType::Num(Number::Float(a)),
+                            location,
+                        },
+                    ] => Some(vec![Token {
+                        token_type: TokenType::Num(Number::Float(-a)),
+                        location: covering(token.location, location),
+                    }]),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match rewritten {
+                Some(tokens) => {
+                    folded.truncate(start);
+                    folded.extend(tokens);
+                }
+                None => folded.push(token),
+            }
+            starts.push(start);
+        }
+        folded
+    }
+
+    /// Gathers the arguments of `solve`, whose keyword has just been consumed, and differentiates the equation.
+    fn parse_solve<'a>(
+        &self,
+        token_location: Option<Location>,
+        tokens: &mut impl Iterator<Item = Token>,
+    ) -> IResult<'a, Equation> {
+        let error = |message: String| error(token_location, message, "this equation");
+        let usage = || {
+            error(String::from(
+                "`solve` takes an equation, a variable and optionally a guess, as in `solve(x^2 = 2, x)`.",
+            ))
+        };
+
+        let location = start(token_location);
+        let args = Parser::gather_args(tokens).ok_or_else(usage)?;
+        let (equation, variable, guess) = match args.as_slice() {
+            [equation, variable] => (equation, variable, None),
+            [equation, variable, guess] if !guess.is_empty() => (equation, variable, Some(guess)),
+            _ => return Err(usage()),
+        };
+        let variable = match variable.as_slice() {
+            [
+                Token {
+                    token_type: TokenType::Id(id),
+                    ..
+                },
+            ] => id.clone(),
+            _ => {
+                return Err(error(String::from(
+                    "The second argument of `solve` must be a variable name.",
+                )));
+            }
+        };
+
+        // `lhs = rhs` is solved as `lhs - rhs = 0`.
+        let mut sides = equation.split(|t| t.token_type == TokenType::BinOp(BinOp::Equal));
+        let body = match (sides.next(), sides.next(), sides.next()) {
+            (Some(expr), None, _) if !expr.is_empty() => self.parse_into_rpn(expr.to_vec())?.1,
+            (Some(lhs), Some(rhs), None) if !lhs.is_empty() && !rhs.is_empty() => {
+                let mut body = self.parse_into_rpn(lhs.to_vec())?.1;
+                body.extend(self.parse_into_rpn(rhs.to_vec())?.1);
+                body.push(Token {
+                    token_type: TokenType::BinOp(BinOp::Minus),
+                    location: token_location,
+                });
+                body
+            }
+            (Some(_), Some(_), Some(_)) => {
+                return Err(error(String::from(
+                    "An equation passed to `solve` can only have one `=`.",
+                )));
+            }
+            _ => return Err(usage()),
+        };
+        let guess_location = guess
+            .and_then(|guess| guess[0].location)
+            .map_or(location, |l| l.start);
+        let guess = match guess {
+            Some(guess) => Some(self.parse_into_rpn(guess.clone())?.1),
+            None => None,
+        };
+
+        // Bodies that cannot be differentiated, such as ones with sums, are solved by bracketing alone.
+        // The derivatives have no source of their own, so they are placed at the whole equation.
+        let (derivatives, quadratic) = match Node::from_rpn(&body, &self.functions) {
+            Ok(node) => {
+                let location = body.iter().fold(token_location, |location, token| {
+                    covering(location, token.location)
+                });
+                let first = node.simplify().derivative(&variable).simplify();
+                let second = first.derivative(&variable).simplify();
+                let third = second.derivative(&variable).simplify();
+                (
+                    Some([first.to_rpn(location), second.to_rpn(location)]),
+                    third == Node::Num(0.),
+                )
+            }
+            Err(_) => (None, false),
+        };
+        let body = self.optimize(body);
 
-        Ok((Span::new(""), result))
+        Ok((
+            Span::new(""),
+            Equation {
+                variable,
+                body,
+                derivatives,
+                quadratic,
+                guess,
+                location,
+                guess_location,
+            },
+        ))
     }
 }
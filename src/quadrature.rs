@@ -0,0 +1,145 @@
+/// Tolerance of `integrate` when none is given, as an absolute error on the result.
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+/// Integrand evaluations allowed before an integral is reported as not converging.
+pub const MAX_EVALUATIONS: usize = 100_000;
+/// How many times an interval can be halved.
+const MAX_DEPTH: u32 = 50;
+
+/// Why an integral could not be computed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuadratureError {
+    /// The tolerance was not met within `MAX_EVALUATIONS` or `MAX_DEPTH`. Holds the best value and its error estimate.
+    NotConverged { value: f64, error: f64 },
+    /// The integrand was infinite or NaN at this point.
+    NotFinite { at: f64 },
+}
+
+/// Integrates `f` from `a` to `b` with adaptive Simpson's rule, returning the value and an estimate of its error.
+/// Each interval is halved until Richardson extrapolation says its share of `tolerance` is met.
+pub fn adaptive_simpson(
+    f: impl Fn(f64) -> f64,
+    a: f64,
+    b: f64,
+    tolerance: f64,
+) -> Result<(f64, f64), QuadratureError> {
+    if a == b {
+        return Ok((0., 0.));
+    }
+    let mut simpson = Simpson {
+        f,
+        evaluations: 0,
+        converged: true,
+    };
+    let (fa, fb) = (simpson.eval(a)?, simpson.eval(b)?);
+    let m = (a + b) / 2.;
+    let fm = simpson.eval(m)?;
+    let whole = (b - a) / 6. * (fa + 4. * fm + fb);
+    let (value, error) = simpson.refine([a, m, b], [fa, fm, fb], whole, tolerance, MAX_DEPTH)?;
+
+    if simpson.converged {
+        Ok((value, error))
+    } else {
+        Err(QuadratureError::NotConverged { value, error })
+    }
+}
+
+struct Simpson<F> {
+    f: F,
+    evaluations: usize,
+    /// Cleared when an interval is accepted without meeting its tolerance.
+    converged: bool,
+}
+
+impl<F: Fn(f64) -> f64> Simpson<F> {
+    fn eval(&mut self, x: f64) -> Result<f64, QuadratureError> {
+        self.evaluations += 1;
+        let y = (self.f)(x);
+        if y.is_finite() {
+            Ok(y)
+        } else {
+            Err(QuadratureError::NotFinite { at: x })
+        }
+    }
+
+    /// Compares Simpson's rule on `[a, b]`, already computed as `whole`, with its two halves.
+    fn refine(
+        &mut self,
+        [a, m, b]: [f64; 3],
+        [fa, fm, fb]: [f64; 3],
+        whole: f64,
+        tolerance: f64,
+        depth: u32,
+    ) -> Result<(f64, f64), QuadratureError> {
+        let (lm, rm) = ((a + m) / 2., (m + b) / 2.);
+        let (flm, frm) = (self.eval(lm)?, self.eval(rm)?);
+        let left = (m - a) / 6. * (fa + 4. * flm + fm);
+        let right = (b - m) / 6. * (fm + 4. * frm + fb);
+        let delta = left + right - whole;
+        // The integrand is computed in `f32`, so anything finer than its precision is noise.
+        let threshold = tolerance.max(f32::EPSILON as f64 * (left + right).abs());
+        if delta.abs() <= 15. * threshold {
+            return Ok((left + right + delta / 15., delta.abs() / 15.));
+        }
+        if depth == 0 || self.evaluations >= MAX_EVALUATIONS {
+            self.converged = false;
+            return Ok((left + right + delta / 15., delta.abs() / 15.));
+        }
+
+        let (left_value, left_error) =
+            self.refine([a, lm, m], [fa, flm, fm], left, tolerance / 2., depth - 1)?;
+        let (right_value, right_error) =
+            self.refine([m, rm, b], [fm, frm, fb], right, tolerance / 2., depth - 1)?;
+        Ok((left_value + right_value, left_error + right_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cubics_are_exact() {
+        let (value, error) =
+            adaptive_simpson(|x| x * x * x - 2. * x, 0., 2., DEFAULT_TOLERANCE).unwrap();
+        assert_eq!((value, error), (0., 0.));
+        assert_eq!(
+            adaptive_simpson(|x| x, 1., 0., DEFAULT_TOLERANCE),
+            Ok((-0.5, 0.))
+        );
+        assert_eq!(
+            adaptive_simpson(|x| x, 3., 3., DEFAULT_TOLERANCE),
+            Ok((0., 0.))
+        );
+    }
+
+    #[test]
+    fn meets_the_tolerance() {
+        for tolerance in [1e-2, 1e-4, 1e-6] {
+            let (value, error) =
+                adaptive_simpson(f64::sin, 0., std::f64::consts::PI, tolerance).unwrap();
+            assert!(error <= tolerance, "estimated {error} for {tolerance}");
+            assert!((value - 2.).abs() <= tolerance, "{value} for {tolerance}");
+        }
+        // Finer than `f32` precision, the integrand is only noise.
+        let (value, error) = adaptive_simpson(f64::sin, 0., std::f64::consts::PI, 1e-12).unwrap();
+        assert!(error <= 2. * f32::EPSILON as f64);
+        assert!((value - 2.).abs() <= 2. * f32::EPSILON as f64);
+    }
+
+    #[test]
+    fn reports_what_it_cannot_integrate() {
+        // A jump cannot be resolved finer than `MAX_DEPTH` halvings.
+        let step = |x: f64| if x < 1. / 3. { 0. } else { 1. };
+        match adaptive_simpson(step, 0., 1., 1e-300) {
+            Err(QuadratureError::NotConverged { value, error }) => {
+                assert!((value - 2. / 3.).abs() < 1e-9);
+                assert!(error > 0.);
+            }
+            result => panic!("converged to {result:?}"),
+        }
+        assert_eq!(
+            adaptive_simpson(|x| 1. / x, 0., 1., DEFAULT_TOLERANCE),
+            Err(QuadratureError::NotFinite { at: 0. })
+        );
+    }
+}
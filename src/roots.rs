@@ -0,0 +1,231 @@
+/// Newton steps taken before falling back to bracketing.
+const MAX_NEWTON_STEPS: usize = 100;
+/// Iterations of Brent's method, which halves the bracket at worst every other step.
+const MAX_BRENT_STEPS: usize = 200;
+/// Each scan for sign changes samples this many points, over wider and wider ranges.
+const SCAN_SAMPLES: usize = 2000;
+const SCAN_RANGES: [f64; 3] = [10., 1e3, 1e6];
+
+/// Why a linear or quadratic equation has no roots to return.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootError {
+    /// A linear equation whose terms in the variable all cancel.
+    Degenerate { always: bool },
+}
+
+/// Roots of `a x^2 + b x + c`, as `(re, im)` pairs in ascending order of their real parts.
+pub fn quadratic(a: f64, b: f64, c: f64) -> Result<Vec<(f64, f64)>, RootError> {
+    if a == 0. {
+        if b == 0. {
+            return Err(RootError::Degenerate { always: c == 0. });
+        }
+        return Ok(vec![(-c / b, 0.)]);
+    }
+
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        let (re, im) = (-b / (2. * a), (-discriminant).sqrt() / (2. * a).abs());
+        return Ok(vec![(re, -im), (re, im)]);
+    }
+    // Avoids cancellation between `-b` and the square root.
+    let q = -(b + b.signum() * discriminant.sqrt()) / 2.;
+    let mut roots = if q == 0. {
+        vec![(0., 0.)]
+    } else if discriminant == 0. {
+        vec![(q / a, 0.)]
+    } else {
+        vec![(q / a, 0.), (c / q, 0.)]
+    };
+    roots.sort_by(|x, y| x.0.total_cmp(&y.0));
+    Ok(roots)
+}
+
+/// Newton's method from `guess`, or `None` if it stalls on a flat spot, diverges or runs out of steps.
+pub fn newton(f: impl Fn(f64) -> f64, df: impl Fn(f64) -> f64, guess: f64) -> Option<f64> {
+    let mut x = guess;
+    for _ in 0..MAX_NEWTON_STEPS {
+        let (y, slope) = (f(x), df(x));
+        if y == 0. {
+            return Some(x);
+        }
+        if slope == 0. || !slope.is_finite() || !y.is_finite() {
+            return None;
+        }
+        let step = y / slope;
+        x -= step;
+        // The equation is evaluated in `f32`, so this is as close as it gets.
+        if step.abs() <= 4. * f32::EPSILON as f64 * x.abs().max(1.) {
+            return f(x).is_finite().then_some(x);
+        }
+    }
+    None
+}
+
+/// Widens an interval around `guess` until `f` changes sign across it.
+pub fn bracket(f: impl Fn(f64) -> f64, guess: f64) -> Option<(f64, f64)> {
+    let mut width = guess.abs().max(1.) / 100.;
+    for _ in 0..64 {
+        let (a, b) = (guess - width, guess + width);
+        if changes_sign(f(a), f(guess)) {
+            return Some((a, guess));
+        }
+        if changes_sign(f(guess), f(b)) {
+            return Some((guess, b));
+        }
+        width *= 2.;
+    }
+    None
+}
+
+/// Every sign change of `f` on a grid, searching wider ranges until one has any.
+pub fn scan(f: impl Fn(f64) -> f64) -> Vec<(f64, f64)> {
+    for range in SCAN_RANGES {
+        let step = 2. * range / SCAN_SAMPLES as f64;
+        let points: Vec<(f64, f64)> = (0..=SCAN_SAMPLES)
+            .map(|n| {
+                let x = -range + n as f64 * step;
+                (x, f(x))
+            })
+            .collect();
+        let mut brackets: Vec<(f64, f64)> = points
+            .windows(2)
+            .filter(|pair| changes_sign(pair[0].1, pair[1].1))
+            .map(|pair| (pair[0].0, pair[1].0))
+            .collect();
+        // A sample that lands exactly on a root, but not a run of them, which is more likely underflow.
+        brackets.extend(
+            points
+                .windows(3)
+                .filter(|triple| triple[1].1 == 0. && triple[0].1 != 0. && triple[2].1 != 0.)
+                .map(|triple| (triple[1].0, triple[1].0)),
+        );
+        if !brackets.is_empty() {
+            return brackets;
+        }
+    }
+    vec![]
+}
+
+/// Brent's method on a bracket from `bracket` or `scan`. Returns `None` if the sign change turns out to be a pole.
+pub fn brent(f: impl Fn(f64) -> f64, a: f64, b: f64) -> Option<f64> {
+    let (mut a, mut b) = (a, b);
+    let (mut fa, mut fb) = (f(a), f(b));
+    if fa == 0. {
+        return Some(a);
+    }
+    if fb == 0. {
+        return Some(b);
+    }
+    let bound = fa.abs().min(fb.abs());
+    if fa.abs() < fb.abs() {
+        std::mem::swap(&mut a, &mut b);
+        std::mem::swap(&mut fa, &mut fb);
+    }
+    let (mut c, mut fc) = (a, fa);
+    let mut d = b - a;
+    let mut bisected = true;
+
+    for _ in 0..MAX_BRENT_STEPS {
+        let tolerance = 2. * f32::EPSILON as f64 * b.abs().max(1.);
+        if fb == 0. || (b - a).abs() <= tolerance {
+            break;
+        }
+        let mut s = if fa != fc && fb != fc {
+            // Inverse quadratic interpolation.
+            a * fb * fc / ((fa - fb) * (fa - fc))
+                + b * fa * fc / ((fb - fa) * (fb - fc))
+                + c * fa * fb / ((fc - fa) * (fc - fb))
+        } else {
+            // Secant.
+            b - fb * (b - a) / (fb - fa)
+        };
+        let between = (s - (3. * a + b) / 4.) * (s - b) < 0.;
+        let slow = if bisected {
+            (s - b).abs() >= (b - c).abs() / 2.
+        } else {
+            (s - b).abs() >= (c - d).abs() / 2.
+        };
+        bisected = !between || slow;
+        if bisected {
+            s = (a + b) / 2.;
+        }
+        let fs = f(s);
+        d = c;
+        c = b;
+        fc = fb;
+        if changes_sign(fa, fs) {
+            b = s;
+            fb = fs;
+        } else {
+            a = s;
+            fa = fs;
+        }
+        if fa.abs() < fb.abs() {
+            std::mem::swap(&mut a, &mut b);
+            std::mem::swap(&mut fa, &mut fb);
+        }
+    }
+
+    // Near a pole the function grows instead of vanishing.
+    (fb.is_finite() && fb.abs() <= bound).then_some(b)
+}
+
+fn changes_sign(x: f64, y: f64) -> bool {
+    (x < 0. && y > 0.) || (x > 0. && y < 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadratics() {
+        assert_eq!(quadratic(0., 2., -4.), Ok(vec![(2., 0.)]));
+        assert_eq!(quadratic(1., 0., -4.), Ok(vec![(-2., 0.), (2., 0.)]));
+        assert_eq!(quadratic(1., -2., 1.), Ok(vec![(1., 0.)]));
+        assert_eq!(quadratic(1., 0., 1.), Ok(vec![(0., -1.), (0., 1.)]));
+        assert_eq!(quadratic(1., 0., 0.), Ok(vec![(0., 0.)]));
+        assert_eq!(
+            quadratic(0., 0., 1.),
+            Err(RootError::Degenerate { always: false })
+        );
+        assert_eq!(
+            quadratic(0., 0., 0.),
+            Err(RootError::Degenerate { always: true })
+        );
+        // `-b` and the square root nearly cancel in the small root.
+        let roots = quadratic(1., -1e8, 1.).unwrap();
+        assert!((roots[0].0 - 1e-8).abs() < 1e-20);
+    }
+
+    #[test]
+    fn newton_stops_where_it_cannot_go_on() {
+        let root = newton(|x| x * x - 2., |x| 2. * x, 1.).unwrap();
+        assert!((root - 2f64.sqrt()).abs() < 1e-6);
+        // A flat spot at the guess.
+        assert_eq!(newton(|x| x * x + 1., |x| 2. * x, 0.), None);
+        // No real root to converge on.
+        assert_eq!(newton(|x| x * x + 1., |x| 2. * x, 1.), None);
+    }
+
+    #[test]
+    fn brackets() {
+        let f = |x: f64| x.powi(3) - 2. * x - 1.;
+        let (a, b) = bracket(f, 10.).unwrap();
+        assert!(f(a) * f(b) <= 0.);
+        let mut found: Vec<f64> = scan(f)
+            .into_iter()
+            .filter_map(|(a, b)| brent(f, a, b))
+            .collect();
+        // `-1` lands on the grid, so it comes after the sign changes.
+        found.sort_by(f64::total_cmp);
+        let expected = [-1., (1. - 5f64.sqrt()) / 2., (1. + 5f64.sqrt()) / 2.];
+        assert_eq!(found.len(), 3);
+        for (root, expected) in found.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{root} is not {expected}");
+        }
+        // A sign change across a pole is not a root.
+        assert_eq!(brent(|x| 1. / x, -1., 2.), None);
+        assert!(scan(|x| x * x + 1.).is_empty());
+    }
+}
@@ -0,0 +1,429 @@
+use std::path::Path;
+
+use miette::{Diagnostic, LabeledSpan, NamedSource, Report, miette};
+
+use crate::lexer::{Lexer, Statement};
+use crate::number::Number;
+use crate::{Token, TokenType};
+
+/// Variable bound to the most recent result.
+pub const LAST_RESULT: &str = "ans";
+
+/// Variables and functions defined by earlier programs, kept as the statements that defined them so
+/// that a saved session stays readable and can be edited by hand.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// The name each statement defines, and the statement, in the order they were made except that
+    /// each one comes after the definitions it uses.
+    definitions: Vec<(String, String)>,
+    /// What earlier programs printed, which later ones read as `_1`, `_2`, ... and `ans`. Only kept
+    /// for as long as the session runs.
+    results: Vec<Number>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the definitions in a file written by `save`, or by hand like any other program. Nothing
+    /// is added from a file that does not lex, since every program after it would fail, or from one
+    /// with a definition that cannot be kept.
+    pub fn load(&mut self, path: &Path) -> miette::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| miette!("Could not read `{}`: {e}", path.display()))?;
+        if !text.trim().is_empty()
+            && let Err(e) = Lexer::new(&text).lex()
+        {
+            let mut e = *e;
+            e.src = NamedSource::new(path.display().to_string(), text);
+            return Err(Report::new(e));
+        }
+        let mut loaded = self.clone();
+        let refused = loaded.record(&text);
+        if !refused.is_empty() {
+            return Err(miette!("{}", refused.join("\n")));
+        }
+        *self = loaded;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let lines: String = self
+            .definitions
+            .iter()
+            .map(|(_, statement)| format!("{statement}\n"))
+            .collect();
+        std::fs::write(path, lines)
+    }
+
+    /// Remembers the definitions `src` makes, each in the place of an earlier one of the same name,
+    /// so that what used the old definition still comes after it. The results `src` printed must
+    /// already be pushed, since `ans` and `_n` in a definition are replaced with the results they
+    /// stand for: the next program, or a saved session, would read them as different ones. Returns
+    /// why each definition that is not kept was not: a result it reads cannot be written back as a
+    /// number, or it uses a definition that uses it, which no saved session could load.
+    pub fn record(&mut self, src: &str) -> Vec<String> {
+        let statements = statements(src);
+        let defined: Vec<Option<String>> = statements.iter().map(|s| defined_name(s)).collect();
+        let assigned: Vec<String> = (self.definitions.iter().map(|(name, _)| name))
+            .chain(defined.iter().flatten())
+            .cloned()
+            .collect();
+        // The results printed before `src`, and then before each of its statements.
+        let mut printed = self
+            .results
+            .len()
+            .saturating_sub(defined.iter().filter(|name| name.is_none()).count());
+        let mut refused = vec![];
+        for (statement, name) in statements.into_iter().zip(defined) {
+            let Some(name) = name else {
+                printed += 1;
+                continue;
+            };
+            let Some(statement) = self.with_results(statement, printed, &assigned) else {
+                refused.push(format!(
+                    "`{name}` reads a result that cannot be written as a number, so it is not kept."
+                ));
+                continue;
+            };
+            let before = self.definitions.clone();
+            match self
+                .definitions
+                .iter()
+                .position(|(defined, _)| *defined == name)
+            {
+                Some(n) => self.definitions[n] = (name.clone(), statement),
+                None => self.definitions.push((name.clone(), statement)),
+            }
+            if let Err(cycle) = self.order() {
+                self.definitions = before;
+                let cycle: Vec<String> = cycle.iter().map(|name| format!("`{name}`")).collect();
+                refused.push(format!(
+                    "`{name}` is not kept, since {} would be defined in terms of each other.",
+                    cycle.join(", ")
+                ));
+            }
+        }
+        refused
+    }
+
+    /// `statement` with each `ans` and `_n` it reads written as the value of the result, where
+    /// `printed` results came before it and `assigned` are the names the session defines, or `None`
+    /// if one of them has no literal.
+    fn with_results(&self, statement: &str, printed: usize, assigned: &[String]) -> Option<String> {
+        let lexed = Lexer::new(statement).lex();
+        let (params, tokens) = match lexed.as_deref() {
+            Ok([Statement::Assign(_, tokens)]) => (&[][..], tokens),
+            Ok([Statement::Function(_, params, tokens)]) => (&params[..], tokens),
+            _ => return Some(statement.to_owned()),
+        };
+        let mut statement = statement.to_owned();
+        for token in tokens.iter().rev() {
+            let (TokenType::Id(name), Some(location)) = (&token.token_type, token.location) else {
+                continue;
+            };
+            let param = params
+                .iter()
+                .any(|param| param.token_type == token.token_type);
+            if param || assigned.contains(name) {
+                continue;
+            }
+            let result = if name == LAST_RESULT {
+                self.results[..printed].last()
+            } else {
+                name.strip_prefix('_')
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .and_then(|n| self.results.get(n.checked_sub(1)?))
+            };
+            if let Some(result) = result {
+                statement.replace_range(location.start..location.end, &literal(result)?);
+            }
+        }
+        Some(statement)
+    }
+
+    /// Moves each definition after the ones it uses, which a new definition in place of an old one
+    /// can use even if they were made after it. Definitions that use each other cannot be ordered,
+    /// so they are left as they were and their names returned.
+    fn order(&mut self) -> Result<(), Vec<String>> {
+        let names: Vec<String> = self
+            .definitions
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut uses: Vec<Vec<String>> = self
+            .definitions
+            .iter()
+            .map(|(name, statement)| {
+                let mut used = used_names(statement);
+                used.retain(|used| used != name && names.contains(used));
+                used
+            })
+            .collect();
+        let mut remaining = self.definitions.clone();
+        let mut ordered = vec![];
+        while !remaining.is_empty() {
+            let Some(next) = uses.iter().position(|used| used.is_empty()) else {
+                return Err(remaining.into_iter().map(|(name, _)| name).collect());
+            };
+            let (name, statement) = remaining.remove(next);
+            uses.remove(next);
+            for used in &mut uses {
+                used.retain(|used| *used != name);
+            }
+            ordered.push((name, statement));
+        }
+        self.definitions = ordered;
+        Ok(())
+    }
+
+    /// Keeps `result` as the next `_n` and as `ans` for the programs after it.
+    pub fn push_result(&mut self, result: Number) {
+        self.results.push(result);
+    }
+
+    /// Every result printed so far, which `Compiler::with_results` defines for the next program.
+    pub fn results(&self) -> &[Number] {
+        &self.results
+    }
+
+    /// `src` preceded by every definition, so that it can use them.
+    pub fn program(&self, src: &str) -> String {
+        self.definitions
+            .iter()
+            .map(|(_, statement)| statement.as_str())
+            .chain([src])
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// `report`, an error in `program(src)`, shown against `src` alone when that is all it points at,
+    /// rather than after every definition made so far.
+    pub fn in_source(&self, src: &str, report: Report) -> Report {
+        let offset = self.program(src).len() - src.len();
+        let in_src = report
+            .labels()
+            .is_some_and(|mut labels| labels.all(|label| label.offset() >= offset));
+        if !in_src {
+            return report;
+        }
+        Report::new(InSource {
+            report,
+            src: NamedSource::new("input", src.to_owned()),
+            offset,
+        })
+    }
+}
+
+/// A diagnostic moved from the program a session ran onto the source it was given.
+#[derive(Debug)]
+struct InSource {
+    report: Report,
+    src: NamedSource<String>,
+    offset: usize,
+}
+
+impl std::fmt::Display for InSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.report, f)
+    }
+}
+
+impl std::error::Error for InSource {}
+
+impl Diagnostic for InSource {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.report.code()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.report.help()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        Some(&self.src)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let labels = self.report.labels()?.map(|label| {
+            let span = (label.offset() - self.offset, label.len());
+            let text = label.label().map(str::to_owned);
+            if label.primary() {
+                LabeledSpan::new_primary_with_span(text, span)
+            } else {
+                LabeledSpan::new_with_span(text, span)
+            }
+        });
+        Some(Box::new(labels))
+    }
+}
+
+fn statements(src: &str) -> Vec<&str> {
+    if src.trim().is_empty() {
+        return vec![];
+    }
+    Lexer::new(src)
+        .statements()
+        .into_iter()
+        .map(|statement| *statement.fragment())
+        .collect()
+}
+
+/// `value` written so that it lexes back to itself, for the kinds of value that can be.
+fn literal(value: &Number) -> Option<String> {
+    let text = match value {
+        Number::Float(x) if x.is_finite() => x.to_string(),
+        Number::Decimal(d) => d.to_string(),
+        Number::Matrix(m) if m.data.iter().all(|x| x.is_finite()) => m.to_string(),
+        _ => return None,
+    };
+    Some(if text.starts_with('-') {
+        format!("({text})")
+    } else {
+        text
+    })
+}
+
+/// The variables and functions a definition reads, other than its parameters.
+fn used_names(statement: &str) -> Vec<String> {
+    let id = |token: &Token| match &token.token_type {
+        TokenType::Id(name) | TokenType::Call(name, _) | TokenType::Grad(name, _) => {
+            Some(name.clone())
+        }
+        _ => None,
+    };
+    let (params, tokens) = match Lexer::new(statement).lex().as_deref() {
+        Ok([Statement::Assign(_, tokens)]) => (vec![], tokens.clone()),
+        Ok([Statement::Function(_, params, tokens)]) => {
+            (params.iter().filter_map(id).collect(), tokens.clone())
+        }
+        _ => return vec![],
+    };
+    tokens
+        .iter()
+        .filter_map(id)
+        .filter(|name| !params.contains(name))
+        .collect()
+}
+
+/// The variable or function a single statement assigns, if it is a definition.
+fn defined_name(statement: &str) -> Option<String> {
+    let statements = Lexer::new(statement).lex().ok()?;
+    match statements.first()? {
+        Statement::Assign(
+            Token {
+                token_type: TokenType::Id(name),
+                ..
+            },
+            _,
+        )
+        | Statement::Function(
+            Token {
+                token_type: TokenType::Id(name),
+                ..
+            },
+            ..,
+        ) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::Complex;
+
+    /// A session that ran each of `lines` in turn.
+    fn recorded(lines: &[&str]) -> Session {
+        let mut session = Session::new();
+        for line in lines {
+            session.record(line);
+        }
+        session
+    }
+
+    /// A file in a directory of its own for `name`, which is removed first if an earlier run left it behind.
+    fn file(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mathling-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("session")
+    }
+
+    #[test]
+    fn redefinitions_take_the_old_place() {
+        let session = recorded(&["f(x) = x", "g(x) = f(x) + 1; 2 + 2", "f(x) = 2*x"]);
+        assert_eq!(session.program("g(1)"), "f(x) = 2*x; g(x) = f(x) + 1; g(1)");
+        let session = recorded(&["x = 1", "y = x + 1", "x = 3"]);
+        assert_eq!(session.program("y"), "x = 3; y = x + 1; y");
+    }
+
+    #[test]
+    fn definitions_come_after_what_they_use() {
+        let session = recorded(&["f(x) = x", "h(x) = 5", "f(x) = h(x) + 1"]);
+        assert_eq!(session.program("f(0)"), "h(x) = 5; f(x) = h(x) + 1; f(0)");
+        // A definition that would make two use each other is not kept.
+        let mut session = recorded(&["a = 1", "b = a"]);
+        assert_eq!(
+            session.record("a = b"),
+            ["`a` is not kept, since `a`, `b` would be defined in terms of each other."]
+        );
+        assert_eq!(session.program("a"), "a = 1; b = a; a");
+    }
+
+    #[test]
+    fn definitions_keep_the_results_they_read() {
+        let mut session = recorded(&["2 + 3"]);
+        session.push_result(Number::Float(5.));
+        session.push_result(Number::Float(-1.));
+        assert!(session.record("y = ans*2; 0 - 1; z = _1 + ans").is_empty());
+        assert_eq!(session.program("z"), "y = 5*2; z = 5 + (-1); z");
+        session.push_result(Number::Float(6.));
+        session.record("f(x) = x + ans; f(_1)");
+        assert_eq!(
+            session.program("z"),
+            "y = 5*2; z = 5 + (-1); f(x) = x + (-1); z"
+        );
+        // A variable named like a result is not one.
+        let mut session = recorded(&["ans = 3"]);
+        session.push_result(Number::Float(4.));
+        session.record("w = ans + _1; 4");
+        assert_eq!(session.program("w"), "ans = 3; w = ans + 4; w");
+        // Nor is a result written back when it is not a number the lexer reads.
+        session.push_result(Number::Complex(Complex { re: 0., im: 1. }));
+        assert_eq!(
+            session.record("i = _2; sqrt(-1)"),
+            ["`i` reads a result that cannot be written as a number, so it is not kept."]
+        );
+        assert_eq!(session.program("i"), "ans = 3; w = ans + 4; i");
+    }
+
+    #[test]
+    fn saved_sessions_load() {
+        let path = file("save");
+        recorded(&["k = 2", "y = k*2", "y", "k = 3"])
+            .save(&path)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "k = 3\ny = k*2\n");
+        let mut loaded = recorded(&["k = 1", "z = 0"]);
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.program("y"), "k = 3; z = 0; y = k*2; y");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn files_that_cannot_be_kept_are_not_loaded() {
+        let path = file("load");
+        std::fs::write(&path, "a = 1\nb = 2 $ 3\n").unwrap();
+        let mut session = recorded(&["c = 4"]);
+        let report = session.load(&path).unwrap_err();
+        assert_eq!(report.to_string(), "Unexpected character `$`");
+        assert_eq!(session.program("c"), "c = 4; c");
+        assert!(session.load(&path.with_extension("missing")).is_err());
+        std::fs::write(&path, "d = 1\na = b\nb = a\n").unwrap();
+        assert!(session.load(&path).is_err());
+        assert_eq!(session.program("c"), "c = 4; c");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,227 @@
+/// Widest bar of a printed histogram, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Why a statistic could not be computed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatsError {
+    /// The statistic needs at least this many values.
+    TooFew(usize),
+    /// A percentile outside `0..=100`, or a bin count that is not a positive whole number.
+    OutOfRange,
+}
+
+pub fn sum(values: &[f32]) -> f32 {
+    values.iter().map(|x| *x as f64).sum::<f64>() as f32
+}
+
+pub fn mean(values: &[f32]) -> Result<f32, StatsError> {
+    at_least_one(values)?;
+    Ok((values.iter().map(|x| *x as f64).sum::<f64>() / values.len() as f64) as f32)
+}
+
+pub fn median(values: &[f32]) -> Result<f32, StatsError> {
+    percentile(values, 50.)
+}
+
+/// The most frequent value, or the smallest of them when several are equally frequent.
+pub fn mode(values: &[f32]) -> Result<f32, StatsError> {
+    at_least_one(values)?;
+    let sorted = sorted(values);
+    let mut best = (sorted[0], 0);
+    for run in sorted.chunk_by(|x, y| x == y) {
+        if run.len() > best.1 {
+            best = (run[0], run.len());
+        }
+    }
+    Ok(best.0)
+}
+
+/// The sample variance, or the population variance when `population` is set.
+pub fn variance(values: &[f32], population: bool) -> Result<f32, StatsError> {
+    if !population && values.len() < 2 {
+        return Err(StatsError::TooFew(2));
+    }
+    let mean = mean(values)? as f64;
+    let squares: f64 = values.iter().map(|x| (*x as f64 - mean).powi(2)).sum();
+    let n = values.len() - if population { 0 } else { 1 };
+    Ok((squares / n as f64) as f32)
+}
+
+/// Interpolates linearly between the two closest ranks, so the 0th and 100th percentiles are the extremes.
+pub fn percentile(values: &[f32], p: f32) -> Result<f32, StatsError> {
+    if !(0. ..=100.).contains(&p) {
+        return Err(StatsError::OutOfRange);
+    }
+    at_least_one(values)?;
+    let sorted = sorted(values);
+    let rank = p as f64 / 100. * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    let fraction = rank - below as f64;
+    Ok((sorted[below] as f64 + (sorted[above] as f64 - sorted[below] as f64) * fraction) as f32)
+}
+
+pub fn min(values: &[f32]) -> Result<f32, StatsError> {
+    at_least_one(values)?;
+    Ok(values.iter().copied().fold(f32::INFINITY, f32::min))
+}
+
+pub fn max(values: &[f32]) -> Result<f32, StatsError> {
+    at_least_one(values)?;
+    Ok(values.iter().copied().fold(f32::NEG_INFINITY, f32::max))
+}
+
+/// Most statistics of no values at all are undefined, unlike the sum and the count.
+fn at_least_one(values: &[f32]) -> Result<(), StatsError> {
+    if values.is_empty() {
+        return Err(StatsError::TooFew(1));
+    }
+    Ok(())
+}
+
+fn sorted(values: &[f32]) -> Vec<f32> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    sorted
+}
+
+/// The result of `histogram`: how many values fall into each of a number of equal-width bins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// One more edge than there are bins. Every bin includes its lower edge, and the last also its upper one.
+    pub edges: Vec<f32>,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// Spreads the bins over the range of the values. Without a bin count, Sturges' rule picks one.
+    pub fn new(values: &[f32], bins: Option<f32>) -> Result<Self, StatsError> {
+        let bins = match bins {
+            Some(bins) if bins >= 1. && bins.fract() == 0. => bins as usize,
+            Some(_) => return Err(StatsError::OutOfRange),
+            None => (values.len() as f64).log2().ceil() as usize + 1,
+        };
+        let (low, high) = (min(values)?, max(values)?);
+        // Every value is the same, so a single bin holds them all.
+        if low == high {
+            return Ok(Histogram {
+                edges: vec![low, high],
+                counts: vec![values.len()],
+            });
+        }
+        let width = (high - low) as f64 / bins as f64;
+        let edges = (0..=bins)
+            .map(|n| (low as f64 + n as f64 * width) as f32)
+            .collect();
+        let mut counts = vec![0; bins];
+        for x in values {
+            let bin = ((*x - low) as f64 / width) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+        Ok(Histogram { edges, counts })
+    }
+}
+
+impl std::fmt::Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total: usize = self.counts.iter().sum();
+        let bins = match self.counts.len() {
+            1 => "1 bin".to_owned(),
+            n => format!("{n} bins"),
+        };
+        write!(f, "{total} values in {bins}")?;
+        let ranges: Vec<String> = self
+            .edges
+            .windows(2)
+            .enumerate()
+            .map(|(n, edges)| {
+                let close = if n == self.counts.len() - 1 { ']' } else { ')' };
+                format!("[{}, {}{close}", edges[0], edges[1])
+            })
+            .collect();
+        let range_width = ranges.iter().map(String::len).max().unwrap_or(0);
+        let most = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let count_width = most.to_string().len();
+        for (range, count) in ranges.iter().zip(&self.counts) {
+            let bar = "#".repeat(count * HISTOGRAM_WIDTH / most);
+            let line = format!("  {range:<range_width$}  {count:>count_width$} {bar}");
+            write!(f, "\n{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f32; 6] = [4., 1., 2., 2., 8., 3.];
+
+    #[test]
+    fn averages() {
+        assert_eq!(mean(&VALUES), Ok(10. / 3.));
+        assert_eq!(median(&VALUES), Ok(2.5));
+        assert_eq!(median(&[3., 1., 2.]), Ok(2.));
+        assert_eq!(mode(&VALUES), Ok(2.));
+        // The smallest of the most frequent values.
+        assert_eq!(mode(&[3., 1., 3., 1.]), Ok(1.));
+    }
+
+    #[test]
+    fn spread() {
+        assert_eq!(variance(&[1., 2., 3., 4.], false), Ok(5. / 3.));
+        assert_eq!(variance(&[1., 2., 3., 4.], true), Ok(1.25));
+        assert_eq!(min(&VALUES), Ok(1.));
+        assert_eq!(max(&VALUES), Ok(8.));
+        assert_eq!(sum(&VALUES), 20.);
+    }
+
+    #[test]
+    fn percentiles() {
+        assert_eq!(percentile(&VALUES, 0.), Ok(1.));
+        assert_eq!(percentile(&VALUES, 100.), Ok(8.));
+        // Halfway between the ranks of 3 and 4.
+        assert_eq!(percentile(&VALUES, 70.), Ok(3.5));
+        assert_eq!(percentile(&VALUES, 101.), Err(StatsError::OutOfRange));
+        assert_eq!(percentile(&VALUES, -1.), Err(StatsError::OutOfRange));
+    }
+
+    #[test]
+    fn too_few_values() {
+        assert_eq!(mean(&[]), Err(StatsError::TooFew(1)));
+        assert_eq!(median(&[]), Err(StatsError::TooFew(1)));
+        assert_eq!(mode(&[]), Err(StatsError::TooFew(1)));
+        assert_eq!(percentile(&[], 50.), Err(StatsError::TooFew(1)));
+        assert_eq!(min(&[]), Err(StatsError::TooFew(1)));
+        assert_eq!(max(&[]), Err(StatsError::TooFew(1)));
+        assert_eq!(variance(&[1.], false), Err(StatsError::TooFew(2)));
+        assert_eq!(variance(&[], true), Err(StatsError::TooFew(1)));
+        assert_eq!(Histogram::new(&[], None), Err(StatsError::TooFew(1)));
+        assert_eq!(sum(&[]), 0.);
+    }
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::new(&[0., 1., 2., 3., 4.], Some(2.)).unwrap();
+        assert_eq!(histogram.edges, [0., 2., 4.]);
+        // The last bin includes its upper edge.
+        assert_eq!(histogram.counts, [2, 3]);
+        // Sturges' rule: ceil(log2(5)) + 1 bins.
+        assert_eq!(
+            Histogram::new(&[0., 1., 2., 3., 4.], None)
+                .unwrap()
+                .counts
+                .len(),
+            4
+        );
+        let same = Histogram::new(&[2., 2.], None).unwrap();
+        assert_eq!((same.edges, same.counts), (vec![2., 2.], vec![2]));
+        assert_eq!(
+            Histogram::new(&VALUES, Some(0.)),
+            Err(StatsError::OutOfRange)
+        );
+        assert_eq!(
+            Histogram::new(&VALUES, Some(1.5)),
+            Err(StatsError::OutOfRange)
+        );
+    }
+}
@@ -1,15 +1,157 @@
-use std::collections::{HashMap, hash_map::Iter};
+use std::collections::HashMap;
+
+use miette::NamedSource;
 
 use crate::{
     Token, TokenType,
-    lexer::{BinOp, Expr},
-    math_lexing::MathLexer,
+    error::{ConstantAssignmentError, EvalError},
+    lexer::Statement,
+    number::{Complex, Decimal, DecimalError, Number, NumberMode},
+    parse, span,
 };
 
+/// A read-only name that is defined before any user variable.
+pub struct Constant {
+    /// Every spelling of the constant; the first is the ASCII one.
+    pub names: &'static [&'static str],
+    pub value: f64,
+    pub description: &'static str,
+}
+
+pub const CONSTANTS: [Constant; 6] = [
+    Constant {
+        names: &["pi", "π"],
+        value: std::f64::consts::PI,
+        description: "ratio of a circle's circumference to its diameter",
+    },
+    Constant {
+        names: &["tau", "τ"],
+        value: std::f64::consts::TAU,
+        description: "ratio of a circle's circumference to its radius",
+    },
+    Constant {
+        names: &["e"],
+        value: std::f64::consts::E,
+        description: "base of the natural logarithm",
+    },
+    Constant {
+        names: &["phi", "φ"],
+        value: 1.618_033_988_749_895,
+        description: "golden ratio",
+    },
+    Constant {
+        names: &["inf"],
+        value: f64::INFINITY,
+        description: "positive infinity",
+    },
+    Constant {
+        names: &["nan"],
+        value: f64::NAN,
+        description: "not a number",
+    },
+];
+
+pub fn find_constant(id: &str) -> Option<&'static Constant> {
+    CONSTANTS
+        .iter()
+        .find(|constant| constant.names.contains(&id))
+}
+
+/// The value of a constant in the given number mode. Decimal mode rounds it to the configured scale,
+/// and has no value for `inf` and `nan`, or for a constant too large for the scale.
+pub fn lookup_constant(id: &str, number_mode: NumberMode) -> Option<Result<Number, DecimalError>> {
+    let constant = find_constant(id)?;
+    Some(match number_mode {
+        NumberMode::Float => Ok(Number::Float(constant.value as f32)),
+        NumberMode::Decimal(_) if !constant.value.is_finite() => Err(DecimalError::NotFinite),
+        NumberMode::Decimal(config) => {
+            let digits = format!("{:.18}", constant.value);
+            let (integral, fractional) = digits.split_once('.').unwrap();
+            Decimal::from_literal(integral, Some(fractional), config).map(Number::Decimal)
+        }
+    })
+}
+
+/// Constants are resolved before variables, so an assignment to one would silently do nothing, and a
+/// parameter or a variable of `sum`, `prod`, `integrate`, `solve` or `d/dx` named after one would be
+/// the constant wherever it is used.
+pub fn check_assignments(
+    src: &str,
+    statements: &[Statement],
+) -> Result<(), Box<ConstantAssignmentError>> {
+    for statement in statements {
+        let (names, tokens) = match statement {
+            Statement::Assign(target, tokens) => (vec![target], tokens),
+            Statement::Function(target, params, tokens) => {
+                (std::iter::once(target).chain(params).collect(), tokens)
+            }
+            Statement::Print(tokens, ..) => (vec![], tokens),
+        };
+        for token in names.into_iter().cloned().chain(bound_variables(tokens)) {
+            let name = match &token.token_type {
+                TokenType::Id(id) | TokenType::Derivative(Some(id)) => id.as_str(),
+                // The constant has already been read as its value.
+                _ => token.location.map_or("", |l| &src[l.start..l.end]),
+            };
+            if let Some(constant) = find_constant(name) {
+                return Err(Box::new(ConstantAssignmentError {
+                    src: NamedSource::new("input", src.to_owned()),
+                    name: name.to_owned(),
+                    span: span(token.location),
+                    description: constant.description,
+                }));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The tokens that name the variable of each `sum`, `prod`, `integrate`, `solve` and `d/dx` in `tokens`.
+fn bound_variables(tokens: &[Token]) -> Vec<Token> {
+    let mut variables = vec![];
+    for (n, token) in tokens.iter().enumerate() {
+        let (position, arity) = match token.token_type {
+            TokenType::Derivative(Some(_)) => {
+                variables.push(token.clone());
+                continue;
+            }
+            TokenType::Reduction(_) => (0, Some(4)),
+            TokenType::Integrate | TokenType::Solve => (1, None),
+            _ => continue,
+        };
+        let Some(args) = parse::Parser::gather_args(&mut tokens[n + 1..].iter().cloned()) else {
+            continue;
+        };
+        if arity.is_some_and(|arity| args.len() != arity) {
+            continue;
+        }
+        if let Some([variable]) = args.get(position).map(Vec::as_slice) {
+            variables.push(variable.clone());
+        }
+    }
+    variables
+}
+
+/// Identifier that evaluates to the imaginary unit unless a variable of the same name is in scope.
+pub const IMAGINARY_UNIT: &str = "i";
+
+/// Resolves identifiers that are not user variables, which is only the imaginary unit. Units are
+/// only units after a number or after `in` and `to`, so `s` on its own is an unknown variable.
+pub fn lookup_builtin(id: &str) -> Option<Number> {
+    (id == IMAGINARY_UNIT).then_some(Number::Complex(Complex::I))
+}
+
 pub struct SymbolTable<O: Clone> {
     pub variables: HashMap<String, O>,
 }
 
+impl<O: Clone + std::fmt::Debug> Default for SymbolTable<O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<O: Clone + std::fmt::Debug> SymbolTable<O> {
     pub fn new() -> Self {
         Self {
@@ -27,83 +169,72 @@ impl<O: Clone + std::fmt::Debug> SymbolTable<O> {
 }
 
 impl SymbolTable<Vec<Token>> {
-    pub fn simplify(&self) -> SymbolTable<f32> {
-        let mut fp_symbol_table = SymbolTable::<f32>::new();
-        for (id, tokens) in self.variables.clone() {
-            let mut stack: Vec<Token> = vec![];
-            dbg!(&tokens);
-            for token in tokens {
-                match token {
-                    Token {
-                        token_type: TokenType::Fp(ref n),
-                        ..
-                    } => stack.push(token),
-                    Token {
-                        token_type: TokenType::BinOp(op),
-                        ..
-                    } => {
-                        // Made `mut` so they can be made into floats if the operator is division.
-                        let mut y = match stack.pop() {
-                            Some(Token {
-                                token_type: TokenType::Fp(n),
-                                ..
-                            }) => n,
-                            _ => {
-                                panic!("Ill-formed expression.")
-                            }
-                        };
-                        let mut x = match stack.pop() {
-                            Some(Token {
-                                token_type: TokenType::Fp(n),
-                                ..
-                            }) => n,
-                            _ => panic!("Ill-formed expression or variable reference."),
-                        };
-                        match op {
-                            BinOp::Plus => {
-                                stack.push(Token {
-                                    token_type: TokenType::Fp(x + y),
-                                    location_col: None,
-                                });
-                            }
-                            BinOp::Minus => {
-                                stack.push(Token {
-                                    token_type: TokenType::Fp(x - y),
-                                    location_col: None,
-                                });
-                            }
-                            BinOp::Times => {
-                                stack.push(Token {
-                                    token_type: TokenType::Fp(x * y),
-                                    location_col: None,
-                                });
-                            }
-                            BinOp::Divide => {
-                                stack.push(Token {
-                                    token_type: TokenType::Fp(x / y),
-                                    location_col: None,
-                                });
-                            }
-                            BinOp::Equal => unreachable!(),
-                        }
-                    }
-                    _ => (),
-                }
-            }
-
-            assert!(
-                stack.len() == 1,
-                "Evaluator stack is not of length 1. There exists an ill-formed expression."
-            );
-            match stack.first().unwrap() {
-                Token {
-                    token_type: TokenType::Fp(n),
-                    ..
-                } => fp_symbol_table.variables.insert(id, *n),
-                _ => panic!("Error: After eval, last token is NOT a number."),
+    /// Evaluates with `eval`, the same evaluator as the printed expression, every variable that is not
+    /// in `values` yet and does not read a result in `later`, which are still to be printed. Definitions
+    /// can contain loops and function calls that only exist as compiled code, hence the evaluator.
+    pub fn evaluate(
+        &self,
+        values: &mut SymbolTable<Number>,
+        later: &[String],
+        eval: impl Fn(&[Token], &SymbolTable<Number>) -> Result<Number, Box<EvalError>>,
+    ) -> Result<(), Box<EvalError>> {
+        let mut pending: Vec<(&String, &Vec<Token>)> = self
+            .variables
+            .iter()
+            .filter(|(id, _)| values.lookup(id).is_none())
+            .collect();
+        while !pending.is_empty() {
+            // A variable is evaluated once the variables it reads are. If none is ready and no result
+            // is still to come, the one left reads itself, and `eval` reports that.
+            let ready = pending.iter().position(|(_, tokens)| {
+                let mut free = vec![];
+                parse::free_variables(tokens, &mut vec![], &mut free);
+                free.iter().all(|id| {
+                    values.lookup(id).is_some()
+                        || (self.lookup(id).is_none() && !later.contains(id))
+                })
+            });
+            let ready = match ready {
+                Some(ready) => ready,
+                None if later.is_empty() => 0,
+                None => break,
             };
+            let (id, tokens) = pending.swap_remove(ready);
+            let n = eval(tokens, values)?;
+            values.variables.insert(id.clone(), n);
         }
 
-        fp_symbol_table
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    /// The constant `src` wrongly binds, if any.
+    fn rebound(src: &str) -> Option<String> {
+        let statements = Lexer::new(src).lex().unwrap();
+        check_assignments(src, &statements)
+            .err()
+            .map(|error| error.name)
+    }
+
+    #[test]
+    fn constants_cannot_be_bound() {
+        assert_eq!(rebound("e = 3"), Some("e".to_owned()));
+        assert_eq!(rebound("pi(x) = x"), Some("pi".to_owned()));
+        assert_eq!(rebound("f(x, e) = x * e; f(1, 3)"), Some("e".to_owned()));
+        assert_eq!(rebound("sum(tau, 1, 3, tau)"), Some("tau".to_owned()));
+        assert_eq!(rebound("2 * integrate(e^2, e, 0, 1)"), Some("e".to_owned()));
+        assert_eq!(rebound("solve(phi^2 = 2, phi)"), Some("phi".to_owned()));
+        assert_eq!(rebound("d/de(e^2)"), Some("e".to_owned()));
+        // Constants can still be used anywhere else.
+        assert_eq!(
+            rebound("f(x) = x * e; sum(k, 1, 3, pi * k); sum([e, 1])"),
+            None
+        );
+        assert_eq!(rebound("integrate(x, x, 0, pi); solve(x = e, x, pi)"), None);
     }
 }
@@ -0,0 +1,731 @@
+use std::collections::HashMap;
+
+use miette::{LabeledSpan, NamedSource};
+
+use crate::{
+    Location, Token, TokenType, covering,
+    error::DimensionError,
+    lexer::{BinOp, Builtin},
+    number::{Number, Shown},
+    span, start,
+    symbols::SymbolTable,
+};
+
+/// Exponents of the base dimensions a quantity is measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dimension {
+    length: i8,
+    mass: i8,
+    time: i8,
+    data: i8,
+}
+
+/// Symbols the base dimensions are displayed with, in the same order as the fields of `Dimension`.
+const BASE_SYMBOLS: [&str; 4] = ["m", "kg", "s", "B"];
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension::new(0, 0, 0, 0);
+    pub const LENGTH: Dimension = Dimension::new(1, 0, 0, 0);
+    pub const MASS: Dimension = Dimension::new(0, 1, 0, 0);
+    pub const TIME: Dimension = Dimension::new(0, 0, 1, 0);
+    pub const DATA: Dimension = Dimension::new(0, 0, 0, 1);
+
+    const fn new(length: i8, mass: i8, time: i8, data: i8) -> Self {
+        Self {
+            length,
+            mass,
+            time,
+            data,
+        }
+    }
+
+    fn exponents(&self) -> [i8; 4] {
+        [self.length, self.mass, self.time, self.data]
+    }
+
+    fn from_exponents([length, mass, time, data]: [i8; 4]) -> Self {
+        Self::new(length, mass, time, data)
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        *self == Dimension::NONE
+    }
+
+    /// `None` when a power would leave a fractional exponent, such as `sqrt(2 s)`.
+    pub fn powf(self, exp: f32) -> Option<Self> {
+        let mut exponents = self.exponents();
+        for e in exponents.iter_mut() {
+            let scaled = *e as f32 * exp;
+            if scaled.fract() != 0. {
+                return None;
+            }
+            *e = scaled as i8;
+        }
+        Some(Self::from_exponents(exponents))
+    }
+
+    /// Plain-language name used in diagnostics, e.g. `length/time`.
+    pub fn describe(&self) -> String {
+        if self.is_dimensionless() {
+            return "dimensionless".to_owned();
+        }
+        let names = ["length", "mass", "time", "data"];
+        Dimension::compose(&names, self.exponents())
+    }
+
+    fn compose(symbols: &[&str], exponents: [i8; 4]) -> String {
+        let term = |symbol: &str, e: i8| match e {
+            1 => symbol.to_owned(),
+            e => format!("{symbol}^{e}"),
+        };
+        let numerator: Vec<String> = exponents
+            .iter()
+            .zip(symbols)
+            .filter(|(e, _)| **e > 0)
+            .map(|(e, s)| term(s, *e))
+            .collect();
+        let denominator: Vec<String> = exponents
+            .iter()
+            .zip(symbols)
+            .filter(|(e, _)| **e < 0)
+            .map(|(e, s)| term(s, -*e))
+            .collect();
+
+        let mut composed = if numerator.is_empty() {
+            "1".to_owned()
+        } else {
+            numerator.join("*")
+        };
+        for d in denominator {
+            composed.push('/');
+            composed.push_str(&d);
+        }
+        composed
+    }
+}
+
+impl std::ops::Mul for Dimension {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let (a, b) = (self.exponents(), other.exponents());
+        Self::from_exponents(std::array::from_fn(|i| a[i] + b[i]))
+    }
+}
+
+impl std::ops::Div for Dimension {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let (a, b) = (self.exponents(), other.exponents());
+        Self::from_exponents(std::array::from_fn(|i| a[i] - b[i]))
+    }
+}
+
+impl std::fmt::Display for Dimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Dimension::compose(&BASE_SYMBOLS, self.exponents()))
+    }
+}
+
+/// A named unit: how many base units one of it is worth, and what it measures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    pub factor: f32,
+    pub dimension: Dimension,
+}
+
+struct BaseUnit {
+    symbol: &'static str,
+    factor: f32,
+    dimension: Dimension,
+    prefixes: Prefixes,
+}
+
+#[derive(PartialEq)]
+enum Prefixes {
+    None,
+    Si,
+    /// SI prefixes as well as the binary ones (`Ki`, `Mi`, ...).
+    SiAndBinary,
+}
+
+const SI_PREFIXES: [(&str, f32); 10] = [
+    ("p", 1e-12),
+    ("n", 1e-9),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("m", 1e-3),
+    ("c", 1e-2),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+    ("T", 1e12),
+];
+
+const BINARY_PREFIXES: [(&str, f32); 5] = [
+    ("Ki", 1024.),
+    ("Mi", 1048576.),
+    ("Gi", 1073741824.),
+    ("Ti", 1099511627776.),
+    ("Pi", 1125899906842624.),
+];
+
+const REGISTRY: [BaseUnit; 14] = [
+    BaseUnit {
+        symbol: "m",
+        factor: 1.,
+        dimension: Dimension::LENGTH,
+        prefixes: Prefixes::Si,
+    },
+    BaseUnit {
+        symbol: "inch",
+        factor: 0.0254,
+        dimension: Dimension::LENGTH,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "ft",
+        factor: 0.3048,
+        dimension: Dimension::LENGTH,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "yd",
+        factor: 0.9144,
+        dimension: Dimension::LENGTH,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "mi",
+        factor: 1609.344,
+        dimension: Dimension::LENGTH,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "g",
+        factor: 1e-3,
+        dimension: Dimension::MASS,
+        prefixes: Prefixes::Si,
+    },
+    BaseUnit {
+        symbol: "lb",
+        factor: 0.45359237,
+        dimension: Dimension::MASS,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "s",
+        factor: 1.,
+        dimension: Dimension::TIME,
+        prefixes: Prefixes::Si,
+    },
+    BaseUnit {
+        symbol: "min",
+        factor: 60.,
+        dimension: Dimension::TIME,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "h",
+        factor: 3600.,
+        dimension: Dimension::TIME,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "day",
+        factor: 86400.,
+        dimension: Dimension::TIME,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "week",
+        factor: 604800.,
+        dimension: Dimension::TIME,
+        prefixes: Prefixes::None,
+    },
+    BaseUnit {
+        symbol: "B",
+        factor: 1.,
+        dimension: Dimension::DATA,
+        prefixes: Prefixes::SiAndBinary,
+    },
+    BaseUnit {
+        symbol: "bit",
+        factor: 0.125,
+        dimension: Dimension::DATA,
+        prefixes: Prefixes::SiAndBinary,
+    },
+];
+
+impl Unit {
+    /// Finds a unit by symbol, with an optional prefix (`km`, `ms`, `GiB`).
+    pub fn lookup(name: &str) -> Option<Unit> {
+        let unit = |factor: f32, base: &BaseUnit| Unit {
+            name: name.to_owned(),
+            factor: factor * base.factor,
+            dimension: base.dimension,
+        };
+
+        // Exact symbols are tried before prefixed ones.
+        if let Some(base) = REGISTRY.iter().find(|b| b.symbol == name) {
+            return Some(unit(1., base));
+        }
+        REGISTRY
+            .iter()
+            .filter(|b| b.prefixes != Prefixes::None)
+            .find_map(|base| {
+                let prefix = name.strip_suffix(base.symbol)?;
+                let binary: &[(&str, f32)] = match base.prefixes {
+                    Prefixes::SiAndBinary => &BINARY_PREFIXES,
+                    _ => &[],
+                };
+                SI_PREFIXES
+                    .iter()
+                    .chain(binary)
+                    .find(|(p, _)| *p == prefix)
+                    .map(|(_, factor)| unit(*factor, base))
+            })
+    }
+
+    /// Parses a unit expression such as `MiB/s`, `km/h` or `kg*m/s^2`, the target of `in` and `to`.
+    pub fn parse(text: &str) -> Option<Unit> {
+        let text = text.trim();
+        let mut factor = 1f32;
+        let mut dimension = Dimension::NONE;
+        let mut dividing = false;
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            let end = rest.find(['*', '/']).unwrap_or(rest.len());
+            let (term, remaining) = rest.split_at(end);
+            let (symbol, exp) = match term.split_once('^') {
+                Some((symbol, exp)) => (symbol.trim(), exp.trim().parse::<i8>().ok()?),
+                None => (term.trim(), 1),
+            };
+            let unit = Unit::lookup(symbol)?;
+            let term_factor = unit.factor.powi(exp as i32);
+            let term_dimension = unit.dimension.powf(exp as f32)?;
+            if dividing {
+                factor /= term_factor;
+                dimension = dimension / term_dimension;
+            } else {
+                factor *= term_factor;
+                dimension = dimension * term_dimension;
+            }
+
+            dividing = remaining.starts_with('/');
+            rest = remaining.get(1..).unwrap_or("");
+        }
+
+        Some(Unit {
+            name: text.to_owned(),
+            factor,
+            dimension,
+        })
+    }
+}
+
+/// A float carrying a physical dimension. The value is stored in base units (metres, kilograms, seconds, bytes).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f32,
+    pub dimension: Dimension,
+    /// Unit chosen with `in`/`to` for display.
+    pub display_unit: Option<Unit>,
+}
+
+impl Quantity {
+    pub fn new(value: f32, dimension: Dimension) -> Self {
+        Self {
+            value,
+            dimension,
+            display_unit: None,
+        }
+    }
+
+    /// Drops the dimension once it cancels out, so `6 m / 2 m` is a plain number.
+    pub fn into_number(self) -> Number {
+        if self.dimension.is_dimensionless() && self.display_unit.is_none() {
+            Number::Float(self.value)
+        } else {
+            Number::Quantity(self)
+        }
+    }
+
+    pub fn convert(mut self, unit: Unit) -> Self {
+        self.display_unit = Some(unit);
+        self
+    }
+}
+
+impl std::fmt::Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.display_unit {
+            Some(unit) => write!(f, "{} {}", Shown(self.value / unit.factor), unit.name),
+            None => write!(f, "{} {}", Shown(self.value), self.dimension),
+        }
+    }
+}
+
+/// Dimension and source position of one entry on the checker's stack.
+#[derive(Clone, Copy)]
+struct Operand {
+    dimension: Dimension,
+    /// Known when the operand is a plain literal, which is what exponents need.
+    constant: Option<f32>,
+    location: Option<Location>,
+}
+
+/// Walks an RPN expression checking that every operation is dimensionally sound, before anything is evaluated.
+pub fn check_dimensions(
+    src: &str,
+    rpn: &[Token],
+    variables: &SymbolTable<Vec<Token>>,
+) -> Result<Dimension, Box<DimensionError>> {
+    let mut known = HashMap::new();
+    check_rpn(src, rpn, variables, &mut known)
+}
+
+fn check_rpn(
+    src: &str,
+    rpn: &[Token],
+    variables: &SymbolTable<Vec<Token>>,
+    known: &mut HashMap<String, Dimension>,
+) -> Result<Dimension, Box<DimensionError>> {
+    let error = |message: String, labels: Vec<LabeledSpan>, help: Option<String>| {
+        Box::new(DimensionError {
+            src: NamedSource::new("input", src.to_owned()),
+            message,
+            labels,
+            help,
+        })
+    };
+    let label = |operand: &Operand, text: String| {
+        LabeledSpan::new_with_span(Some(text), span(operand.location))
+    };
+
+    let mut stack: Vec<Operand> = vec![];
+    for token in rpn {
+        let location = token.location;
+        match &token.token_type {
+            TokenType::Num(n) => stack.push(Operand {
+                dimension: match n {
+                    Number::Quantity(q) => q.dimension,
+                    _ => Dimension::NONE,
+                },
+                constant: match n {
+                    Number::Float(x) => Some(*x),
+                    _ => None,
+                },
+                location,
+            }),
+            TokenType::Id(id) => {
+                let dimension = if let Some(dimension) = known.get(id) {
+                    *dimension
+                } else if let Some(tokens) = variables.lookup(id) {
+                    let dimension = check_rpn(src, tokens, variables, known)?;
+                    known.insert(id.clone(), dimension);
+                    dimension
+                } else {
+                    Dimension::NONE
+                };
+                stack.push(Operand {
+                    dimension,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Negate => {
+                let x = stack.pop().expect("Ill-formed expression.");
+                stack.push(Operand {
+                    dimension: x.dimension,
+                    constant: x.constant.map(|c| -c),
+                    location: covering(location, x.location),
+                });
+            }
+            TokenType::Builtin(builtin) => {
+                let x = stack.pop().expect("Ill-formed expression.");
+                let dimension = match builtin {
+                    Builtin::Re | Builtin::Im | Builtin::Abs | Builtin::Conj => Some(x.dimension),
+                    Builtin::Sqrt => x.dimension.powf(0.5),
+                    Builtin::Arg | Builtin::Exp | Builtin::Ln => {
+                        x.dimension.is_dimensionless().then_some(Dimension::NONE)
+                    }
+                };
+                let Some(dimension) = dimension else {
+                    return Err(error(
+                        format!(
+                            "`{builtin}` cannot be applied to a value measured in {}",
+                            x.dimension
+                        ),
+                        vec![label(&x, x.dimension.describe())],
+                        None,
+                    ));
+                };
+                stack.push(Operand {
+                    dimension,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::BinOp(op) => {
+                let y = stack.pop().expect("Ill-formed expression.");
+                let x = stack.pop().expect("Ill-formed expression.");
+                let dimension = match op {
+                    BinOp::Plus | BinOp::Minus if x.dimension != y.dimension => {
+                        return Err(error(
+                            format!("Cannot apply `{op}` to {} and {}", x.dimension, y.dimension),
+                            vec![
+                                label(&x, x.dimension.describe()),
+                                label(&y, y.dimension.describe()),
+                            ],
+                            Some(
+                                "Both sides of `+` and `-` must measure the same thing.".to_owned(),
+                            ),
+                        ));
+                    }
+                    BinOp::Plus | BinOp::Minus => x.dimension,
+                    BinOp::Times | BinOp::MatMul => x.dimension * y.dimension,
+                    BinOp::Divide => x.dimension / y.dimension,
+                    BinOp::Power if !y.dimension.is_dimensionless() => {
+                        return Err(error(
+                            "Exponents must be dimensionless".to_owned(),
+                            vec![label(&y, y.dimension.describe())],
+                            None,
+                        ));
+                    }
+                    BinOp::Power if x.dimension.is_dimensionless() => Dimension::NONE,
+                    BinOp::Power => match y.constant.and_then(|exp| x.dimension.powf(exp)) {
+                        Some(dimension) => dimension,
+                        None => {
+                            return Err(error(
+                                format!("Cannot raise a value measured in {} to this power", x.dimension),
+                                vec![label(&x, x.dimension.describe()), label(&y, "exponent".to_owned())],
+                                Some("Quantities with units need a literal exponent that keeps whole-number units.".to_owned()),
+                            ));
+                        }
+                    },
+                    BinOp::Equal => unreachable!(),
+                };
+                stack.push(Operand {
+                    dimension,
+                    constant: None,
+                    location: covering(x.location, y.location),
+                });
+            }
+            TokenType::Loop(reduction) => {
+                // The index shadows any variable of the same name, but only inside the body.
+                let shadowed = known.insert(reduction.index.clone(), Dimension::NONE);
+                let parts = [
+                    (&reduction.from, reduction.from_location),
+                    (&reduction.to, reduction.to_location),
+                    (&reduction.body, start(location)),
+                ];
+                for (rpn, part_location) in parts {
+                    let dimension = check_rpn(src, rpn, variables, known)?;
+                    if !dimension.is_dimensionless() {
+                        return Err(error(
+                            format!("`{}` only works on plain numbers", reduction.reduction),
+                            vec![LabeledSpan::at_offset(
+                                part_location,
+                                dimension.describe(),
+                            )],
+                            Some("Sums and products are compiled to float loops, which cannot carry units.".to_owned()),
+                        ));
+                    }
+                }
+                match shadowed {
+                    Some(dimension) => known.insert(reduction.index.clone(), dimension),
+                    None => known.remove(&reduction.index),
+                };
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Integral(integral) => {
+                // The variable shadows any variable of the same name, but only inside the body.
+                let shadowed = known.insert(integral.variable.clone(), Dimension::NONE);
+                let body = check_rpn(src, &integral.body, variables, known);
+                match shadowed {
+                    Some(dimension) => known.insert(integral.variable.clone(), dimension),
+                    None => known.remove(&integral.variable),
+                };
+                let mut parts = vec![
+                    (body?, start(location)),
+                    (
+                        check_rpn(src, &integral.from, variables, known)?,
+                        integral.from_location,
+                    ),
+                    (
+                        check_rpn(src, &integral.to, variables, known)?,
+                        integral.to_location,
+                    ),
+                ];
+                if let Some(tolerance) = &integral.tolerance {
+                    parts.push((
+                        check_rpn(src, tolerance, variables, known)?,
+                        integral.tolerance_location,
+                    ));
+                }
+                if let Some((dimension, part_location)) = parts
+                    .into_iter()
+                    .find(|(dimension, _)| !dimension.is_dimensionless())
+                {
+                    return Err(error(
+                        "`integrate` only works on plain numbers".to_owned(),
+                        vec![LabeledSpan::at_offset(part_location, dimension.describe())],
+                        Some(
+                            "Integrands are compiled to float code, which cannot carry units."
+                                .to_owned(),
+                        ),
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Simplified(node) => {
+                stack.push(Operand {
+                    dimension: check_rpn(src, &node.to_rpn(location), variables, known)?,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Equation(equation) => {
+                // The variable shadows any variable of the same name, but only inside the body.
+                let shadowed = known.insert(equation.variable.clone(), Dimension::NONE);
+                let body = check_rpn(src, &equation.body, variables, known);
+                match shadowed {
+                    Some(dimension) => known.insert(equation.variable.clone(), dimension),
+                    None => known.remove(&equation.variable),
+                };
+                let mut parts = vec![(body?, start(location))];
+                if let Some(guess) = &equation.guess {
+                    parts.push((
+                        check_rpn(src, guess, variables, known)?,
+                        equation.guess_location,
+                    ));
+                }
+                if let Some((dimension, part_location)) = parts
+                    .into_iter()
+                    .find(|(dimension, _)| !dimension.is_dimensionless())
+                {
+                    return Err(error(
+                        "`solve` only works on plain numbers".to_owned(),
+                        vec![LabeledSpan::at_offset(part_location, dimension.describe())],
+                        Some(
+                            "Equations are compiled to float code, which cannot carry units."
+                                .to_owned(),
+                        ),
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Call(name, arity) | TokenType::Grad(name, arity) => {
+                let args = stack.split_off(stack.len() - arity);
+                if let Some(arg) = args.iter().find(|arg| !arg.dimension.is_dimensionless()) {
+                    return Err(error(
+                        format!("`{name}` only takes plain numbers"),
+                        vec![label(arg, arg.dimension.describe())],
+                        Some(
+                            "Functions are compiled to float code, which cannot carry units."
+                                .to_owned(),
+                        ),
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Array(len) => {
+                let entries = stack.split_off(stack.len() - len);
+                if let Some(entry) = entries
+                    .iter()
+                    .find(|entry| !entry.dimension.is_dimensionless())
+                {
+                    return Err(error(
+                        "Vectors and matrices only hold plain numbers".to_owned(),
+                        vec![label(entry, entry.dimension.describe())],
+                        None,
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Statistic(statistic, arity) => {
+                let args = stack.split_off(stack.len() - arity);
+                if let Some(arg) = args.iter().find(|arg| !arg.dimension.is_dimensionless()) {
+                    return Err(error(
+                        format!("`{statistic}` only takes plain numbers"),
+                        vec![label(arg, arg.dimension.describe())],
+                        None,
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::LinAlg(linalg) => {
+                let args = stack.split_off(stack.len() - linalg.arity());
+                if let Some(arg) = args.iter().find(|arg| !arg.dimension.is_dimensionless()) {
+                    return Err(error(
+                        format!("`{linalg}` only takes plain numbers"),
+                        vec![label(arg, arg.dimension.describe())],
+                        None,
+                    ));
+                }
+                stack.push(Operand {
+                    dimension: Dimension::NONE,
+                    constant: None,
+                    location,
+                });
+            }
+            TokenType::Convert(unit) => {
+                let x = stack.pop().expect("Ill-formed expression.");
+                if x.dimension != unit.dimension {
+                    return Err(error(
+                        format!("Cannot convert {} to `{}`", x.dimension, unit.name),
+                        vec![
+                            label(&x, x.dimension.describe()),
+                            LabeledSpan::new_with_span(
+                                Some(unit.dimension.describe()),
+                                span(location),
+                            ),
+                        ],
+                        None,
+                    ));
+                }
+                stack.push(x);
+            }
+            TokenType::Reduction(_)
+            | TokenType::Integrate
+            | TokenType::Solve
+            | TokenType::Simplify
+            | TokenType::Derivative(_)
+            | TokenType::Comma
+            | TokenType::LeftBracket
+            | TokenType::RightBracket
+            | TokenType::LeftParen
+            | TokenType::RightParen => (),
+        }
+    }
+
+    Ok(stack.pop().map_or(Dimension::NONE, |x| x.dimension))
+}
@@ -1,26 +1,53 @@
-use std::error::Error;
-
 use nom::{
-    AsChar, Input, Parser,
-    branch::{Choice, alt},
-    bytes::complete::{is_a, is_not, tag},
-    character::{
-        char,
-        complete::{alpha0, alphanumeric1, one_of, space0},
-        multispace0,
-        streaming::{alpha1, alphanumeric0},
-    },
-    combinator::{map, opt, recognize},
-    multi::{many0, many1},
-    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    Parser,
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while_m_n, take_while1},
+    character::{char, complete::space0},
+    combinator::recognize,
+    sequence::{delimited, pair},
 };
 
-use crate::{
-    IResult, Span,
-    error::{self, ParseError},
-    lexer::{BinOp, Expr, Statement},
-};
+use crate::{IResult, Location, Span, error::ParseError};
 
 pub fn ws_tag(input: &str) -> impl Parser<Span<'_>, Output = Span<'_>, Error = ParseError<'_>> {
     delimited(space0, tag(input), space0)
 }
+
+/// Words that cannot be used as names: `in`, `to`, `print` and `display` already have a meaning, and
+/// the rest are kept for syntax to come.
+pub const KEYWORDS: &[&str] = &[
+    "in", "to", "print", "display", "if", "then", "else", "for", "while", "let", "fn", "return",
+    "and", "or", "not",
+];
+
+/// A word following the Unicode identifier rules, such as `x1`, `rate_2024`, `Δt` or `π`, so Greek
+/// constants lex the same way as `pi`. It may also start with an underscore, which covers references
+/// to earlier results such as `_2`. Keywords are words too.
+pub fn word(input: Span) -> IResult<Span> {
+    recognize(pair(
+        alt((
+            take_while_m_n(1, 1, unicode_ident::is_xid_start),
+            recognize(pair(char('_'), take_while1(unicode_ident::is_xid_continue))),
+        )),
+        take_while(unicode_ident::is_xid_continue),
+    ))
+    .parse(input)
+}
+
+/// A word that is not a keyword, which fails outright on a keyword. A subscript is written with an underscore, as in `x_1`.
+pub fn identifier(input: Span) -> IResult<Span> {
+    let (rest, name) = word(input)?;
+    if KEYWORDS.contains(name.fragment()) {
+        return Err(nom::Err::Failure(ParseError::at(
+            Location::of(&name),
+            format!("`{name}` is a reserved keyword and cannot be used as a name"),
+            "reserved keyword",
+        )));
+    }
+    Ok((rest, name))
+}
+
+/// Whether the whole of `name` is an identifier, as for the columns of a table.
+pub fn is_identifier(name: &str) -> bool {
+    matches!(identifier(Span::new(name)), Ok((rest, _)) if rest.is_empty())
+}